@@ -0,0 +1,79 @@
+//! Baseline timings for reading and evaluating, run with `cargo bench`.
+//! A name given on the command line runs only the benchmarks containing it.
+//!
+//! This is a plain timing loop rather than Criterion, because Criterion
+//! can't be fetched where this is built. It only prints a mean per run:
+//! there are no outlier checks, confidence intervals or saved baselines,
+//! so its numbers are not statistically comparable between runs. Use them
+//! only to spot large changes.
+extern crate lust;
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use lust::lisp::read::{eval, eval_all, read_all, Environment, FuncId, Value};
+
+/// Runs `f` for about a second after warming up, and prints the mean time
+/// per run.
+fn bench<T, F: FnMut() -> T>(name: &str, mut f: F) {
+    if let Some(filter) = env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        if !name.contains(&filter) {
+            return;
+        }
+    }
+    let warm_up = Instant::now();
+    let mut runs: u32 = 0;
+    while warm_up.elapsed() < Duration::from_millis(200) {
+        black_box(f());
+        runs += 1;
+    }
+    let runs = runs * 5;
+    let start = Instant::now();
+    for _ in 0..runs {
+        black_box(f());
+    }
+    println!("{:<24} {:>12?} per run ({} runs)", name, start.elapsed() / runs, runs);
+}
+
+/// An expression nesting `depth` calls, each with a few literal arguments.
+fn nested(depth: usize) -> String {
+    let mut src = String::new();
+    for i in 0..depth {
+        src.push_str(&format!("(+ {} \"s{}\" #t ", i, i));
+    }
+    src.push('0');
+    for _ in 0..depth {
+        src.push(')');
+    }
+    src
+}
+
+fn main() {
+    let src = nested(300);
+    bench("parse nested", || read_all(&src).unwrap());
+
+    let mut env = Environment::with_builtins();
+    eval_all("(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))", &mut env).unwrap();
+    let fib = read_all("(fib 15)").unwrap().remove(0);
+    bench("eval fib 15", || eval(&fib, &mut env).unwrap());
+
+    let terms: Vec<Value> = (0..1000).map(Value::Int).collect();
+    let mut env = Environment::with_builtins();
+    bench("add 1000 ints", || FuncId::Add.call(terms.clone(), &mut env).unwrap());
+
+    // A name bound at the top, looked up from inside a dozen nested scopes
+    // full of other names.
+    let mut env = Environment::with_builtins();
+    let mut src = String::from("(define target 1)");
+    for i in 0..1000 {
+        src.push_str(&format!(" (define name-{} {})", i, i));
+    }
+    eval_all(&src, &mut env).unwrap();
+    let mut lookup = String::from("target");
+    for i in 0..12 {
+        lookup = format!("(let ((a{} 1) (b{} 2) (c{} 3)) {})", i, i, i, lookup);
+    }
+    let lookup = read_all(&lookup).unwrap().remove(0);
+    bench("lookup through scopes", || eval(&lookup, &mut env).unwrap());
+    bench("lookup top level", || env.get("name-500").unwrap());
+}
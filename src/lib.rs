@@ -1 +1,7 @@
+#[cfg(feature = "bignum")]
+extern crate num_bigint;
+
 pub mod lisp;
+
+pub use lisp::error::LustError;
+pub use lisp::read::{eval_all, eval_program, eval_str};
@@ -0,0 +1,1908 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+use lisp::expr::{EvalError, Environment, Function, Native, apply};
+use lisp::lexer::{Token, parse_number};
+use lisp::read;
+use lisp::read::ReadError;
+use lisp::value::{Key, Value};
+
+macro_rules! builtins {
+    ($($id:ident $name:expr => $func:ident),* $(,)*) => {
+        /// A function implemented in Rust, called with evaluated arguments.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum FuncId {
+            $($id),*
+        }
+
+        impl FuncId {
+            pub const ALL: &'static [FuncId] = &[$(FuncId::$id),*];
+
+            /// The name the function is bound to in a new environment.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(FuncId::$id => $name),*
+                }
+            }
+
+            pub fn call(self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+                self.call_indexed(args, env).map_err(EvalError::unindexed)
+            }
+
+            /// Calls the function, leaving an error about one of the
+            /// arguments as `EvalError::Arg` with its index.
+            pub fn call_indexed(self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+                match self {
+                    $(FuncId::$id => $func(args, env)),*
+                }
+            }
+        }
+    }
+}
+
+builtins! {
+    Add "+" => add,
+    Sub "-" => sub,
+    Mul "*" => mul,
+    Abs "abs" => abs,
+    Min "min" => min,
+    Max "max" => max,
+    Gcd "gcd" => gcd,
+    Expt "expt" => expt,
+    Modulo "modulo" => modulo,
+    Remainder "remainder" => remainder,
+    Sqrt "sqrt" => sqrt,
+    Floor "floor" => floor,
+    Ceil "ceil" => ceil,
+    Round "round" => round,
+    Truncate "truncate" => truncate,
+    Sin "sin" => sin,
+    Cos "cos" => cos,
+    Tan "tan" => tan,
+    Atan "atan" => atan,
+    Random "random" => random,
+    Seed "seed!" => seed,
+    BitAnd "bit-and" => bit_and,
+    BitOr "bit-or" => bit_or,
+    BitXor "bit-xor" => bit_xor,
+    BitNot "bit-not" => bit_not,
+    ShiftLeft "shift-left" => shift_left,
+    ShiftRight "shift-right" => shift_right,
+    Eq "=" => num_eq,
+    Ne "!=" => num_ne,
+    Lt "<" => lt,
+    Gt ">" => gt,
+    Le "<=" => le,
+    Ge ">=" => ge,
+    IsNumber "number?" => is_number,
+    IsString "string?" => is_string,
+    IsBool "bool?" => is_bool,
+    IsList "list?" => is_list,
+    IsSymbol "symbol?" => is_symbol,
+    IsEq "eq?" => is_eq,
+    IsEqual "equal?" => is_equal,
+    Not "not" => not,
+    Map "map" => map,
+    ForEach "for-each" => for_each,
+    Filter "filter" => filter,
+    Reduce "reduce" => reduce,
+    Memoize "memoize" => memoize,
+    Partial "partial" => partial,
+    Force "force" => force,
+    Macro "macro" => make_macro,
+    Gensym "gensym" => gensym,
+    DefineConstant "define-constant" => define_constant,
+    Unset "unset!" => unset,
+    Cons "cons" => cons,
+    ConsStar "cons*" => cons_star,
+    List "list" => list,
+    Car "car" => car,
+    Cdr "cdr" => cdr,
+    Length "length" => length,
+    Reverse "reverse" => reverse,
+    Append "append" => append,
+    Nth "nth" => nth,
+    Sort "sort" => sort,
+    Range "range" => range,
+    MakeMap "make-map" => make_map,
+    MapSet "map-set" => map_set,
+    MapGet "map-get" => map_get,
+    Assoc "assoc" => assoc,
+    AlistToMap "alist->map" => alist_to_map,
+    Vector "vector" => vector,
+    VectorRef "vector-ref" => vector_ref,
+    VectorSet "vector-set!" => vector_set,
+    VectorLength "vector-length" => vector_length,
+    Print "print" => print,
+    Display "display" => display,
+    Write "write" => write,
+    ReadLine "read-line" => read_line,
+    Format "format" => format,
+    StringAppend "string-append" => string_append,
+    StringLength "string-length" => string_length,
+    Substring "substring" => substring,
+    StringToNumber "string->number" => string_to_number,
+    Error "error" => error,
+    Assert "assert" => assert,
+    AssertEqual "assert-equal" => assert_equal,
+    Load "load" => load,
+}
+
+impl FuncId {
+    /// Whether calling the function can't change anything or depend on
+    /// anything that changes, and it never calls a function it's given.
+    pub fn is_pure(self) -> bool {
+        !matches!(self, FuncId::Random | FuncId::Seed | FuncId::Map | FuncId::ForEach | FuncId::Filter
+                  | FuncId::Reduce | FuncId::Force | FuncId::Gensym | FuncId::DefineConstant | FuncId::Unset
+                  | FuncId::Sort
+                  | FuncId::Print | FuncId::Display | FuncId::Write | FuncId::ReadLine | FuncId::Load)
+    }
+}
+
+
+fn arity(name: &str, args: &[Value], n: usize) -> Result<(), EvalError> {
+    if args.len() != n {
+        let plural = if n == 1 { "" } else { "s" };
+        return Err(EvalError::Arity(format!("{} takes {} argument{}, got {}", name, n, plural, args.len())));
+    }
+    Ok(())
+}
+
+fn one_arg(name: &str, mut args: Vec<Value>) -> Result<Value, EvalError> {
+    arity(name, &args, 1)?;
+    Ok(args.pop().expect("Argument disappeared"))
+}
+
+fn two_args(name: &str, mut args: Vec<Value>) -> Result<(Value, Value), EvalError> {
+    arity(name, &args, 2)?;
+    let second = args.pop().expect("Argument disappeared");
+    let first = args.pop().expect("Argument disappeared");
+    Ok((first, second))
+}
+
+/// `result`, with an error in it being about the argument at `index`.
+fn about<T>(index: usize, result: Result<T, EvalError>) -> Result<T, EvalError> {
+    result.map_err(|err| err.about(index))
+}
+
+fn ints(args: &[Value]) -> Result<Vec<i64>, EvalError> {
+    args.iter().enumerate().map(|(i, arg)| about(i, arg.as_int())).collect()
+}
+
+/// The arguments as floats if any of them is one, since then the result
+/// is one too.
+fn floats(args: &[Value]) -> Result<Option<Vec<f64>>, EvalError> {
+    if !args.iter().any(Value::is_float) {
+        return Ok(None);
+    }
+    args.iter().enumerate().map(|(i, arg)| about(i, arg.as_float())).collect::<Result<_, _>>().map(Some)
+}
+
+
+fn add(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if let Some(floats) = floats(&args)? {
+        return Ok(Value::Float(floats.iter().sum()));
+    }
+    let sum = ints(&args).ok().and_then(|ints| ints.iter().try_fold(0i64, |acc, &i| acc.checked_add(i)));
+    sum.map_or_else(|| promote("+", &args), |sum| Ok(Value::Int(sum)))
+}
+
+fn sub(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::Arity(String::from("- takes at least 1 argument")));
+    }
+    if let Some(floats) = floats(&args)? {
+        return Ok(Value::Float(match floats.split_first() {
+            Some((first, [])) => -first,
+            Some((first, rest)) => rest.iter().fold(*first, |acc, x| acc - x),
+            None => unreachable!()
+        }));
+    }
+    let diff = ints(&args).ok().and_then(|ints| match ints.split_first() {
+        Some((first, [])) => first.checked_neg(),
+        Some((first, rest)) => rest.iter().try_fold(*first, |acc, &i| acc.checked_sub(i)),
+        None => None
+    });
+    diff.map_or_else(|| promote("-", &args), |diff| Ok(Value::Int(diff)))
+}
+
+fn mul(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if let Some(floats) = floats(&args)? {
+        return Ok(Value::Float(floats.iter().product()));
+    }
+    let product = ints(&args).ok().and_then(|ints| ints.iter().try_fold(1i64, |acc, &i| acc.checked_mul(i)));
+    product.map_or_else(|| promote("*", &args), |product| Ok(Value::Int(product)))
+}
+
+/// Redoes the arithmetic of `name` when it didn't work out with `i64`s,
+/// whether because an argument isn't one or because the result overflowed.
+#[cfg(not(feature = "bignum"))]
+fn promote(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+    ints(args)?;
+    Err(overflow(name, args))
+}
+
+fn overflow(name: &str, args: &[Value]) -> EvalError {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    EvalError::Overflow(format!("({} {})", name, args.join(" ")))
+}
+
+/// Redoes the arithmetic of `name` with big integers, when it didn't work
+/// out with `i64`s.
+#[cfg(feature = "bignum")]
+fn promote(name: &str, args: &[Value]) -> Result<Value, EvalError> {
+    let nums = bigs(args)?;
+    let result = match (name, nums.split_first()) {
+        ("+", _) => nums.iter().sum(),
+        ("*", _) => nums.iter().product(),
+        ("-", Some((first, []))) => -first,
+        ("-", Some((first, rest))) => rest.iter().fold(first.clone(), |acc, n| acc - n),
+        _ => unreachable!("No big integer version of {}", name)
+    };
+    Ok(Value::from_big(result))
+}
+
+#[cfg(feature = "bignum")]
+fn bigs(args: &[Value]) -> Result<Vec<BigInt>, EvalError> {
+    args.iter().enumerate().map(|(i, arg)| about(i, arg.as_big())).collect()
+}
+
+#[cfg(not(feature = "bignum"))]
+fn abs(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("abs", args)?;
+    if let Value::Float(x) = val {
+        return Ok(Value::Float(x.abs()));
+    }
+    about(0, val.as_int())?.checked_abs().map(Value::Int).ok_or_else(|| overflow("abs", &[val]))
+}
+
+#[cfg(feature = "bignum")]
+fn abs(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("abs", args)?;
+    if let Value::Float(x) = val {
+        return Ok(Value::Float(x.abs()));
+    }
+    match about(0, val.as_int())?.checked_abs() {
+        Some(i) => Ok(Value::Int(i)),
+        None => Ok(Value::from_big(-about(0, val.as_big())?))
+    }
+}
+
+/// The argument that compares as `pick` against all the others, such as
+/// the least for `Ordering::Less`.
+fn extreme(name: &str, args: Vec<Value>, pick: Ordering) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::Arity(format!("{} takes at least 1 argument", name)));
+    }
+    orderings(&args)?;
+    let mut args = args.into_iter();
+    let mut best = args.next().expect("Argument disappeared");
+    for arg in args {
+        if num_cmp(&arg, &best)? == pick {
+            best = arg;
+        }
+    }
+    Ok(best)
+}
+
+fn min(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    extreme("min", args, Ordering::Less)
+}
+
+fn max(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    extreme("max", args, Ordering::Greater)
+}
+
+fn gcd(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::Arity(String::from("gcd takes at least 1 argument")));
+    }
+    let mut result = 0u64;
+    for i in ints(&args)? {
+        let mut a = result;
+        let mut b = i.unsigned_abs();
+        while b != 0 {
+            let rem = a % b;
+            a = b;
+            b = rem;
+        }
+        result = a;
+    }
+    i64::try_from(result).map(Value::Int).map_err(|_| overflow("gcd", &args))
+}
+
+/// Raises the first argument to the power of the second, as a float if
+/// either is one. With only integers to give, a negative exponent is a
+/// type mismatch.
+fn expt(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (base, exp) = two_args("expt", args)?;
+    if base.is_float() || exp.is_float() {
+        return Ok(Value::Float(about(0, base.as_float())?.powf(about(1, exp.as_float())?)));
+    }
+    let (b, e) = (about(0, base.as_int())?, about(1, exp.as_int())?);
+    if e < 0 {
+        return Err(EvalError::TypeMismatch(format!("expt needs a non-negative exponent, got {}", e)).about(1));
+    }
+    let result = match (b, u32::try_from(e)) {
+        (_, Ok(e)) => b.checked_pow(e),
+        (0, Err(_)) | (1, Err(_)) => Some(b),
+        (-1, Err(_)) => Some(if e % 2 == 0 { 1 } else { -1 }),
+        _ => None
+    };
+    result.map_or_else(|| promote_expt(base, exp), |result| Ok(Value::Int(result)))
+}
+
+#[cfg(not(feature = "bignum"))]
+fn promote_expt(base: Value, exp: Value) -> Result<Value, EvalError> {
+    Err(overflow("expt", &[base, exp]))
+}
+
+#[cfg(feature = "bignum")]
+fn promote_expt(base: Value, exp: Value) -> Result<Value, EvalError> {
+    match u32::try_from(exp.as_int()?) {
+        Ok(e) => Ok(Value::from_big(base.as_big()?.pow(e))),
+        Err(_) => Err(overflow("expt", &[base, exp]))
+    }
+}
+
+/// The two integer arguments, failing if the second, which divides the
+/// first, is zero.
+fn division(name: &str, args: Vec<Value>) -> Result<(i64, i64), EvalError> {
+    let (a, b) = two_args(name, args)?;
+    match (about(0, a.as_int())?, about(1, b.as_int())?) {
+        (_, 0) => Err(EvalError::Invalid(format!("({} {} 0) divides by zero", name, a)).about(1)),
+        ints => Ok(ints)
+    }
+}
+
+/// What is left over after dividing the first argument by the second and
+/// rounding the quotient down, which has the sign of the divisor:
+/// `(modulo -7 3)` is 2 and `(modulo 7 -3)` is -2.
+fn modulo(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (a, b) = division("modulo", args)?;
+    // Wrapping only matters for i64::MIN and -1, where the answer is 0.
+    let rem = a.wrapping_rem(b);
+    Ok(Value::Int(if rem != 0 && (rem < 0) != (b < 0) { rem + b } else { rem }))
+}
+
+/// What is left over after dividing the first argument by the second and
+/// rounding the quotient towards zero, which has the sign of the dividend,
+/// like Rust's `%`: `(remainder -7 3)` is -1 and `(remainder 7 -3)` is 1.
+fn remainder(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (a, b) = division("remainder", args)?;
+    Ok(Value::Int(a.wrapping_rem(b)))
+}
+
+fn sqrt(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("sqrt", args)?;
+    let x = about(0, val.as_float())?;
+    if x < 0.0 {
+        return Err(EvalError::Invalid(format!("(sqrt {}) has no real root", val)).about(0));
+    }
+    Ok(Value::Float(x.sqrt()))
+}
+
+/// Rounds a float to an integer with `op`, leaving integers as they are.
+/// The result is always an integer, so `(floor 3.7)` is 3 rather than 3.0.
+fn rounding(name: &str, args: Vec<Value>, op: fn(f64) -> f64) -> Result<Value, EvalError> {
+    match one_arg(name, args)? {
+        Value::Float(x) if !x.is_finite() => Err(EvalError::Invalid(format!("({} {:?}) isn't an integer", name, x)).about(0)),
+        Value::Float(x) => float_to_int(name, op(x)),
+        val if val.is_number() => Ok(val),
+        other => Err(EvalError::TypeMismatch(format!("expected number, got {}", other)).about(0))
+    }
+}
+
+#[cfg(not(feature = "bignum"))]
+fn float_to_int(name: &str, x: f64) -> Result<Value, EvalError> {
+    // 2^63 is the first float too big for an `i64`, and -2^63 just fits.
+    if (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&x) {
+        Ok(Value::Int(x as i64))
+    } else {
+        Err(overflow(name, &[Value::Float(x)]))
+    }
+}
+
+#[cfg(feature = "bignum")]
+fn float_to_int(_name: &str, x: f64) -> Result<Value, EvalError> {
+    Ok(Value::from_big(format!("{:.0}", x).parse().expect("Whole floats are valid big integers")))
+}
+
+fn floor(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    rounding("floor", args, f64::floor)
+}
+
+fn ceil(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    rounding("ceil", args, f64::ceil)
+}
+
+/// Rounds to the nearest integer, and halfway cases to the even one, so
+/// `(round 2.5)` is 2 and `(round 3.5)` is 4.
+fn round(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    rounding("round", args, f64::round_ties_even)
+}
+
+fn truncate(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    rounding("truncate", args, f64::trunc)
+}
+
+/// Applies `op` to the single argument as a float.
+fn float_fn(name: &str, args: Vec<Value>, op: fn(f64) -> f64) -> Result<Value, EvalError> {
+    Ok(Value::Float(op(about(0, one_arg(name, args)?.as_float())?)))
+}
+
+fn sin(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    float_fn("sin", args, f64::sin)
+}
+
+fn cos(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    float_fn("cos", args, f64::cos)
+}
+
+fn tan(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    float_fn("tan", args, f64::tan)
+}
+
+/// The arctangent of the argument, or with two, `(atan y x)`, the angle of
+/// the point (x, y), which unlike `(atan (/ y x))` is right in every
+/// quadrant.
+fn atan(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.len() == 2 {
+        let (y, x) = two_args("atan", args)?;
+        return Ok(Value::Float(about(0, y.as_float())?.atan2(about(1, x.as_float())?)));
+    }
+    float_fn("atan", args, f64::atan)
+}
+
+/// A random integer from 0 up to but not including the argument.
+fn random(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("random", args)?;
+    match u64::try_from(about(0, val.as_int())?) {
+        Ok(n) if n > 0 => Ok(Value::Int(env.random_below(n) as i64)),
+        _ => Err(EvalError::Invalid(format!("(random {}) has nothing to pick from", val)).about(0))
+    }
+}
+
+/// Makes the numbers `random` returns from here on the same every time
+/// for the same seed.
+fn seed(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    env.seed_random(about(0, one_arg("seed!", args)?.as_int())? as u64);
+    Ok(Value::Unit)
+}
+
+fn bit_and(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Int(ints(&args)?.into_iter().fold(-1, |acc, i| acc & i)))
+}
+
+fn bit_or(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Int(ints(&args)?.into_iter().fold(0, |acc, i| acc | i)))
+}
+
+fn bit_xor(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Int(ints(&args)?.into_iter().fold(0, |acc, i| acc ^ i)))
+}
+
+fn bit_not(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Int(!about(0, one_arg("bit-not", args)?.as_int())?))
+}
+
+/// Shifts the bits of the first argument by the second, which must be
+/// from 0 to 63. Bits shifted out are lost; shifting right keeps the sign.
+fn shift(name: &str, args: Vec<Value>, op: fn(i64, u32) -> Option<i64>) -> Result<Value, EvalError> {
+    let (n, amount) = two_args(name, args)?;
+    let i = about(0, n.as_int())?;
+    let result = u32::try_from(about(1, amount.as_int())?).ok().and_then(|amount| op(i, amount));
+    result.map(Value::Int).ok_or_else(|| overflow(name, &[n, amount]))
+}
+
+fn shift_left(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    shift("shift-left", args, i64::checked_shl)
+}
+
+fn shift_right(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    shift("shift-right", args, i64::checked_shr)
+}
+
+/// Checks that `test` holds for every adjacent pair of arguments, so that
+/// `(< 1 2 3)` means `1 < 2` and `2 < 3`.
+fn compare(name: &str, args: &[Value], test: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::Arity(format!("{} takes at least 1 argument", name)));
+    }
+    Ok(Value::Bool(orderings(args)?.into_iter().all(test)))
+}
+
+/// How each adjacent pair of arguments compares, with a non-number among
+/// them being the error about its index.
+fn orderings(args: &[Value]) -> Result<Vec<Ordering>, EvalError> {
+    if let Some((i, arg)) = args.iter().enumerate().find(|(_, arg)| !arg.is_number()) {
+        return Err(EvalError::TypeMismatch(format!("expected number, got {}", arg)).about(i));
+    }
+    args.windows(2).map(|pair| num_cmp(&pair[0], &pair[1])).collect()
+}
+
+fn num_cmp(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    if a.is_float() || b.is_float() {
+        return a.as_float()?.partial_cmp(&b.as_float()?)
+            .ok_or_else(|| EvalError::Invalid(format!("NaN can't be compared: {} and {}", a, b)));
+    }
+    int_cmp(a, b)
+}
+
+#[cfg(not(feature = "bignum"))]
+fn int_cmp(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    Ok(a.as_int()?.cmp(&b.as_int()?))
+}
+
+#[cfg(feature = "bignum")]
+fn int_cmp(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    Ok(a.as_big()?.cmp(&b.as_big()?))
+}
+
+fn num_eq(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare("=", &args, |o| o == Ordering::Equal)
+}
+
+/// Whether each number differs from the one after it.
+fn num_ne(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare("!=", &args, |o| o != Ordering::Equal)
+}
+
+fn lt(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare("<", &args, |o| o == Ordering::Less)
+}
+
+fn gt(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare(">", &args, |o| o == Ordering::Greater)
+}
+
+fn le(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare("<=", &args, |o| o != Ordering::Greater)
+}
+
+fn ge(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    compare(">=", &args, |o| o != Ordering::Less)
+}
+
+fn is_number(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(one_arg("number?", args)?.is_number()))
+}
+
+fn is_string(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(one_arg("string?", args)?, Value::Str(_))))
+}
+
+fn is_bool(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(one_arg("bool?", args)?, Value::Bool(_))))
+}
+
+fn is_list(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(one_arg("list?", args)?.is_list()))
+}
+
+fn is_symbol(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(one_arg("symbol?", args)?, Value::Symbol(_))))
+}
+
+fn is_eq(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (a, b) = two_args("eq?", args)?;
+    Ok(Value::Bool(a.is_eq(&b)))
+}
+
+fn is_equal(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (a, b) = two_args("equal?", args)?;
+    Ok(Value::Bool(a.equal(&b)))
+}
+
+fn not(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Bool(!one_arg("not", args)?.is_truthy()))
+}
+
+fn map(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let (func, list) = two_args("map", args)?;
+    let mapped = about(1, list.to_vec())?.into_iter()
+        .map(|item| apply(&func, vec![item], env))
+        .collect::<Result<Vec<Value>, EvalError>>()?;
+    Ok(Value::list(mapped))
+}
+
+/// Calls the function on each element of the list in turn, for its side
+/// effects.
+fn for_each(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let (func, list) = two_args("for-each", args)?;
+    for item in about(1, list.to_vec())? {
+        apply(&func, vec![item], env)?;
+    }
+    Ok(Value::Unit)
+}
+
+fn filter(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let (pred, list) = two_args("filter", args)?;
+    let mut kept = vec![];
+    for item in about(1, list.to_vec())? {
+        if apply(&pred, vec![item.clone()], env)?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(Value::list(kept))
+}
+
+fn reduce(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    arity("reduce", &args, 3)?;
+    let mut args = args.into_iter();
+    let func = args.next().expect("Argument disappeared");
+    let init = args.next().expect("Argument disappeared");
+    let list = args.next().expect("Argument disappeared");
+    about(2, list.to_vec())?.into_iter()
+        .try_fold(init, |acc, item| apply(&func, vec![acc, item], env))
+}
+
+/// A function that remembers what it returned for each list of arguments,
+/// which is only right if the function it wraps is pure. Calls with
+/// arguments that can't be map keys aren't remembered.
+struct Memoized {
+    func: Value,
+    cache: Mutex<HashMap<Vec<Key>, Value>>
+}
+
+impl Memoized {
+    fn cache(&self) -> MutexGuard<'_, HashMap<Vec<Key>, Value>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Function for Memoized {
+    fn call(&self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+        let key = match args.iter().map(Key::from_value).collect::<Result<Vec<Key>, EvalError>>() {
+            Ok(key) => key,
+            Err(_) => return apply(&self.func, args, env)
+        };
+        if let Some(val) = self.cache().get(&key) {
+            return Ok(val.clone());
+        }
+        // Not holding the lock while calling, since the function may well
+        // call itself.
+        let val = apply(&self.func, args, env)?;
+        self.cache().insert(key, val.clone());
+        Ok(val)
+    }
+}
+
+fn memoize(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let func = one_arg("memoize", args)?;
+    if !matches!(func, Value::Builtin(_) | Value::Closure(_) | Value::Native(_)) {
+        return Err(EvalError::TypeMismatch(format!("expected function, got {}", func)).about(0));
+    }
+    let memoized = Memoized {func, cache: Mutex::new(HashMap::new())};
+    Ok(Value::Native(Arc::new(Native::new("memoized", memoized))))
+}
+
+/// A function called with some of its arguments already given, which go
+/// before the ones it's called with.
+struct Partial {
+    func: Value,
+    args: Vec<Value>
+}
+
+impl Function for Partial {
+    fn call(&self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+        apply(&self.func, self.args.iter().cloned().chain(args).collect(), env)
+    }
+}
+
+fn partial(mut args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(EvalError::Arity(String::from("partial takes at least 1 argument, got 0")));
+    }
+    let func = args.remove(0);
+    if !matches!(func, Value::Builtin(_) | Value::Closure(_) | Value::Native(_)) {
+        return Err(EvalError::TypeMismatch(format!("expected function, got {}", func)).about(0));
+    }
+    Ok(Value::Native(Arc::new(Native::new("partial", Partial {func, args}))))
+}
+
+/// The value of a promise, or any other value as it is.
+fn force(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("force", args)? {
+        Value::Promise(promise) => promise.force(env),
+        other => Ok(other)
+    }
+}
+
+fn make_macro(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("macro", args)? {
+        func @ Value::Builtin(_) | func @ Value::Closure(_) | func @ Value::Native(_) => Ok(Value::Macro(Arc::new(func))),
+        other => Err(EvalError::TypeMismatch(format!("expected function, got {}", other)).about(0))
+    }
+}
+
+fn gensym(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    arity("gensym", &args, 0)?;
+    Ok(Value::Symbol(env.gensym()))
+}
+
+fn define_constant(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match two_args("define-constant", args)? {
+        (Value::Symbol(name), val) => {
+            env.define_constant(&name, val)?;
+            Ok(Value::Unit)
+        },
+        (other, _) => Err(EvalError::TypeMismatch(format!("expected symbol, got {}", other)).about(0))
+    }
+}
+
+fn unset(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("unset!", args)? {
+        Value::Symbol(name) => Ok(Value::Bool(env.unset(&name))),
+        other => Err(EvalError::TypeMismatch(format!("expected symbol, got {}", other)).about(0))
+    }
+}
+
+fn cons(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (car, cdr) = two_args("cons", args)?;
+    Ok(Value::cons(car, cdr))
+}
+
+/// The arguments before the last consed onto it, so that with a list last
+/// it's a list of them all.
+fn cons_star(mut args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let mut tail = args.pop().ok_or_else(|| EvalError::Arity(String::from("cons* takes at least 1 argument, got 0")))?;
+    while let Some(val) = args.pop() {
+        tail = Value::cons(val, tail);
+    }
+    Ok(tail)
+}
+
+fn list(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::list(args))
+}
+
+fn car(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("car", args)? {
+        Value::Pair(pair) => Ok(pair.0.clone()),
+        other => Err(EvalError::TypeMismatch(format!("expected pair, got {}", other)).about(0))
+    }
+}
+
+fn cdr(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("cdr", args)? {
+        Value::Pair(pair) => Ok(pair.1.clone()),
+        other => Err(EvalError::TypeMismatch(format!("expected pair, got {}", other)).about(0))
+    }
+}
+
+fn length(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Int(about(0, one_arg("length", args)?.to_vec())?.len() as i64))
+}
+
+fn reverse(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let items = about(0, one_arg("reverse", args)?.to_vec())?;
+    Ok(items.into_iter().fold(Value::Nil, |tail, item| Value::cons(item, tail)))
+}
+
+/// Joins lists end to end. The last one isn't copied, but shared.
+fn append(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let mut args = args.into_iter().enumerate().rev();
+    let last = match args.next() {
+        Some((_, last)) if last.is_list() => last,
+        Some((i, other)) => return Err(EvalError::TypeMismatch(format!("expected list, got {}", other)).about(i)),
+        None => return Ok(Value::Nil)
+    };
+    args.try_fold(last, |tail, (i, list)| {
+        Ok(about(i, list.to_vec())?.into_iter().rev().fold(tail, |tail, item| Value::cons(item, tail)))
+    })
+}
+
+/// The element at a zero-based index in a list.
+fn nth(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (index, list) = two_args("nth", args)?;
+    let i = about(0, index.as_int())?;
+    let items = about(1, list.to_vec())?;
+    if i < 0 || i as usize >= items.len() {
+        return Err(EvalError::Index(format!("nth {} of {}", i, list)).about(0));
+    }
+    Ok(items.into_iter().nth(i as usize).expect("Index was checked"))
+}
+
+/// The elements of a list in order, by the comparator if given, which is
+/// called as `(less a b)` and must return a boolean, or else by number.
+/// Equal elements keep the order they had.
+fn sort(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(EvalError::Arity(format!("sort takes 1 or 2 arguments, got {}", args.len())));
+    }
+    let items = about(0, args[0].to_vec())?;
+    let sorted = match args.get(1) {
+        Some(less) => merge_sort(items, &mut |a, b| match apply(less, vec![a.clone(), b.clone()], env)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(EvalError::TypeMismatch(format!("expected the comparator to return a boolean, got {}", other)))
+        })?,
+        None => {
+            about(0, orderings(&items).map_err(EvalError::unindexed))?;
+            merge_sort(items, &mut |a, b| Ok(num_cmp(a, b)? == Ordering::Less))?
+        }
+    };
+    Ok(Value::list(sorted))
+}
+
+/// A stable sort that asks `less` about each pair at most once, and stops
+/// at the first error it returns, which the standard library's can't.
+fn merge_sort<F>(mut items: Vec<Value>, less: &mut F) -> Result<Vec<Value>, EvalError>
+    where F: FnMut(&Value, &Value) -> Result<bool, EvalError>
+{
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = merge_sort(items.split_off(items.len() / 2), less)?;
+    let left = merge_sort(items, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Taking from the left unless the right is strictly less is what
+        // keeps equal elements in order.
+        let next = if less(r, l)? { right.next() } else { left.next() };
+        merged.push(next.expect("Peeked element disappeared"));
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// The integers from the first argument up to but not including the
+/// second, counting by the third if given, or 1. A negative step counts
+/// down instead.
+fn range(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(EvalError::Arity(format!("range takes 2 or 3 arguments, got {}", args.len())));
+    }
+    let ints = ints(&args)?;
+    let (start, end, step) = (ints[0], ints[1], ints.get(2).cloned().unwrap_or(1));
+    if step == 0 {
+        return Err(EvalError::Invalid(format!("(range {} {} 0) never gets anywhere", start, end)).about(2));
+    }
+    let mut items = vec![];
+    let mut i = Some(start);
+    while let Some(n) = i.filter(|&n| if step > 0 { n < end } else { n > end }) {
+        items.push(Value::Int(n));
+        i = n.checked_add(step);
+    }
+    Ok(Value::list(items))
+}
+
+fn vector(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    Ok(Value::Vector(Arc::new(args)))
+}
+
+/// The vector in `val` and the index in `index`, which must be in range,
+/// as the first and second arguments.
+fn vector_index(val: Value, index: &Value) -> Result<(Arc<Vec<Value>>, usize), EvalError> {
+    let items = match val {
+        Value::Vector(items) => items,
+        other => return Err(EvalError::TypeMismatch(format!("expected vector, got {}", other)).about(0))
+    };
+    let i = about(1, index.as_int())?;
+    if i < 0 || i as usize >= items.len() {
+        return Err(EvalError::Index(format!("vector of length {} has no element {}", items.len(), i)).about(1));
+    }
+    Ok((items, i as usize))
+}
+
+fn vector_ref(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (vector, index) = two_args("vector-ref", args)?;
+    let (items, i) = vector_index(vector, &index)?;
+    Ok(items[i].clone())
+}
+
+/// A copy of a vector with the element at an index replaced; despite the
+/// name, the original is unchanged, as with `map-set`.
+fn vector_set(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    arity("vector-set!", &args, 3)?;
+    let mut args = args.into_iter();
+    let vector = args.next().expect("Argument disappeared");
+    let (mut items, i) = vector_index(vector, &args.next().expect("Argument disappeared"))?;
+    Arc::make_mut(&mut items)[i] = args.next().expect("Argument disappeared");
+    Ok(Value::Vector(items))
+}
+
+fn vector_length(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("vector-length", args)? {
+        Value::Vector(items) => Ok(Value::Int(items.len() as i64)),
+        other => Err(EvalError::TypeMismatch(format!("expected vector, got {}", other)).about(0))
+    }
+}
+
+fn make_map(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    arity("make-map", &args, 0)?;
+    Ok(Value::Map(Arc::new(BTreeMap::new())))
+}
+
+/// A copy of a map with a key set to a value; the original is unchanged.
+fn map_set(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    arity("map-set", &args, 3)?;
+    let mut args = args.into_iter();
+    let mut map = match args.next().expect("Argument disappeared") {
+        Value::Map(map) => map,
+        other => return Err(EvalError::TypeMismatch(format!("expected map, got {}", other)).about(0))
+    };
+    let key = about(1, Key::from_value(&args.next().expect("Argument disappeared")))?;
+    Arc::make_mut(&mut map).insert(key, args.next().expect("Argument disappeared"));
+    Ok(Value::Map(map))
+}
+
+fn map_get(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (map, key) = two_args("map-get", args)?;
+    match map {
+        Value::Map(ref map) => map.get(&about(1, Key::from_value(&key))?).cloned()
+            .ok_or_else(|| EvalError::NoSuchKey(key.to_string()).about(1)),
+        other => Err(EvalError::TypeMismatch(format!("expected map, got {}", other)).about(0))
+    }
+}
+
+/// The entries of an association list, which are pairs of a key and a
+/// value such as `(a . 1)`.
+fn alist_entries(alist: &Value) -> Result<Vec<Value>, EvalError> {
+    let entries = alist.to_vec()?;
+    match entries.iter().find(|entry| !matches!(entry, Value::Pair(_))) {
+        Some(entry) => Err(EvalError::TypeMismatch(format!("expected a pair in association list, got {}", entry))),
+        None => Ok(entries)
+    }
+}
+
+/// The first entry of an association list whose key is `equal?` to the
+/// one given, or `#f` if none is.
+fn assoc(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (key, alist) = two_args("assoc", args)?;
+    for entry in about(1, alist_entries(&alist))? {
+        if let Value::Pair(ref pair) = entry {
+            if pair.0.equal(&key) {
+                return Ok(entry.clone());
+            }
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+/// A map of the entries of an association list, where the first entry
+/// with a key wins as it would for `assoc`.
+fn alist_to_map(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let mut map = BTreeMap::new();
+    for entry in about(0, alist_entries(&one_arg("alist->map", args)?))? {
+        if let Value::Pair(ref pair) = entry {
+            map.entry(about(0, Key::from_value(&pair.0))?).or_insert_with(|| pair.1.clone());
+        }
+    }
+    Ok(Value::Map(Arc::new(map)))
+}
+
+fn error(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("error", args)? {
+        Value::Str(message) => Err(EvalError::Custom(message)),
+        other => Err(EvalError::TypeMismatch(format!("expected string, got {}", other)).about(0))
+    }
+}
+
+/// The message given after the `n` arguments checked, if any.
+fn assertion(name: &str, args: &[Value], n: usize) -> Result<String, EvalError> {
+    match args.len() {
+        len if len == n => Ok(String::from("Assertion failed")),
+        len if len == n + 1 => match args[n] {
+            Value::Str(ref message) => Ok(format!("Assertion failed: {}", message)),
+            ref other => Err(EvalError::TypeMismatch(format!("expected string, got {}", other)).about(n))
+        },
+        len => Err(EvalError::Arity(format!("{} takes {} or {} arguments, got {}", name, n, n + 1, len)))
+    }
+}
+
+/// Fails unless the value is truthy. `(assert expr)` is read as a call
+/// with the text of `expr` as the message.
+fn assert(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let message = assertion("assert", &args, 1)?;
+    if !args[0].is_truthy() {
+        return Err(EvalError::Custom(message));
+    }
+    Ok(Value::Unit)
+}
+
+/// Fails unless the values are `equal?`, with both in the message.
+fn assert_equal(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let message = assertion("assert-equal", &args, 2)?;
+    if !args[0].equal(&args[1]) {
+        return Err(EvalError::Custom(format!("{}, got {} and {}", message, args[0], args[1])));
+    }
+    Ok(Value::Unit)
+}
+
+fn load(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match one_arg("load", args)? {
+        Value::Str(path) => { read::load(&path, env)?; Ok(Value::Unit) },
+        other => Err(EvalError::TypeMismatch(format!("expected string, got {}", other)).about(0))
+    }
+}
+
+/// Fills in each `~a` in a format string with the next argument, strings
+/// without their quotes. `~~` is a literal `~`.
+fn format(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let (template, values) = match args.split_first() {
+        Some((Value::Str(template), values)) => (template, values),
+        Some((other, _)) => return Err(EvalError::TypeMismatch(format!("expected string, got {}", other)).about(0)),
+        None => return Err(EvalError::Arity(String::from("format takes at least 1 argument")))
+    };
+    let mut out = String::new();
+    let mut placeholders = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => {
+                match values.get(placeholders) {
+                    Some(Value::Str(s)) => out.push_str(s),
+                    Some(val) => out.push_str(&val.to_string()),
+                    None => ()
+                }
+                placeholders += 1;
+            },
+            Some('~') => out.push('~'),
+            Some(c) => return Err(EvalError::TypeMismatch(format!("unknown format directive '~{}'", c)).about(0)),
+            None => return Err(EvalError::TypeMismatch(String::from("format string ends with '~'")).about(0))
+        }
+    }
+    if placeholders != values.len() {
+        return Err(EvalError::Arity(format!("format string has {} placeholders, got {} arguments",
+                                            placeholders, values.len())));
+    }
+    Ok(Value::Str(out))
+}
+
+fn string_append(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let mut out = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        out.push_str(about(i, arg.as_str())?);
+    }
+    Ok(Value::Str(out))
+}
+
+fn string_length(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let s = one_arg("string-length", args)?;
+    Ok(Value::Int(about(0, s.as_str())?.chars().count() as i64))
+}
+
+/// The characters of a string from a start index up to, but not including,
+/// an end index that defaults to the end of the string.
+fn substring(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(EvalError::Arity(format!("substring takes 2 or 3 arguments, got {}", args.len())));
+    }
+    let s = about(0, args[0].as_str())?;
+    let len = s.chars().count() as i64;
+    let start = about(1, args[1].as_int())?;
+    let end = match args.get(2) {
+        Some(end) => about(2, end.as_int())?,
+        None => len
+    };
+    if start < 0 || end < start || end > len {
+        return Err(EvalError::Index(format!("substring {}..{} of {}", start, end, args[0])));
+    }
+    Ok(Value::Str(s.chars().skip(start as usize).take((end - start) as usize).collect()))
+}
+
+fn string_to_number(args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+    let s = one_arg("string->number", args)?;
+    match parse_number(about(0, s.as_str())?) {
+        Ok(Token::Float(x)) => Ok(Value::Float(x)),
+        Ok(Token::Int(i)) => Ok(Value::Int(i)),
+        Ok(_) => unreachable!("parse_number only makes numbers"),
+        Err(ReadError::Parse(_)) => Err(EvalError::Overflow(format!("(string->number {})", s)).about(0)),
+        Err(_) => Err(EvalError::TypeMismatch(format!("expected a number, got {}", s)).about(0))
+    }
+}
+
+fn print(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("print", args)?;
+    writeln!(env.output(), "{}", val)?;
+    Ok(val)
+}
+
+/// Writes a value for people to read, with strings and characters as just
+/// their text, and no newline.
+fn display(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("display", args)?;
+    write!(env.output(), "{}", val.display())?;
+    Ok(Value::Unit)
+}
+
+/// Writes a value the way the reader reads it, and no newline.
+fn write(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let val = one_arg("write", args)?;
+    write!(env.output(), "{}", val)?;
+    Ok(Value::Unit)
+}
+
+/// The next line of input as a string, or false at the end of it.
+fn read_line(args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    arity("read-line", &args, 0)?;
+    Ok(env.read_line()?.map_or(Value::Bool(false), Value::Str))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use lisp::expr::{Environment, EvalError};
+    use lisp::value::Value;
+    use super::FuncId;
+
+    fn ints(vals: &[i64]) -> Vec<Value> {
+        vals.iter().map(|i| Value::Int(*i)).collect()
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_add_two_and_two() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(4), FuncId::Add.call(ints(&[2, 2]), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_add_three_values() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(6), FuncId::Add.call(ints(&[1, 2, 3]), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_add_char_is_type_mismatch() {
+        let mut env = Environment::with_builtins();
+        match FuncId::Add.call(vec![Value::Int(1), Value::Char('a')], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[cfg(not(feature = "bignum"))]
+    fn assert_overflow(func: FuncId, args: &[i64]) {
+        let mut env = Environment::with_builtins();
+        match func.call(ints(args), &mut env) {
+            Err(EvalError::Overflow(_)) => (),
+            other => panic!("Expected overflow, got {:?}", other)
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    fn assert_overflow(func: FuncId, args: &[i64]) {
+        let mut env = Environment::with_builtins();
+        match func.call(ints(args), &mut env) {
+            Ok(Value::BigInt(_)) => (),
+            other => panic!("Expected a big integer, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_add_overflow() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(i64::MAX), FuncId::Add.call(ints(&[i64::MAX - 1, 1]), &mut env).unwrap());
+        assert_overflow(FuncId::Add, &[i64::MAX, 1]);
+        assert_overflow(FuncId::Add, &[i64::MIN, -1]);
+    }
+
+    #[test]
+    fn test_sub_overflow() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(i64::MIN), FuncId::Sub.call(ints(&[i64::MIN + 1, 1]), &mut env).unwrap());
+        assert_overflow(FuncId::Sub, &[i64::MIN, 1]);
+        assert_overflow(FuncId::Sub, &[i64::MIN]);
+    }
+
+    #[test]
+    fn test_mul_overflow() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(i64::MIN), FuncId::Mul.call(ints(&[i64::MIN / 2, 2]), &mut env).unwrap());
+        assert_overflow(FuncId::Mul, &[i64::MAX, 2]);
+        assert_overflow(FuncId::Mul, &[i64::MIN, -1]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_overflow_message() {
+        let mut env = Environment::with_builtins();
+        let err = FuncId::Add.call(ints(&[i64::MAX, 1]), &mut env).unwrap_err();
+        assert_eq!("Integer overflow: (+ 9223372036854775807 1)", err.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "bignum")]
+    fn test_big_arithmetic() {
+        let mut env = Environment::with_builtins();
+        let big = FuncId::Mul.call(ints(&[i64::MAX, 4]), &mut env).unwrap();
+        assert_eq!("36893488147419103228", big.to_string());
+        let back = FuncId::Sub.call(vec![big.clone(), Value::Int(i64::MAX), Value::Int(i64::MAX), Value::Int(i64::MAX)], &mut env);
+        assert_eq!(Value::Int(i64::MAX), back.unwrap());
+        assert_eq!(Value::Bool(true), FuncId::Lt.call(vec![Value::Int(1), big.clone()], &mut env).unwrap());
+        assert_eq!(Value::Bool(true), FuncId::IsNumber.call(vec![big], &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_abs() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(5), FuncId::Abs.call(ints(&[-5]), &mut env).unwrap());
+        assert_eq!(Value::Int(5), FuncId::Abs.call(ints(&[5]), &mut env).unwrap());
+        assert_eq!(Value::Int(i64::MAX), FuncId::Abs.call(ints(&[-i64::MAX]), &mut env).unwrap());
+        assert_overflow(FuncId::Abs, &[i64::MIN]);
+        match FuncId::Abs.call(ints(&[1, 2]), &mut env) {
+            Err(EvalError::Arity(_)) => (),
+            other => panic!("Expected arity error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_min_max() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(1), FuncId::Min.call(ints(&[3, 1, 2]), &mut env).unwrap());
+        assert_eq!(Value::Int(3), FuncId::Max.call(ints(&[3, 1, 2]), &mut env).unwrap());
+        assert_eq!(Value::Int(-7), FuncId::Min.call(ints(&[-7]), &mut env).unwrap());
+        match FuncId::Max.call(vec![], &mut env) {
+            Err(EvalError::Arity(_)) => (),
+            other => panic!("Expected arity error, got {:?}", other)
+        }
+        match FuncId::Min.call(vec![Value::Int(1), Value::Bool(true)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_gcd() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(6), FuncId::Gcd.call(ints(&[12, 18]), &mut env).unwrap());
+        assert_eq!(Value::Int(4), FuncId::Gcd.call(ints(&[-12, 8, 20]), &mut env).unwrap());
+        assert_eq!(Value::Int(5), FuncId::Gcd.call(ints(&[-5]), &mut env).unwrap());
+        assert_eq!(Value::Int(0), FuncId::Gcd.call(ints(&[0, 0]), &mut env).unwrap());
+        match FuncId::Gcd.call(ints(&[i64::MIN, 0]), &mut env) {
+            Err(EvalError::Overflow(_)) => (),
+            other => panic!("Expected overflow, got {:?}", other)
+        }
+        match FuncId::Gcd.call(vec![], &mut env) {
+            Err(EvalError::Arity(_)) => (),
+            other => panic!("Expected arity error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_expt() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(1024), FuncId::Expt.call(ints(&[2, 10]), &mut env).unwrap());
+        assert_eq!(Value::Int(-27), FuncId::Expt.call(ints(&[-3, 3]), &mut env).unwrap());
+        assert_eq!(Value::Int(1), FuncId::Expt.call(ints(&[7, 0]), &mut env).unwrap());
+        assert_eq!(Value::Int(1), FuncId::Expt.call(ints(&[0, 0]), &mut env).unwrap());
+        assert_eq!(Value::Int(-1), FuncId::Expt.call(ints(&[-1, i64::MAX]), &mut env).unwrap());
+        assert_eq!(Value::Int(i64::MIN), FuncId::Expt.call(ints(&[-2, 63]), &mut env).unwrap());
+        assert_overflow(FuncId::Expt, &[2, 63]);
+        assert_overflow(FuncId::Expt, &[10, 19]);
+        match FuncId::Expt.call(ints(&[2, -1]), &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    fn float(x: f64) -> Value {
+        Value::Float(x)
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(float(3.5), FuncId::Add.call(vec![Value::Int(1), float(2.5)], &mut env).unwrap());
+        assert_eq!(float(-1.5), FuncId::Sub.call(vec![float(1.5)], &mut env).unwrap());
+        assert_eq!(float(0.5), FuncId::Sub.call(vec![Value::Int(2), float(1.5)], &mut env).unwrap());
+        assert_eq!(float(3.0), FuncId::Mul.call(vec![Value::Int(2), float(1.5)], &mut env).unwrap());
+        assert_eq!(float(2.5), FuncId::Abs.call(vec![float(-2.5)], &mut env).unwrap());
+        assert_eq!(float(0.5), FuncId::Min.call(vec![Value::Int(1), float(0.5)], &mut env).unwrap());
+        assert_eq!(Value::Bool(true), FuncId::Lt.call(vec![Value::Int(1), float(1.5), Value::Int(2)], &mut env).unwrap());
+        assert_eq!(Value::Bool(true), FuncId::Eq.call(vec![Value::Int(2), float(2.0)], &mut env).unwrap());
+        assert!(matches!(FuncId::Lt.call(vec![float(f64::NAN), Value::Int(1)], &mut env), Err(EvalError::Invalid(_))));
+        assert_eq!(float(2.0_f64.sqrt()), FuncId::Expt.call(vec![float(2.0), float(0.5)], &mut env).unwrap());
+        assert_eq!("2.0", float(2.0).to_string());
+    }
+
+    #[test]
+    fn test_modulo_and_remainder() {
+        let mut env = Environment::with_builtins();
+        let cases = [(7, 3, 1, 1), (-7, 3, 2, -1), (7, -3, -2, 1), (-7, -3, -1, -1), (6, 3, 0, 0), (-6, 3, 0, 0),
+                     (i64::MIN, -1, 0, 0), (i64::MIN, i64::MAX, i64::MAX - 1, -1)];
+        for (a, b, modulo, remainder) in cases {
+            assert_eq!(Value::Int(modulo), FuncId::Modulo.call(ints(&[a, b]), &mut env).unwrap(), "(modulo {} {})", a, b);
+            assert_eq!(Value::Int(remainder), FuncId::Remainder.call(ints(&[a, b]), &mut env).unwrap(), "(remainder {} {})", a, b);
+        }
+        for func in [FuncId::Modulo, FuncId::Remainder] {
+            assert!(matches!(func.call(ints(&[5, 0]), &mut env), Err(EvalError::Invalid(_))));
+            assert!(matches!(func.call(vec![float(5.0), Value::Int(2)], &mut env), Err(EvalError::TypeMismatch(_))));
+            assert!(matches!(func.call(ints(&[5]), &mut env), Err(EvalError::Arity(_))));
+        }
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(float(2.0_f64.sqrt()), FuncId::Sqrt.call(ints(&[2]), &mut env).unwrap());
+        assert_eq!(float(1.5), FuncId::Sqrt.call(vec![float(2.25)], &mut env).unwrap());
+        assert_eq!(float(0.0), FuncId::Sqrt.call(ints(&[0]), &mut env).unwrap());
+        assert!(matches!(FuncId::Sqrt.call(ints(&[-1]), &mut env), Err(EvalError::Invalid(_))));
+        assert!(matches!(FuncId::Sqrt.call(vec![float(-0.25)], &mut env), Err(EvalError::Invalid(_))));
+        assert!(matches!(FuncId::Sqrt.call(vec![str("4")], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_rounding() {
+        let mut env = Environment::with_builtins();
+        let cases = [
+            (FuncId::Floor, [3, -4, 3, 2, 2, -3]),
+            (FuncId::Ceil, [4, -3, 3, 3, 2, -2]),
+            (FuncId::Round, [4, -4, 3, 2, 2, -2]),
+            (FuncId::Truncate, [3, -3, 3, 2, 2, -2])
+        ];
+        for (func, expected) in cases {
+            let args = [float(3.7), float(-3.7), Value::Int(3), float(2.5), float(2.0), float(-2.5)];
+            for (arg, want) in args.iter().zip(expected) {
+                assert_eq!(Value::Int(want), func.call(vec![arg.clone()], &mut env).unwrap(), "{:?} {}", func, arg);
+            }
+        }
+        assert_eq!(Value::Int(4), FuncId::Round.call(vec![float(3.5)], &mut env).unwrap());
+        assert!(matches!(FuncId::Floor.call(vec![float(f64::NAN)], &mut env), Err(EvalError::Invalid(_))));
+        assert!(matches!(FuncId::Ceil.call(vec![float(f64::INFINITY)], &mut env), Err(EvalError::Invalid(_))));
+        assert!(matches!(FuncId::Round.call(vec![str("1.5")], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[cfg(not(feature = "bignum"))]
+    #[test]
+    fn test_rounding_overflow() {
+        let mut env = Environment::with_builtins();
+        assert!(matches!(FuncId::Floor.call(vec![float(1e19)], &mut env), Err(EvalError::Overflow(_))));
+        assert_eq!(Value::Int(i64::MIN), FuncId::Floor.call(vec![float(-9_223_372_036_854_775_808.0)], &mut env).unwrap());
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn test_rounding_overflow() {
+        let mut env = Environment::with_builtins();
+        assert_eq!("10000000000000000000", FuncId::Floor.call(vec![float(1e19)], &mut env).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_trigonometry() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(float(0.0), FuncId::Sin.call(ints(&[0]), &mut env).unwrap());
+        assert_eq!(float(1.0), FuncId::Cos.call(ints(&[0]), &mut env).unwrap());
+        assert_eq!(float(0.0), FuncId::Tan.call(vec![float(0.0)], &mut env).unwrap());
+        assert_eq!(float(1.0_f64.atan()), FuncId::Atan.call(ints(&[1]), &mut env).unwrap());
+        let pi = env.get("pi").unwrap().as_float().unwrap();
+        assert!(pi.to_string().starts_with("3.14159"), "{}", pi);
+        assert_eq!(float(pi / 2.0), FuncId::Atan.call(ints(&[1, 0]), &mut env).unwrap());
+        assert_eq!(float(-3.0 * pi / 4.0), FuncId::Atan.call(ints(&[-1, -1]), &mut env).unwrap());
+        assert!(matches!(FuncId::Atan.call(ints(&[1, 2, 3]), &mut env), Err(EvalError::Arity(_))));
+        assert!(matches!(FuncId::Sin.call(vec![str("0")], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_random() {
+        let mut env = Environment::with_builtins();
+        let draws = |env: &mut Environment| -> Vec<Value> {
+            FuncId::Seed.call(ints(&[42]), env).unwrap();
+            (0..20).map(|_| FuncId::Random.call(ints(&[10]), env).unwrap()).collect()
+        };
+        let first = draws(&mut env);
+        assert_eq!(first, draws(&mut env));
+        assert_eq!(first, draws(&mut Environment::with_builtins()));
+        assert!(first.iter().all(|val| (0..10).contains(&val.as_int().unwrap())));
+        assert!(first.iter().any(|val| *val != first[0]));
+        assert_eq!(Value::Int(0), FuncId::Random.call(ints(&[1]), &mut env).unwrap());
+        let big = FuncId::Random.call(ints(&[i64::MAX]), &mut env).unwrap().as_int().unwrap();
+        assert!((0..i64::MAX).contains(&big));
+        for bad in [0, -5] {
+            assert!(matches!(FuncId::Random.call(ints(&[bad]), &mut env), Err(EvalError::Invalid(_))));
+        }
+        assert!(matches!(FuncId::Random.call(vec![float(2.5)], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    fn str(s: &str) -> Value {
+        Value::Str(String::from(s))
+    }
+
+    #[test]
+    fn test_format() {
+        let mut env = Environment::with_builtins();
+        let args = vec![str("x=~a y=~a"), Value::Int(1), Value::Int(2)];
+        assert_eq!(str("x=1 y=2"), FuncId::Format.call(args, &mut env).unwrap());
+        let args = vec![str("~a and ~a"), str("text"), Value::list(vec![str("s"), Value::Bool(true)])];
+        assert_eq!(str("text and (\"s\" #t)"), FuncId::Format.call(args, &mut env).unwrap());
+        assert_eq!(str("no placeholders"), FuncId::Format.call(vec![str("no placeholders")], &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_format_tilde() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(str("~a is 5, ~6"), FuncId::Format.call(vec![str("~~a is ~a, ~~~a"), Value::Int(5), Value::Int(6)], &mut env).unwrap());
+        match FuncId::Format.call(vec![str("~b")], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_format_argument_count() {
+        let mut env = Environment::with_builtins();
+        for args in [vec![str("~a ~a"), Value::Int(1)], vec![str("~a"), Value::Int(1), Value::Int(2)], vec![]] {
+            match FuncId::Format.call(args, &mut env) {
+                Err(EvalError::Arity(_)) => (),
+                other => panic!("Expected arity error, got {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_append() {
+        let mut env = Environment::with_builtins();
+        let args = vec![str("a"), str("b"), str("c")];
+        assert_eq!(str("abc"), FuncId::StringAppend.call(args, &mut env).unwrap());
+        assert_eq!(str(""), FuncId::StringAppend.call(vec![], &mut env).unwrap());
+        match FuncId::StringAppend.call(vec![str("a"), Value::Int(1)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_string_length() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(5), FuncId::StringLength.call(vec![str("hello")], &mut env).unwrap());
+        assert_eq!(Value::Int(3), FuncId::StringLength.call(vec![str("aλ🦀")], &mut env).unwrap());
+        assert_eq!(Value::Int(0), FuncId::StringLength.call(vec![str("")], &mut env).unwrap());
+        match FuncId::StringLength.call(vec![Value::Char('a')], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_substring() {
+        let mut env = Environment::with_builtins();
+        let sub = |args: &[i64], env: &mut Environment| {
+            let mut all = vec![str("hello")];
+            all.extend(ints(args));
+            FuncId::Substring.call(all, env)
+        };
+        assert_eq!(str("el"), sub(&[1, 3], &mut env).unwrap());
+        assert_eq!(str("llo"), sub(&[2], &mut env).unwrap());
+        assert_eq!(str(""), sub(&[5, 5], &mut env).unwrap());
+        assert_eq!(str("λ"), FuncId::Substring.call(vec![str("aλb"), Value::Int(1), Value::Int(2)], &mut env).unwrap());
+        for range in &[[0, 6], [3, 2], [-1, 2], [6, 6]] {
+            match sub(range, &mut env) {
+                Err(EvalError::Index(_)) => (),
+                other => panic!("Expected index error for {:?}, got {:?}", range, other)
+            }
+        }
+        match sub(&[], &mut env) {
+            Err(EvalError::Arity(_)) => (),
+            other => panic!("Expected arity error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_string_to_number() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(42), FuncId::StringToNumber.call(vec![str("42")], &mut env).unwrap());
+        assert_eq!(Value::Int(-255), FuncId::StringToNumber.call(vec![str("-0xff")], &mut env).unwrap());
+        for bad in &["", "abc", "4 2", "12a"] {
+            match FuncId::StringToNumber.call(vec![str(bad)], &mut env) {
+                Err(EvalError::TypeMismatch(_)) => (),
+                other => panic!("Expected type mismatch for {:?}, got {:?}", bad, other)
+            }
+        }
+        match FuncId::StringToNumber.call(vec![str("99999999999999999999")], &mut env) {
+            Err(EvalError::Overflow(_)) => (),
+            other => panic!("Expected overflow, got {:?}", other)
+        }
+    }
+
+    fn list(vals: &[i64]) -> Value {
+        Value::list(ints(vals))
+    }
+
+    #[test]
+    fn test_length() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(3), FuncId::Length.call(vec![list(&[1, 2, 3])], &mut env).unwrap());
+        assert_eq!(Value::Int(0), FuncId::Length.call(vec![Value::Nil], &mut env).unwrap());
+        match FuncId::Length.call(vec![Value::cons(Value::Int(1), Value::Int(2))], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(list(&[3, 2, 1]), FuncId::Reverse.call(vec![list(&[1, 2, 3])], &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Reverse.call(vec![Value::Nil], &mut env).unwrap());
+        match FuncId::Reverse.call(vec![Value::Int(1)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_append() {
+        let mut env = Environment::with_builtins();
+        let args = vec![list(&[1, 2]), Value::Nil, list(&[3]), list(&[4, 5])];
+        assert_eq!(list(&[1, 2, 3, 4, 5]), FuncId::Append.call(args, &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Append.call(vec![], &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Append.call(vec![Value::Nil, Value::Nil], &mut env).unwrap());
+        for args in [vec![list(&[1]), Value::Int(2)], vec![Value::Int(1), list(&[2])]] {
+            match FuncId::Append.call(args, &mut env) {
+                Err(EvalError::TypeMismatch(_)) => (),
+                other => panic!("Expected type mismatch, got {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_nth() {
+        let mut env = Environment::with_builtins();
+        let abc = Value::list(vec![Value::Symbol(String::from("a")), Value::Symbol(String::from("b")),
+                                   Value::Symbol(String::from("c"))]);
+        assert_eq!(Value::Symbol(String::from("b")), FuncId::Nth.call(vec![Value::Int(1), abc.clone()], &mut env).unwrap());
+        for args in [vec![Value::Int(3), abc.clone()], vec![Value::Int(-1), abc], vec![Value::Int(0), Value::Nil]] {
+            match FuncId::Nth.call(args, &mut env) {
+                Err(EvalError::Index(_)) => (),
+                other => panic!("Expected index error, got {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_cons_car_cdr() {
+        let mut env = Environment::with_builtins();
+        let list = FuncId::Cons.call(vec![Value::Int(1), Value::list(ints(&[2, 3]))], &mut env).unwrap();
+        assert_eq!(Value::list(ints(&[1, 2, 3])), list);
+        assert_eq!(Value::Int(1), FuncId::Car.call(vec![list.clone()], &mut env).unwrap());
+        assert_eq!(Value::list(ints(&[2, 3])), FuncId::Cdr.call(vec![list], &mut env).unwrap());
+        let pair = FuncId::Cons.call(ints(&[1, 2]), &mut env).unwrap();
+        assert_eq!(Value::Int(2), FuncId::Cdr.call(vec![pair], &mut env).unwrap());
+        for func in [FuncId::Car, FuncId::Cdr] {
+            assert!(matches!(func.call(vec![Value::Nil], &mut env), Err(EvalError::TypeMismatch(_))));
+        }
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut env = Environment::with_builtins();
+        env.set_input(Box::new("first line\r\nsecond\n\nlast".as_bytes()));
+        for line in ["first line", "second", "", "last"] {
+            assert_eq!(Value::Str(String::from(line)), FuncId::ReadLine.call(vec![], &mut env).unwrap());
+        }
+        assert_eq!(Value::Bool(false), FuncId::ReadLine.call(vec![], &mut env).unwrap());
+        assert!(matches!(FuncId::ReadLine.call(vec![Value::Int(1)], &mut env), Err(EvalError::Arity(_))));
+    }
+
+    #[test]
+    fn test_assert() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Unit, FuncId::Assert.call(vec![Value::Int(1)], &mut env).unwrap());
+        assert_eq!(Value::Unit, FuncId::AssertEqual.call(vec![Value::list(ints(&[1])), Value::list(ints(&[1]))], &mut env).unwrap());
+        match FuncId::Assert.call(vec![Value::Bool(false), Value::Str(String::from("x"))], &mut env) {
+            Err(EvalError::Custom(message)) => assert_eq!("Assertion failed: x", message),
+            other => panic!("Expected a failed assertion, got {:?}", other)
+        }
+        match FuncId::AssertEqual.call(vec![Value::Int(1), Value::Str(String::from("1"))], &mut env) {
+            Err(EvalError::Custom(message)) => assert_eq!("Assertion failed, got 1 and \"1\"", message),
+            other => panic!("Expected a failed assertion, got {:?}", other)
+        }
+        assert!(matches!(FuncId::Assert.call(vec![Value::Bool(false), Value::Int(1)], &mut env), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(FuncId::AssertEqual.call(vec![Value::Int(1)], &mut env), Err(EvalError::Arity(_))));
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut env = Environment::with_builtins();
+        let sorted = FuncId::Sort.call(vec![Value::list(ints(&[3, 1, 2, 5, 4, 1]))], &mut env).unwrap();
+        assert_eq!(Value::list(ints(&[1, 1, 2, 3, 4, 5])), sorted);
+        let mixed = Value::list(vec![float(2.5), Value::Int(-1), Value::Int(2)]);
+        assert_eq!(Value::list(vec![Value::Int(-1), Value::Int(2), float(2.5)]), FuncId::Sort.call(vec![mixed], &mut env).unwrap());
+        let args = vec![Value::list(ints(&[3, 1, 2])), Value::Builtin(FuncId::Gt)];
+        assert_eq!(Value::list(ints(&[3, 2, 1])), FuncId::Sort.call(args, &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Sort.call(vec![Value::Nil], &mut env).unwrap());
+        match FuncId::Sort.call(vec![Value::list(vec![Value::Int(1), str("a")])], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+        match FuncId::Sort.call(vec![Value::list(ints(&[2, 1])), Value::Builtin(FuncId::Add)], &mut env) {
+            Err(EvalError::TypeMismatch(ref err)) if err.contains("boolean") => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_range() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::list(ints(&[0, 1, 2, 3, 4])), FuncId::Range.call(ints(&[0, 5]), &mut env).unwrap());
+        assert_eq!(Value::list(ints(&[0, 2, 4, 6, 8])), FuncId::Range.call(ints(&[0, 10, 2]), &mut env).unwrap());
+        assert_eq!(Value::list(ints(&[1, 4])), FuncId::Range.call(ints(&[1, 5, 3]), &mut env).unwrap());
+        assert_eq!(Value::list(ints(&[5, 3, 1])), FuncId::Range.call(ints(&[5, 0, -2]), &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Range.call(ints(&[3, 3]), &mut env).unwrap());
+        assert_eq!(Value::Nil, FuncId::Range.call(ints(&[5, 0]), &mut env).unwrap());
+        assert_eq!(Value::list(ints(&[i64::MAX - 1])), FuncId::Range.call(ints(&[i64::MAX - 1, i64::MAX, 5]), &mut env).unwrap());
+        assert!(matches!(FuncId::Range.call(ints(&[0, 5, 0]), &mut env), Err(EvalError::Invalid(_))));
+        assert!(matches!(FuncId::Range.call(ints(&[5]), &mut env), Err(EvalError::Arity(_))));
+        assert!(matches!(FuncId::Range.call(vec![Value::Int(0), float(2.0)], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let mut env = Environment::with_builtins();
+        let empty = FuncId::MakeMap.call(vec![], &mut env).unwrap();
+        let one = FuncId::MapSet.call(vec![empty.clone(), str("a"), Value::Int(1)], &mut env).unwrap();
+        let two = FuncId::MapSet.call(vec![one.clone(), Value::Symbol(String::from("b")), Value::Int(2)], &mut env).unwrap();
+        assert_eq!(Value::Int(1), FuncId::MapGet.call(vec![two.clone(), str("a")], &mut env).unwrap());
+        assert_eq!(Value::Int(2), FuncId::MapGet.call(vec![two.clone(), Value::Symbol(String::from("b"))], &mut env).unwrap());
+        let replaced = FuncId::MapSet.call(vec![two.clone(), str("a"), Value::Int(3)], &mut env).unwrap();
+        assert_eq!(Value::Int(3), FuncId::MapGet.call(vec![replaced.clone(), str("a")], &mut env).unwrap());
+        assert_eq!("{}", empty.to_string());
+        assert_eq!("{\"a\" 1}", one.to_string());
+        assert_eq!("{\"a\" 3, b 2}", replaced.to_string());
+        // The maps that were set from are unchanged.
+        assert_eq!("{\"a\" 1, b 2}", two.to_string());
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let mut env = Environment::with_builtins();
+        let map = FuncId::MakeMap.call(vec![], &mut env).unwrap();
+        let map = FuncId::MapSet.call(vec![map, Value::Int(1), Value::Int(2)], &mut env).unwrap();
+        match FuncId::MapGet.call(vec![map.clone(), Value::Int(2)], &mut env) {
+            Err(EvalError::NoSuchKey(ref key)) if key == "2" => (),
+            other => panic!("Expected missing key, got {:?}", other)
+        }
+        match FuncId::MapGet.call(vec![map.clone(), str("1")], &mut env) {
+            Err(EvalError::NoSuchKey(_)) => (),
+            other => panic!("Expected missing key, got {:?}", other)
+        }
+        match FuncId::MapSet.call(vec![map, Value::Nil, Value::Int(2)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+        match FuncId::MapGet.call(vec![Value::Nil, Value::Int(1)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_list_and_cons_star() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Nil, FuncId::List.call(vec![], &mut env).unwrap());
+        assert_eq!("(1 2 3)", FuncId::List.call(ints(&[1, 2, 3]), &mut env).unwrap().to_string());
+        let tail = Value::list(ints(&[3, 4]));
+        assert_eq!("(1 2 3 4)", FuncId::ConsStar.call(vec![Value::Int(1), Value::Int(2), tail.clone()], &mut env).unwrap().to_string());
+        assert_eq!("(1 . 2)", FuncId::ConsStar.call(ints(&[1, 2]), &mut env).unwrap().to_string());
+        assert_eq!(tail, FuncId::ConsStar.call(vec![tail.clone()], &mut env).unwrap());
+        assert!(matches!(FuncId::ConsStar.call(vec![], &mut env), Err(EvalError::Arity(_))));
+    }
+
+    #[test]
+    fn test_vectors() {
+        let mut env = Environment::with_builtins();
+        let v = FuncId::Vector.call(ints(&[1, 2, 3]), &mut env).unwrap();
+        assert_eq!("#(1 2 3)", v.to_string());
+        assert_eq!("#()", FuncId::Vector.call(vec![], &mut env).unwrap().to_string());
+        assert_eq!(Value::Int(3), FuncId::VectorLength.call(vec![v.clone()], &mut env).unwrap());
+        assert_eq!(Value::Int(2), FuncId::VectorRef.call(vec![v.clone(), Value::Int(1)], &mut env).unwrap());
+        let set = FuncId::VectorSet.call(vec![v.clone(), Value::Int(0), str("a")], &mut env).unwrap();
+        assert_eq!("#(\"a\" 2 3)", set.to_string());
+        // The vector that was set from is unchanged.
+        assert_eq!("#(1 2 3)", v.to_string());
+        assert!(set.equal(&FuncId::Vector.call(vec![str("a"), Value::Int(2), Value::Int(3)], &mut env).unwrap()));
+        assert!(!set.equal(&v));
+
+        for i in [3, -1] {
+            assert!(matches!(FuncId::VectorRef.call(vec![v.clone(), Value::Int(i)], &mut env), Err(EvalError::Index(_))));
+            assert!(matches!(FuncId::VectorSet.call(vec![v.clone(), Value::Int(i), Value::Nil], &mut env), Err(EvalError::Index(_))));
+        }
+        assert!(matches!(FuncId::VectorRef.call(vec![Value::Nil, Value::Int(0)], &mut env), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(FuncId::VectorLength.call(vec![Value::list(ints(&[1]))], &mut env), Err(EvalError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(8), FuncId::BitAnd.call(ints(&[12, 10]), &mut env).unwrap());
+        assert_eq!(Value::Int(14), FuncId::BitOr.call(ints(&[12, 10]), &mut env).unwrap());
+        assert_eq!(Value::Int(6), FuncId::BitXor.call(ints(&[12, 10]), &mut env).unwrap());
+        assert_eq!(Value::Int(2), FuncId::BitAnd.call(ints(&[7, 14, 3]), &mut env).unwrap());
+        assert_eq!(Value::Int(-1), FuncId::BitAnd.call(vec![], &mut env).unwrap());
+        assert_eq!(Value::Int(0), FuncId::BitOr.call(vec![], &mut env).unwrap());
+        assert_eq!(Value::Int(-13), FuncId::BitNot.call(ints(&[12]), &mut env).unwrap());
+        assert_eq!(Value::Int(0), FuncId::BitNot.call(ints(&[-1]), &mut env).unwrap());
+        match FuncId::BitXor.call(vec![Value::Int(1), Value::Bool(true)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(16), FuncId::ShiftLeft.call(ints(&[1, 4]), &mut env).unwrap());
+        assert_eq!(Value::Int(i64::MIN), FuncId::ShiftLeft.call(ints(&[1, 63]), &mut env).unwrap());
+        assert_eq!(Value::Int(5), FuncId::ShiftRight.call(ints(&[10, 1]), &mut env).unwrap());
+        assert_eq!(Value::Int(-1), FuncId::ShiftRight.call(ints(&[-8, 63]), &mut env).unwrap());
+        assert_eq!(Value::Int(7), FuncId::ShiftRight.call(ints(&[7, 0]), &mut env).unwrap());
+        for (func, amount) in [(FuncId::ShiftLeft, 64), (FuncId::ShiftRight, 64), (FuncId::ShiftLeft, -1),
+                               (FuncId::ShiftRight, i64::MAX)] {
+            match func.call(ints(&[1, amount]), &mut env) {
+                Err(EvalError::Overflow(_)) => (),
+                other => panic!("Expected overflow for {:?} {}, got {:?}", func, amount, other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_returns_value() {
+        let mut env = Environment::with_builtins();
+        let buf = SharedBuf::default();
+        env.set_output(Box::new(buf.clone()));
+        assert_eq!(Value::Int(42), FuncId::Print.call(ints(&[42]), &mut env).unwrap());
+        assert_eq!(b"42\n".to_vec(), *buf.0.lock().unwrap());
+    }
+
+    #[test]
+    fn test_print_takes_one_argument() {
+        let mut env = Environment::with_builtins();
+        FuncId::Print.call(ints(&[1, 2]), &mut env).unwrap_err();
+    }
+
+    #[test]
+    fn test_display_and_write() {
+        let mut env = Environment::with_builtins();
+        let buf = SharedBuf::default();
+        env.set_output(Box::new(buf.clone()));
+        let s = Value::Str(String::from("a\"b"));
+        assert_eq!(Value::Unit, FuncId::Display.call(vec![s.clone()], &mut env).unwrap());
+        assert_eq!(b"a\"b".to_vec(), *buf.0.lock().unwrap());
+        buf.0.lock().unwrap().clear();
+        assert_eq!(Value::Unit, FuncId::Write.call(vec![s], &mut env).unwrap());
+        assert_eq!(b"\"a\\\"b\"".to_vec(), *buf.0.lock().unwrap());
+
+        let nested = Value::list(vec![Value::Str(String::from("x")), Value::Char('y'), Value::Char(' ')]);
+        buf.0.lock().unwrap().clear();
+        FuncId::Display.call(vec![nested.clone()], &mut env).unwrap();
+        FuncId::Write.call(vec![nested], &mut env).unwrap();
+        assert_eq!("(x y  )(\"x\" #\\y #\\space)", String::from_utf8(buf.0.lock().unwrap().clone()).unwrap());
+        assert!(matches!(FuncId::Write.call(vec![], &mut env), Err(EvalError::Arity(_))));
+    }
+
+    #[test]
+    fn test_compare_two() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Bool(true), FuncId::Lt.call(ints(&[1, 2]), &mut env).unwrap());
+        assert_eq!(Value::Bool(false), FuncId::Ge.call(ints(&[1, 2]), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_not_equal() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Bool(true), FuncId::Ne.call(ints(&[1, 2]), &mut env).unwrap());
+        assert_eq!(Value::Bool(false), FuncId::Ne.call(ints(&[2, 2]), &mut env).unwrap());
+        assert_eq!(Value::Bool(false), FuncId::Ne.call(vec![Value::Int(2), float(2.0)], &mut env).unwrap());
+        assert_eq!(Value::Bool(true), FuncId::Ne.call(ints(&[1, 2, 1]), &mut env).unwrap());
+        assert!(matches!(FuncId::Ne.call(vec![Value::Int(1), Value::Nil], &mut env), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(FuncId::Ne.call(vec![], &mut env), Err(EvalError::Arity(_))));
+    }
+
+    #[test]
+    fn test_compare_chained() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Bool(true), FuncId::Lt.call(ints(&[1, 2, 3]), &mut env).unwrap());
+        assert_eq!(Value::Bool(false), FuncId::Lt.call(ints(&[1, 3, 2]), &mut env).unwrap());
+        assert_eq!(Value::Bool(true), FuncId::Eq.call(ints(&[2, 2, 2]), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_compare_non_number() {
+        let mut env = Environment::with_builtins();
+        match FuncId::Eq.call(vec![Value::Int(1), Value::Bool(true)], &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    fn check(func: FuncId, val: Value) -> Value {
+        let mut env = Environment::with_builtins();
+        func.call(vec![val], &mut env).unwrap()
+    }
+
+    #[test]
+    fn test_is_number() {
+        assert_eq!(Value::Bool(true), check(FuncId::IsNumber, Value::Int(3)));
+        assert_eq!(Value::Bool(false), check(FuncId::IsNumber, Value::Char('3')));
+    }
+
+    #[test]
+    fn test_is_string() {
+        assert_eq!(Value::Bool(true), check(FuncId::IsString, Value::Str(String::from("foo"))));
+        assert_eq!(Value::Bool(false), check(FuncId::IsString, Value::Symbol(String::from("foo"))));
+    }
+
+    #[test]
+    fn test_is_bool() {
+        assert_eq!(Value::Bool(true), check(FuncId::IsBool, Value::Bool(false)));
+        assert_eq!(Value::Bool(false), check(FuncId::IsBool, Value::Int(0)));
+    }
+
+    #[test]
+    fn test_is_list() {
+        assert_eq!(Value::Bool(true), check(FuncId::IsList, Value::list(ints(&[1, 2]))));
+        assert_eq!(Value::Bool(true), check(FuncId::IsList, Value::Nil));
+        assert_eq!(Value::Bool(false), check(FuncId::IsList, Value::cons(Value::Int(1), Value::Int(2))));
+    }
+
+    #[test]
+    fn test_is_symbol() {
+        assert_eq!(Value::Bool(true), check(FuncId::IsSymbol, Value::Symbol(String::from("foo"))));
+        assert_eq!(Value::Bool(false), check(FuncId::IsSymbol, Value::Str(String::from("foo"))));
+    }
+
+    #[test]
+    fn test_not() {
+        assert_eq!(Value::Bool(true), check(FuncId::Not, Value::Bool(false)));
+        assert_eq!(Value::Bool(true), check(FuncId::Not, Value::Int(0)));
+        assert_eq!(Value::Bool(false), check(FuncId::Not, Value::Nil));
+    }
+
+    #[test]
+    fn test_map_builtin() {
+        let mut env = Environment::with_builtins();
+        let args = vec![Value::Builtin(FuncId::Sub), Value::list(ints(&[1, 2]))];
+        assert_eq!(Value::list(ints(&[-1, -2])), FuncId::Map.call(args, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_map_empty() {
+        let mut env = Environment::with_builtins();
+        let args = vec![Value::Builtin(FuncId::Sub), Value::Nil];
+        assert_eq!(Value::Nil, FuncId::Map.call(args, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_map_non_list() {
+        let mut env = Environment::with_builtins();
+        let args = vec![Value::Builtin(FuncId::Sub), Value::Int(1)];
+        match FuncId::Map.call(args, &mut env) {
+            Err(EvalError::TypeMismatch(_)) => (),
+            other => panic!("Expected type mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_reduce_builtin() {
+        let mut env = Environment::with_builtins();
+        let args = vec![Value::Builtin(FuncId::Add), Value::Int(0), Value::list(ints(&[1, 2, 3]))];
+        assert_eq!(Value::Int(6), FuncId::Reduce.call(args, &mut env).unwrap());
+    }
+}
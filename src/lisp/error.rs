@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::fmt;
+use lisp::expr::EvalError;
+use lisp::read::ReadError;
+
+/// Anything that can go wrong while reading or evaluating source text.
+#[derive(Debug)]
+pub enum LustError {
+    Read(ReadError),
+    Eval(EvalError)
+}
+
+impl From<ReadError> for LustError {
+    fn from(err: ReadError) -> LustError {
+        LustError::Read(err)
+    }
+}
+
+impl From<EvalError> for LustError {
+    fn from(err: EvalError) -> LustError {
+        LustError::Eval(err)
+    }
+}
+
+impl fmt::Display for LustError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LustError::Read(ref err) => write!(f, "{}", err),
+            LustError::Eval(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for LustError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LustError::Read(ref err) => Some(err),
+            LustError::Eval(ref err) => Some(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::io;
+    use lisp::expr::EvalError;
+    use lisp::read::ReadError;
+    use super::LustError;
+
+    fn parse_then_eval(fail_read: bool) -> Result<(), LustError> {
+        if fail_read {
+            Err(ReadError::Eof)?;
+        }
+        Err(EvalError::Arity(String::from("f")))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_both_errors() {
+        assert!(matches!(parse_then_eval(true), Err(LustError::Read(ReadError::Eof))));
+        assert!(matches!(parse_then_eval(false), Err(LustError::Eval(EvalError::Arity(_)))));
+    }
+
+    #[test]
+    fn test_display_and_source() {
+        let err = LustError::from(EvalError::UndefinedName(String::from("x"), None));
+        assert_eq!("No such name in environment: x", err.to_string());
+        assert_eq!("No such name in environment: x", err.source().unwrap().to_string());
+
+        let err = LustError::from(ReadError::Io(io::Error::other("boom")));
+        assert_eq!("IO error: boom", err.to_string());
+        assert_eq!("boom", err.source().unwrap().source().unwrap().to_string());
+    }
+}
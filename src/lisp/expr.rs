@@ -1,294 +1,1777 @@
+use std::error::Error;
+use std::f64::consts;
 use std::fmt;
+use std::io;
+use std::io::{BufRead, Write};
+use std::iter;
+use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+use std::time::Instant;
 use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use lisp::builtins::FuncId;
+use lisp::read;
+use lisp::read::{Position, ReadError};
+use lisp::value::Value;
 
 #[derive(Debug)]
 pub enum EvalError {
-    UndefinedName(String)
+    /// A name with no binding, and the closest one that has, if any is
+    /// close enough to be a likely typo.
+    UndefinedName(String, Option<String>),
+    /// A name looked up before it was defined, while it is only declared,
+    /// by `Environment::declare` or `letrec`.
+    NotYetDefined(String),
+    Arity(String),
+    TypeMismatch(String),
+    /// Calls nested deeper than the environment's limit.
+    RecursionLimit(usize),
+    /// The budget set with `Environment::set_fuel` ran out.
+    OutOfFuel,
+    /// An arithmetic result that doesn't fit in an integer.
+    Overflow(String),
+    /// An argument of the right type that the function can't take, such as
+    /// a negative number to `sqrt`.
+    Invalid(String),
+    /// An index past the end of a string or list.
+    Index(String),
+    /// A key that isn't in a map.
+    NoSuchKey(String),
+    /// Raised by a script through `error`.
+    Custom(String),
+    /// An assignment to a name bound with `define-constant`.
+    Immutable(String),
+    /// A file given to `load` couldn't be read.
+    Load(String, ReadError),
+    /// A file given to `load` that is already being loaded.
+    LoadCycle(String),
+    /// What a macro expanded to, which isn't an expression. Boxed to keep
+    /// `EvalError`, and so every level of `eval`, small.
+    Expansion(String, Box<ReadError>),
+    Io(io::Error),
+    /// An error a builtin raised, with where in the source the argument it
+    /// was about, or else the call, starts.
+    At(Position, Box<EvalError>),
+    /// An error a builtin raised about the argument at an index, as it is
+    /// returned by `FuncId::call_indexed`, before `eval` turns it into `At`.
+    Arg(usize, Box<EvalError>)
+}
+
+impl EvalError {
+    /// This error as being about the argument at `index`.
+    pub fn about(self, index: usize) -> EvalError {
+        EvalError::Arg(index, Box::new(self))
+    }
+
+    /// This error without the index of the argument it's about, if it has
+    /// one.
+    pub fn unindexed(self) -> EvalError {
+        match self {
+            EvalError::Arg(_, err) => *err,
+            err => err
+        }
+    }
+}
+
+impl From<io::Error> for EvalError {
+    fn from(err: io::Error) -> EvalError {
+        EvalError::Io(err)
+    }
 }
 
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            EvalError::UndefinedName(ref err) => write!(f, "No such name in environment: {}", err)
+            EvalError::UndefinedName(ref name, Some(ref close)) =>
+                write!(f, "No such name in environment: {}, did you mean '{}'?", name, close),
+            EvalError::UndefinedName(ref name, None) => write!(f, "No such name in environment: {}", name),
+            EvalError::NotYetDefined(ref name) => write!(f, "{} is used before it is defined", name),
+            EvalError::Arity(ref err) => write!(f, "Wrong number of arguments: {}", err),
+            EvalError::TypeMismatch(ref err) => write!(f, "Type mismatch: {}", err),
+            EvalError::RecursionLimit(max) => write!(f, "Calls nested deeper than {}", max),
+            EvalError::OutOfFuel => write!(f, "Out of fuel"),
+            EvalError::Overflow(ref err) => write!(f, "Integer overflow: {}", err),
+            EvalError::Invalid(ref err) => write!(f, "Invalid argument: {}", err),
+            EvalError::Index(ref err) => write!(f, "Index out of range: {}", err),
+            EvalError::NoSuchKey(ref key) => write!(f, "No such key in map: {}", key),
+            EvalError::Custom(ref err) => write!(f, "{}", err),
+            EvalError::Immutable(ref name) => write!(f, "Can't assign to constant: {}", name),
+            EvalError::Load(ref path, ref err) => write!(f, "Couldn't load {}: {}", path, err),
+            EvalError::LoadCycle(ref path) => write!(f, "{} loads itself", path),
+            EvalError::Expansion(ref form, ref err) => write!(f, "Macro expanded to {}: {}", form, err),
+            EvalError::Io(ref err) => write!(f, "IO error: {}", err),
+            EvalError::At(pos, ref err) => write!(f, "{} at line {}, column {}", err, pos.line, pos.column),
+            EvalError::Arg(_, ref err) => write!(f, "{}", err)
         }
     }
 }
 
-pub trait Function : fmt::Debug {
-    fn call(&self, args: &Vec<Box<Expression>>, env: &mut Environment) -> Result<i64, EvalError>;
+impl Error for EvalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            EvalError::Load(_, ref err) => Some(err),
+            EvalError::Expansion(_, ref err) => Some(&**err),
+            EvalError::Io(ref err) => Some(err),
+            EvalError::At(_, ref err) | EvalError::Arg(_, ref err) => Some(&**err),
+            _ => None
+        }
+    }
 }
 
 
+/// One frame of variable bindings, chained to the frame it was created in.
+///
+/// Frames can be shared between threads. Each lookup, assignment or
+/// definition locks the one frame it touches for just that operation, so
+/// it is atomic on its own, but nothing makes a sequence of them atomic:
+/// two threads both evaluating `(set n (+ n 1))` can lose an increment.
 #[derive(Debug)]
-pub struct Add;
+pub struct Scope {
+    vars: RwLock<HashMap<String, Binding>>,
+    parent: Option<Arc<Scope>>,
+    /// Identifies the frame in the layers of forks that bind in it.
+    serial: u64,
+    /// The layer of the environment that created the frame, or 0.
+    layer: u64
+}
+
+/// A bound value, and whether it was bound with `define-constant`, so that
+/// `set` can't change it, or by `Environment::with_builtins`, or is only a
+/// placeholder from `Environment::declare`.
+#[derive(Debug, Clone)]
+struct Binding {
+    val: Value,
+    constant: bool,
+    builtin: bool,
+    declared: bool
+}
 
-impl Add {
-    pub fn new() -> Add {
-        return Add;
+impl Binding {
+    fn var(val: Value) -> Binding {
+        Binding {val, constant: false, builtin: false, declared: false}
     }
 }
 
-impl Function for Add {
-    fn call(&self, args: &Vec<Box<Expression>>, env: &mut Environment) -> Result<i64, EvalError> {
-        args.iter().fold(Ok(0), |acc, expr| { Ok(try!(acc) + try!(expr.eval(env))) })
+/// Serials for frames and layers, unique within the process.
+fn next_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+impl Scope {
+    fn new(parent: Option<Arc<Scope>>, layer: u64) -> Scope {
+        Scope {vars: RwLock::new(HashMap::new()), parent, serial: next_id(), layer}
+    }
+
+    /// A new frame inside `parent`, belonging to `env`.
+    pub fn child(parent: Arc<Scope>, env: &Environment) -> Scope {
+        Scope::new(Some(parent), env.layer_id())
+    }
+
+    // Nothing panics while holding the lock, so the bindings are still
+    // consistent even if it has been poisoned.
+    fn vars(&self) -> RwLockReadGuard<'_, HashMap<String, Binding>> {
+        self.vars.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn vars_mut(&self) -> RwLockWriteGuard<'_, HashMap<String, Binding>> {
+        self.vars.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn define(&self, name: &str, val: Value) {
+        self.vars_mut().insert(String::from(name), Binding::var(val));
     }
 }
 
+/// What a forked environment has bound in frames it didn't create. Those
+/// frames are shared with the environment it was forked from, which must
+/// not see the bindings, so they are kept here by frame instead.
 #[derive(Debug)]
+struct Layer {
+    id: u64,
+    vars: RwLock<HashMap<u64, HashMap<String, Binding>>>,
+    /// The layer of the environment this one was forked from.
+    below: Option<Arc<Layer>>
+}
+
+impl Layer {
+    fn new(below: Option<Arc<Layer>>) -> Layer {
+        Layer {id: next_id(), vars: RwLock::new(HashMap::new()), below}
+    }
+
+    fn vars(&self) -> RwLockReadGuard<'_, HashMap<u64, HashMap<String, Binding>>> {
+        self.vars.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn vars_mut(&self) -> RwLockWriteGuard<'_, HashMap<u64, HashMap<String, Binding>>> {
+        self.vars.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The bound name closest to `name` by edit distance, if it's close enough
+/// to be a likely typo. Ties go to the alphabetically first.
+fn closest<'a, I: Iterator<Item = &'a String>>(name: &str, known: I) -> Option<String> {
+    let mut best: Option<(usize, &String)> = None;
+    for known in known {
+        let distance = levenshtein(name, known);
+        if distance > name.chars().count().max(known.chars().count()) / 2 {
+            continue;
+        }
+        if best.is_none_or(|(d, b)| (distance, known) < (d, b)) {
+            best = Some((distance, known));
+        }
+    }
+    best.map(|(_, known)| known.clone())
+}
+
+/// How many single character insertions, deletions and substitutions it
+/// takes to turn `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+
 pub struct Environment {
-    vars: HashMap<String, i64>
+    scope: Arc<Scope>,
+    /// Where bindings in frames from before a fork go, if this is one.
+    layer: Option<Arc<Layer>>,
+    /// Where `read-line` reads from, if not stdin.
+    input: Option<Box<dyn BufRead + Send>>,
+    out: Box<dyn Write + Send>,
+    /// Where messages that aren't the program's own output go.
+    err: Box<dyn Write + Send>,
+    /// Where each call and what it returned is written, if tracing.
+    trace: Option<Box<dyn Write + Send>>,
+    /// How many calls are currently being evaluated.
+    depth: usize,
+    max_depth: usize,
+    /// How many more expressions may be evaluated, if limited.
+    fuel: Option<u64>,
+    /// The files being loaded, innermost last.
+    loading: Vec<PathBuf>,
+    /// The state of the generator behind `random`.
+    random: u64,
+    /// How many symbols `gensym` has made, shared with the environments
+    /// that share or fork this one so they never make the same one.
+    gensyms: Arc<AtomicU64>,
+    /// Whether rebinding a builtin is an error rather than a warning.
+    strict: bool,
+    warnings: Vec<String>
+}
+
+/// A different seed for each environment, taken from the randomly keyed
+/// hasher std uses for hash maps.
+fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Deep enough for ordinary recursion, shallow enough not to overflow the
+/// native stack of a 2 MiB thread in a debug build.
+pub const DEFAULT_MAX_DEPTH: usize = 400;
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Environment")
+            .field("scope", &self.scope)
+            .field("layer", &self.layer)
+            .field("depth", &self.depth)
+            .field("max_depth", &self.max_depth)
+            .field("fuel", &self.fuel)
+            .finish()
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
 }
 
 impl Environment {
+    /// Creates an environment with nothing bound in it.
     pub fn new() -> Environment {
-        Environment {vars: HashMap::new()}
+        Environment {
+            scope: Arc::new(Scope::new(None, 0)),
+            layer: None,
+            input: None,
+            out: Box::new(io::stdout()),
+            err: Box::new(io::stderr()),
+            trace: None,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            loading: vec![],
+            random: random_seed(),
+            gensyms: Arc::new(AtomicU64::new(0)),
+            strict: false,
+            warnings: vec![]
+        }
+    }
+
+    /// Creates an environment where the builtin functions are bound to
+    /// their names, as ordinary values, along with `pi`.
+    pub fn with_builtins() -> Environment {
+        let env = Environment::new();
+        let builtin = |val| Binding {val, constant: false, builtin: true, declared: false};
+        let mut vars = env.scope.vars_mut();
+        for func in FuncId::ALL {
+            vars.insert(String::from(func.name()), builtin(Value::Builtin(*func)));
+        }
+        vars.insert(String::from("pi"), builtin(Value::Float(consts::PI)));
+        vars.insert(String::from("nil"), builtin(Value::Nil));
+        drop(vars);
+        env
+    }
+
+    /// An environment for another thread that evaluates in the same scope
+    /// as this one, so each sees what the other defines or assigns. It has
+    /// its own output, which is stdout and stderr again, and its own limits,
+    /// which start out as this one's.
+    pub fn share(&self) -> Environment {
+        Environment {
+            scope: self.scope.clone(),
+            layer: self.layer.clone(),
+            input: None,
+            out: Box::new(io::stdout()),
+            err: Box::new(io::stderr()),
+            trace: None,
+            depth: 0,
+            max_depth: self.max_depth,
+            fuel: self.fuel,
+            loading: vec![],
+            random: random_seed(),
+            gensyms: self.gensyms.clone(),
+            strict: self.strict,
+            warnings: vec![]
+        }
+    }
+
+    /// An environment that starts out with everything bound here, but whose
+    /// definitions and assignments stay in it, even when made by closures
+    /// from here. It still sees later changes made here to names it hasn't
+    /// bound itself. Like `share`, it has its own output and limits.
+    pub fn fork(&self) -> Environment {
+        let mut fork = self.share();
+        fork.layer = Some(Arc::new(Layer::new(self.layer.clone())));
+        fork
+    }
+
+    /// A copy of this environment as it is now. Neither this environment
+    /// nor the copy sees what the other changes afterwards, though other
+    /// environments shared with this one stay where they were.
+    pub fn snapshot(&mut self) -> Environment {
+        let snapshot = self.fork();
+        self.layer = Some(Arc::new(Layer::new(self.layer.clone())));
+        snapshot
+    }
+
+    fn layer_id(&self) -> u64 {
+        self.layer.as_ref().map_or(0, |layer| layer.id)
+    }
+
+    /// The binding of `name` in `frame` as this environment sees it, which
+    /// is the one in the first layer that binds it, if any does before the
+    /// layer that created the frame.
+    fn lookup_in(&self, frame: &Scope, name: &str) -> Option<Binding> {
+        let mut layer = self.layer.as_deref();
+        while let Some(current) = layer {
+            if current.id == frame.layer {
+                break;
+            }
+            if let Some(val) = current.vars().get(&frame.serial).and_then(|vars| vars.get(name)) {
+                return Some(val.clone());
+            }
+            layer = current.below.as_deref();
+        }
+        frame.vars().get(name).cloned()
+    }
+
+    fn define_in(&self, frame: &Scope, name: &str, binding: Binding) {
+        match self.layer {
+            Some(ref layer) if layer.id != frame.layer => {
+                layer.vars_mut().entry(frame.serial).or_default().insert(String::from(name), binding);
+            },
+            _ => { frame.vars_mut().insert(String::from(name), binding); }
+        }
+    }
+
+    /// The frames in scope, innermost first.
+    fn frames(&self) -> impl Iterator<Item = &Scope> {
+        let mut scope = Some(&*self.scope);
+        iter::from_fn(move || {
+            let frame = scope?;
+            scope = frame.parent.as_deref();
+            Some(frame)
+        })
+    }
+
+    /// Each name bound here and the value it has, from the innermost scope
+    /// binding it, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        self.names().into_iter().filter_map(|name| {
+            let val = self.get(&name).ok()?;
+            Some((name, val))
+        }).collect()
+    }
+
+    /// The names bound here, in any scope, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        for frame in self.frames() {
+            names.extend(frame.vars().keys().cloned());
+            let mut layer = self.layer.as_deref();
+            while let Some(current) = layer.filter(|layer| layer.id != frame.layer) {
+                if let Some(vars) = current.vars().get(&frame.serial) {
+                    names.extend(vars.keys().cloned());
+                }
+                layer = current.below.as_deref();
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Where `print` and friends write their output; stdout by default.
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut *self.out
+    }
+
+    pub fn set_output(&mut self, out: Box<dyn Write + Send>) {
+        self.out = out;
+    }
+
+    /// Where `read-line` reads from instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn BufRead + Send>) {
+        self.input = Some(input);
+    }
+
+    /// The next line of input without its line ending, or `None` at the
+    /// end.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match self.input {
+            Some(ref mut input) => input.read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Where `time` reports how long things took; stderr by default.
+    pub fn set_error_output(&mut self, err: Box<dyn Write + Send>) {
+        self.err = err;
+    }
+
+    /// Writes each call to `trace` as it's made, with its arguments
+    /// evaluated, and then the value it returns, indented by how deeply
+    /// the call is nested. `None` stops tracing.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write + Send>>) {
+        self.trace = trace;
+    }
+
+    /// Limits how deeply calls may nest before evaluation fails with
+    /// `EvalError::RecursionLimit`.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// How deeply calls may nest, `DEFAULT_MAX_DEPTH` unless it's been set.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Allows only `fuel` more expressions to be evaluated before evaluation
+    /// fails with `EvalError::OutOfFuel`. There is no limit by default.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    /// The fuel left, or `None` if evaluation is unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Makes `random` start over on the sequence for `seed`.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = seed;
+    }
+
+    /// Makes replacing a binding from `with_builtins` with `define` or `set`
+    /// fail with `EvalError::Invalid`, instead of leaving a warning for
+    /// `take_warnings`. Shadowing one in an inner scope is still allowed.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// The warnings since the last call, oldest first.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// Fails, or warns, if `binding` of `name` is about to be replaced and
+    /// is a builtin.
+    fn check_rebinding(&mut self, name: &str, binding: Option<&Binding>) -> Result<(), EvalError> {
+        if !binding.is_some_and(|binding| binding.builtin) {
+            return Ok(());
+        }
+        if self.strict {
+            return Err(EvalError::Invalid(format!("can't rebind builtin {}", name)));
+        }
+        self.warnings.push(format!("rebinding builtin {}", name));
+        Ok(())
+    }
+
+    /// A symbol name that hasn't been made before, for macros to bind
+    /// without capturing the names in the code they're given.
+    pub fn gensym(&self) -> String {
+        format!("g${}", self.gensyms.fetch_add(1, AtomicOrdering::Relaxed) + 1)
+    }
+
+    /// A number from 0 up to but not including `n`, which mustn't be 0.
+    /// The numbers come from SplitMix64, which is quick and good enough for
+    /// scripts but no use for anything that must be unpredictable.
+    pub fn random_below(&mut self, n: u64) -> u64 {
+        // Dropping the lowest numbers leaves a multiple of n to pick from,
+        // so none comes up more often than the others.
+        let skip = n.wrapping_neg() % n;
+        loop {
+            self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.random;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            if z >= skip {
+                return z % n;
+            }
+        }
+    }
+
+    fn burn_fuel(&mut self) -> Result<(), EvalError> {
+        match self.fuel {
+            Some(0) => Err(EvalError::OutOfFuel),
+            Some(ref mut fuel) => { *fuel -= 1; Ok(()) },
+            None => Ok(())
+        }
+    }
+
+    /// Binds `func` to `name` in the innermost scope, where it is called just
+    /// like a builtin.
+    pub fn register<F: Function + 'static>(&mut self, name: &str, func: F) {
+        let func = Value::Native(Arc::new(Native::new(name, func)));
+        self.define_in(&self.scope, name, Binding::var(func));
+    }
+
+    /// Binds a closure to `name`. Its arguments have already been evaluated
+    /// when it is called.
+    pub fn register_native<F>(&mut self, name: &str, func: F)
+        where F: Fn(&[Value], &mut Environment) -> Result<Value, EvalError> + Send + Sync + 'static
+    {
+        self.register(name, NativeFn(func));
+    }
+
+    /// Notes that `path` is being loaded until `finish_load`, failing if it
+    /// already is.
+    pub fn start_load(&mut self, path: PathBuf) -> Result<(), EvalError> {
+        if self.loading.contains(&path) {
+            return Err(EvalError::LoadCycle(path.display().to_string()));
+        }
+        self.loading.push(path);
+        Ok(())
+    }
+
+    pub fn finish_load(&mut self) {
+        self.loading.pop();
+    }
+
+    pub fn get(&self, name: &str) -> Result<Value, EvalError> {
+        match self.frames().find_map(|frame| self.lookup_in(frame, name)) {
+            Some(Binding {declared: true, ..}) => Err(EvalError::NotYetDefined(String::from(name))),
+            Some(binding) => Ok(binding.val),
+            None => Err(EvalError::UndefinedName(String::from(name), closest(name, self.names().iter())))
+        }
     }
 
-    pub fn get(&self, name: &str) -> Result<i64, EvalError> {
-        self.vars.get(name).map(|v| { *v }).ok_or(EvalError::UndefinedName(String::from(name)))
+    /// Assigns to the nearest binding of `name`, or creates one in the
+    /// innermost scope if it isn't bound yet. Fails if the binding is a
+    /// constant, or a builtin in strict mode.
+    pub fn set(&mut self, name: &str, val: Value) -> Result<Value, EvalError> {
+        let found = self.frames().find_map(|frame| Some((frame.serial, self.lookup_in(frame, name)?)));
+        if let Some((_, Binding {constant: true, ..})) = found {
+            return Err(EvalError::Immutable(String::from(name)));
+        }
+        self.check_rebinding(name, found.as_ref().map(|(_, binding)| binding))?;
+        let serial = found.map(|(serial, _)| serial);
+        let frame = self.frames().find(|frame| Some(frame.serial) == serial).unwrap_or(&self.scope);
+        self.define_in(frame, name, Binding::var(val.clone()));
+        Ok(val)
+    }
+
+    /// Binds `name` in the innermost scope, shadowing any outer binding.
+    /// Fails if it replaces a builtin in strict mode.
+    pub fn define(&mut self, name: &str, val: Value) -> Result<Value, EvalError> {
+        let current = self.lookup_in(&self.scope, name);
+        self.check_rebinding(name, current.as_ref())?;
+        self.define_in(&self.scope, name, Binding::var(val.clone()));
+        Ok(val)
+    }
+
+    /// Binds `name` like `define`, but so that `set` can't change it.
+    pub fn define_constant(&mut self, name: &str, val: Value) -> Result<Value, EvalError> {
+        let current = self.lookup_in(&self.scope, name);
+        self.check_rebinding(name, current.as_ref())?;
+        self.define_in(&self.scope, name, Binding {val: val.clone(), constant: true, builtin: false, declared: false});
+        Ok(val)
+    }
+
+    /// Removes the binding of `name` from the innermost scope, uncovering
+    /// any outer one, and returns whether there was one. In a fork, only
+    /// bindings the fork made there are removed.
+    pub fn unset(&mut self, name: &str) -> bool {
+        match self.layer {
+            Some(ref layer) if layer.id != self.scope.layer =>
+                layer.vars_mut().get_mut(&self.scope.serial).is_some_and(|vars| vars.remove(name).is_some()),
+            _ => self.scope.vars_mut().remove(name).is_some()
+        }
+    }
+
+    /// Binds `name` in the innermost scope to a placeholder, unless it is
+    /// bound already, so that looking it up fails with `NotYetDefined`
+    /// until something defines or assigns it.
+    pub fn declare(&mut self, name: &str) {
+        if self.frames().all(|frame| self.lookup_in(frame, name).is_none()) {
+            self.define_placeholder(name);
+        }
     }
 
-    pub fn set(&mut self, name: &str, val: i64) -> i64 {
-        self.vars.insert(String::from(name), val);
-        val
+    /// Binds `name` in the innermost scope to a placeholder whether or not
+    /// it is bound already.
+    fn define_placeholder(&mut self, name: &str) {
+        self.define_in(&self.scope, name, Binding {declared: true, ..Binding::var(Value::Unit)});
     }
 }
 
 
-pub trait Expression : fmt::Debug {
-    fn eval(&self, &mut Environment) -> Result<i64, EvalError>;
+#[derive(Debug, PartialEq)]
+pub struct Lambda {
+    pub params: Vec<String>,
+    /// What the last parameters are bound to when a call leaves them out,
+    /// evaluated in the new scope with the parameters before them bound.
+    pub defaults: Vec<Ast>,
+    /// Parameters given as `:name value` after the others, in any order, and
+    /// what they are bound to when left out, if they may be.
+    pub keys: Vec<(String, Option<Ast>)>,
+    pub body: Vec<Ast>
+}
 
-    fn lvalue(&self, &mut Environment) -> Result<&str, EvalError>;
+/// A lambda together with the scope it was evaluated in.
+pub struct Closure {
+    lambda: Arc<Lambda>,
+    scope: Arc<Scope>
 }
 
-#[derive(Debug)]
-pub struct Literal {
-    val: i64,
+impl Closure {
+    pub fn new(lambda: Arc<Lambda>, env: &Environment) -> Closure {
+        Closure {lambda, scope: env.scope.clone()}
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.lambda.params
+    }
+
+    fn call(&self, mut args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+        let saved = env.scope.clone();
+        // Closures called in tail position come back here to be called in
+        // turn instead of nesting, so loops written as recursion don't grow
+        // the stack.
+        let mut next: Option<Arc<Closure>> = None;
+        let result = loop {
+            let closure = next.as_deref().unwrap_or(self);
+            match closure.enter(args, env).and_then(|()| eval_tail_body(&closure.lambda.body, env)) {
+                Ok(Tail::Call(func, rest)) => { next = Some(func); args = rest; },
+                Ok(Tail::Value(val)) => break Ok(val),
+                Err(err) => break Err(err)
+            }
+        };
+        env.scope = saved;
+        result
+    }
+
+    /// Makes a new scope with the parameters bound to `args`, or their
+    /// defaults, current.
+    fn enter(&self, mut args: Vec<Value>, env: &mut Environment) -> Result<(), EvalError> {
+        let keyed = if self.lambda.keys.is_empty() {
+            vec![]
+        } else {
+            let first = args.iter().position(|arg| arg.as_keyword().is_some()).unwrap_or(args.len());
+            args.split_off(first)
+        };
+        let params = self.params();
+        let defaults = &self.lambda.defaults;
+        let required = params.len() - defaults.len();
+        if args.len() < required || args.len() > params.len() {
+            let takes = if defaults.is_empty() { params.len().to_string() } else { format!("{} to {}", required, params.len()) };
+            return Err(EvalError::Arity(format!("lambda takes {} arguments, got {}", takes, args.len())));
+        }
+        let given = args.len();
+        let scope = Arc::new(Scope::child(self.scope.clone(), env));
+        for (param, arg) in params.iter().zip(args) {
+            scope.define(param, arg);
+        }
+        env.scope = scope;
+        for (param, default) in params[required..].iter().zip(defaults).skip(given - required) {
+            let val = eval(default, env)?;
+            env.scope.define(param, val);
+        }
+        if !self.lambda.keys.is_empty() {
+            self.bind_keys(keyed, env)?;
+        }
+        Ok(())
+    }
+
+    /// Binds the keyword parameters to the values after their keywords in
+    /// `args`, or their defaults.
+    fn bind_keys(&self, args: Vec<Value>, env: &mut Environment) -> Result<(), EvalError> {
+        let keys = &self.lambda.keys;
+        let mut given = vec![None; keys.len()];
+        let mut args = args.into_iter();
+        while let Some(key) = args.next() {
+            let i = match key.as_keyword() {
+                Some(name) => keys.iter().position(|(key, _)| key == name)
+                    .ok_or_else(|| EvalError::Invalid(format!("unknown keyword {}", key)))?,
+                None => return Err(EvalError::Invalid(format!("expected keyword, got {}", key)))
+            };
+            if given[i].is_some() {
+                return Err(EvalError::Invalid(format!("keyword {} given twice", key)));
+            }
+            given[i] = Some(args.next().ok_or_else(|| EvalError::Arity(format!("no value after {}", key)))?);
+        }
+        for ((name, default), val) in keys.iter().zip(given) {
+            let val = match (val, default) {
+                (Some(val), _) => val,
+                (None, Some(default)) => eval(default, env)?,
+                (None, None) => return Err(EvalError::Arity(format!("missing keyword :{}", name)))
+            };
+            env.scope.define(name, val);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Closure").field("params", &self.lambda.params).finish()
+    }
 }
 
-impl Literal {
-    pub fn new(val: i64) -> Literal {
-        Literal {val: val}
+/// Closures are only equal to themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        ptr::eq(self, other)
     }
 }
 
-impl Expression for Literal {
-    fn eval(&self, env: &mut Environment) -> Result<i64, EvalError> {
-        Ok(self.val)
+/// An expression whose evaluation is put off until it is forced, and its
+/// value once it has been.
+pub struct Promise {
+    expr: Arc<Ast>,
+    scope: Arc<Scope>,
+    value: Mutex<Option<Value>>
+}
+
+impl Promise {
+    pub fn new(expr: Arc<Ast>, env: &Environment) -> Promise {
+        Promise {expr, scope: env.scope.clone(), value: Mutex::new(None)}
+    }
+
+    /// Evaluates the expression the first time, and after that returns the
+    /// value it had.
+    pub fn force(&self, env: &mut Environment) -> Result<Value, EvalError> {
+        if let Some(val) = self.cached() {
+            return Ok(val);
+        }
+        let saved = mem::replace(&mut env.scope, self.scope.clone());
+        let result = eval(&self.expr, env);
+        env.scope = saved;
+        let val = result?;
+        // Forcing the promise from inside its own expression may have
+        // given it a value already, which then stands. The same goes for
+        // another thread forcing it at the same time.
+        let mut value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(value.get_or_insert(val).clone())
     }
 
-    fn lvalue(&self, env: &mut Environment) -> Result<&str, EvalError> {
-        Err(EvalError::UndefinedName(format!("{:?}", self.val)))
+    fn cached(&self) -> Option<Value> {
+        self.value.lock().unwrap_or_else(PoisonError::into_inner).clone()
     }
 }
 
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Promise").field("expr", &self.expr).field("value", &self.value).finish()
+    }
+}
 
-#[derive(Debug)]
-pub struct Call {
-    function: Box<Function>,
-    args: Vec<Box<Expression>>,
+/// Promises are only equal to themselves.
+impl PartialEq for Promise {
+    fn eq(&self, other: &Promise) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// Something callable from lisp that is implemented in Rust, for extending
+/// the language without touching the reader or the builtin table.
+pub trait Function: Send + Sync {
+    fn call(&self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError>;
 }
 
-impl Call {
-    pub fn new(function: Box<Function>, args: Vec<Box<Expression>>) -> Call {
-        Call {function: function, args:args}
+/// Adapts a Rust closure over already evaluated arguments to `Function`.
+struct NativeFn<F>(F);
+
+impl<F> Function for NativeFn<F> where F: Fn(&[Value], &mut Environment) -> Result<Value, EvalError> + Send + Sync {
+    fn call(&self, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+        (self.0)(&args, env)
     }
 }
 
-impl Expression for Call {
-    fn eval(&self, env: &mut Environment) -> Result<i64, EvalError> {
-        self.function.call(&self.args, env)
+/// A host `Function` bound to a name.
+pub struct Native {
+    name: String,
+    func: Box<dyn Function>
+}
+
+impl Native {
+    pub fn new<F: Function + 'static>(name: &str, func: F) -> Native {
+        Native {name: String::from(name), func: Box::new(func)}
     }
 
-    fn lvalue(&self, env: &mut Environment) -> Result<&str, EvalError> {
-        Err(EvalError::UndefinedName(format!("({:?} {:?})", self.function, self.args)))
+    pub fn name(&self) -> &str {
+        &self.name
     }
 }
 
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Native").field("name", &self.name).finish()
+    }
+}
 
-#[derive(Debug)]
-pub struct If;
+/// Like closures, native functions are only equal to themselves.
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// Where a call and each of its arguments start in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub call: Position,
+    pub args: Vec<Position>
+}
 
-impl If {
-    pub fn new() -> If {
-        If
+impl Span {
+    pub fn new(call: Position) -> Span {
+        Span {call, args: vec![]}
     }
 }
 
-impl Function for If {
-    fn call(&self, args: &Vec<Box<Expression>>, env: &mut Environment) -> Result<i64, EvalError> {
-        let result = try!(args[0].eval(env));
-        if result != 0 {
-            args[1].eval(env)
-        } else {
-            args[2].eval(env)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ast {
+    Literal(Value),
+    Reference(String),
+    Call { func: Box<Ast>, args: Vec<Ast>, span: Option<Box<Span>> },
+    If(Box<Ast>, Box<Ast>, Box<Ast>),
+    Set(String, Box<Ast>),
+    Define(String, Box<Ast>),
+    Begin(Vec<Ast>),
+    While(Box<Ast>, Vec<Ast>),
+    /// Clauses of a test and a body; `else` is read as a `#t` test.
+    Cond(Vec<(Ast, Vec<Ast>)>),
+    And(Vec<Ast>),
+    Or(Vec<Ast>),
+    Lambda(Arc<Lambda>),
+    /// Evaluates the body, and if that fails the handler, with the error
+    /// message bound to the name.
+    Try(Box<Ast>, String, Vec<Ast>),
+    /// Evaluates the body, then the cleanup whether or not the body failed,
+    /// and returns what the body did. An error from the cleanup takes the
+    /// place of the body's value, but not of its error.
+    Finally(Box<Ast>, Box<Ast>),
+    /// Evaluates the body with the names bound in a new scope.
+    Let(LetKind, Vec<(String, Ast)>, Vec<Ast>),
+    /// Evaluates the body of the first clause whose keys include the value
+    /// of the expression; `else` is read as `None`, which matches anything.
+    Case(Box<Ast>, Vec<(Option<Vec<Value>>, Vec<Ast>)>),
+    /// Makes a promise to evaluate the expression when forced.
+    Delay(Arc<Ast>),
+    /// Evaluates the expression and reports how long it took.
+    Time(Box<Ast>),
+}
+
+/// Where the values bound by a `let` form are evaluated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LetKind {
+    /// `let`, outside the new scope.
+    Parallel,
+    /// `let*`, in the new scope, so each sees the bindings before it.
+    Sequential,
+    /// `letrec`, in the new scope with all the names already bound, so
+    /// that functions can refer to each other.
+    Recursive
+}
+
+impl LetKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            LetKind::Parallel => "let",
+            LetKind::Sequential => "let*",
+            LetKind::Recursive => "letrec"
         }
     }
 }
 
+impl Ast {
+    /// Whether evaluating this in `env` can't change anything or depend on
+    /// anything that changes, as far as can be told without evaluating it:
+    /// it assigns, defines and times nothing, and calls only pure builtins.
+    pub fn is_pure(&self, env: &Environment) -> bool {
+        is_pure(self, env, &mut vec![])
+    }
+}
 
-#[derive(Debug)]
-pub struct Reference {
-    name: String
+/// Whether `expr` is pure, where `locals` are the names bound around it,
+/// which could be bound to anything.
+fn is_pure<'a>(expr: &'a Ast, env: &Environment, locals: &mut Vec<&'a str>) -> bool {
+    fn all<'a>(exprs: &'a [Ast], env: &Environment, locals: &mut Vec<&'a str>) -> bool {
+        exprs.iter().all(|expr| is_pure(expr, env, locals))
+    }
+    match *expr {
+        Ast::Literal(_) | Ast::Reference(_) | Ast::Lambda(_) | Ast::Delay(_) => true,
+        Ast::Set(..) | Ast::Define(..) | Ast::Time(_) => false,
+        Ast::Call {ref func, ref args, ..} => {
+            let func = match **func {
+                Ast::Literal(Value::Builtin(func)) => Some(func),
+                Ast::Reference(ref name) if !locals.contains(&name.as_str()) => match env.get(name) {
+                    Ok(Value::Builtin(func)) => Some(func),
+                    _ => None
+                },
+                _ => None
+            };
+            func.is_some_and(FuncId::is_pure) && all(args, env, locals)
+        },
+        Ast::If(ref cond, ref then, ref otherwise) =>
+            is_pure(cond, env, locals) && is_pure(then, env, locals) && is_pure(otherwise, env, locals),
+        Ast::Begin(ref exprs) | Ast::And(ref exprs) | Ast::Or(ref exprs) => all(exprs, env, locals),
+        Ast::While(ref cond, ref body) => is_pure(cond, env, locals) && all(body, env, locals),
+        Ast::Cond(ref clauses) =>
+            clauses.iter().all(|(test, body)| is_pure(test, env, locals) && all(body, env, locals)),
+        Ast::Case(ref key, ref clauses) =>
+            is_pure(key, env, locals) && clauses.iter().all(|(_, body)| all(body, env, locals)),
+        Ast::Try(ref body, ref name, ref handler) => {
+            if !is_pure(body, env, locals) {
+                return false;
+            }
+            locals.push(name);
+            let pure = all(handler, env, locals);
+            locals.pop();
+            pure
+        },
+        Ast::Finally(ref body, ref cleanup) => is_pure(body, env, locals) && is_pure(cleanup, env, locals),
+        // As if the names were bound for the values too, which is true of
+        // some kinds of let and only makes the others look less pure.
+        Ast::Let(_, ref bindings, ref body) => {
+            let outside = locals.len();
+            locals.extend(bindings.iter().map(|(name, _)| name.as_str()));
+            let pure = bindings.iter().all(|(_, val)| is_pure(val, env, locals)) && all(body, env, locals);
+            locals.truncate(outside);
+            pure
+        }
+    }
 }
 
-impl Reference {
-    pub fn new(name: &str) -> Reference {
-        Reference { name: String::from(name) }
+/// Writes `items` separated by spaces, each preceded by a space.
+fn write_spaced(f: &mut fmt::Formatter, items: &[Ast]) -> fmt::Result {
+    for item in items {
+        write!(f, " {}", item)?;
     }
+    Ok(())
 }
 
-impl Expression for Reference {
-    fn eval(&self, env: &mut Environment) -> Result<i64, EvalError> {
-        env.get(&self.name)
+/// Writes the source text the expression could have been read from.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ast::Literal(ref val @ Value::Symbol(_)) |
+            Ast::Literal(ref val @ Value::Nil) |
+            Ast::Literal(ref val @ Value::Pair(_)) => write!(f, "'{}", val),
+            // Builtins only appear in the tree as what some syntax stands
+            // for, where they were named.
+            Ast::Literal(Value::Builtin(func)) => write!(f, "{}", func.name()),
+            Ast::Literal(ref val) => write!(f, "{}", val),
+            Ast::Reference(ref name) => write!(f, "{}", name),
+            Ast::Call { ref func, ref args, .. } => {
+                write!(f, "({}", func)?;
+                write_spaced(f, args)?;
+                write!(f, ")")
+            },
+            Ast::If(ref cond, ref then, ref otherwise) => write!(f, "(if {} {} {})", cond, then, otherwise),
+            Ast::Set(ref name, ref val) => write!(f, "(set {} {})", name, val),
+            Ast::Define(ref name, ref val) => write!(f, "(define {} {})", name, val),
+            Ast::Begin(ref body) => {
+                write!(f, "(begin")?;
+                write_spaced(f, body)?;
+                write!(f, ")")
+            },
+            Ast::While(ref cond, ref body) => {
+                write!(f, "(while {}", cond)?;
+                write_spaced(f, body)?;
+                write!(f, ")")
+            },
+            Ast::Lambda(ref lambda) => {
+                let required = lambda.params.len() - lambda.defaults.len();
+                let defaulted = lambda.params[required..].iter().zip(&lambda.defaults)
+                    .map(|(param, default)| format!("({} {})", param, default));
+                let keys = lambda.keys.iter().map(|(key, default)| match *default {
+                    Some(ref default) => format!("(:{} {})", key, default),
+                    None => format!(":{}", key)
+                });
+                let params: Vec<String> = lambda.params[..required].iter().cloned().chain(defaulted).chain(keys).collect();
+                write!(f, "(lambda ({})", params.join(" "))?;
+                write_spaced(f, &lambda.body)?;
+                write!(f, ")")
+            },
+            Ast::Cond(ref clauses) => {
+                write!(f, "(cond")?;
+                for (test, body) in clauses {
+                    write!(f, " ({}", test)?;
+                    write_spaced(f, body)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            },
+            Ast::And(ref args) => {
+                write!(f, "(and")?;
+                write_spaced(f, args)?;
+                write!(f, ")")
+            },
+            Ast::Or(ref args) => {
+                write!(f, "(or")?;
+                write_spaced(f, args)?;
+                write!(f, ")")
+            },
+            Ast::Try(ref body, ref name, ref handler) => {
+                write!(f, "(try {} (catch {}", body, name)?;
+                write_spaced(f, handler)?;
+                write!(f, "))")
+            },
+            Ast::Finally(ref body, ref cleanup) => write!(f, "(finally {} {})", body, cleanup),
+            Ast::Let(kind, ref bindings, ref body) => {
+                write!(f, "({} (", kind.name())?;
+                for (i, (name, val)) in bindings.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(f, "{}({} {})", sep, name, val)?;
+                }
+                write!(f, ")")?;
+                write_spaced(f, body)?;
+                write!(f, ")")
+            },
+            Ast::Delay(ref expr) => write!(f, "(delay {})", expr),
+            Ast::Time(ref expr) => write!(f, "(time {})", expr),
+            Ast::Case(ref key, ref clauses) => {
+                write!(f, "(case {}", key)?;
+                for (keys, body) in clauses {
+                    match *keys {
+                        Some(ref keys) => write!(f, " ({}", Value::list(keys.clone()))?,
+                        None => write!(f, " (else")?
+                    }
+                    write_spaced(f, body)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+        }
     }
+}
+
+pub fn eval(ast: &Ast, env: &mut Environment) -> Result<Value, EvalError> {
+    env.burn_fuel()?;
+    match *ast {
+        Ast::Literal(ref val) => Ok(val.clone()),
+        Ast::Reference(ref name) => env.get(name),
+        Ast::Call { ref func, ref args, ref span } => {
+            if env.depth >= env.max_depth {
+                return Err(EvalError::RecursionLimit(env.max_depth));
+            }
+            env.depth += 1;
+            let result = eval_call(func, args, span.as_deref(), env);
+            env.depth -= 1;
+            result
+        },
+        Ast::If(ref cond, ref then, ref otherwise) => {
+            if eval(cond, env)?.is_truthy() {
+                eval(then, env)
+            } else {
+                eval(otherwise, env)
+            }
+        },
+        Ast::Set(ref name, ref val) => {
+            let val = eval(val, env)?;
+            env.set(name, val)?;
+            Ok(Value::Unit)
+        },
+        Ast::Define(ref name, ref val) => {
+            let val = eval(val, env)?;
+            env.define(name, val)?;
+            Ok(Value::Unit)
+        },
+        Ast::Begin(ref body) => {
+            if body.is_empty() {
+                return Ok(Value::Unit);
+            }
+            eval_body(body, env)
+        },
+        Ast::While(ref cond, ref body) => eval_while(cond, body, env),
+        Ast::Lambda(ref lambda) => Ok(Value::Closure(Arc::new(Closure::new(lambda.clone(), env)))),
+        Ast::Cond(ref clauses) => eval_cond(clauses, env),
+        Ast::And(ref args) => eval_and_or(args, false, env),
+        Ast::Or(ref args) => eval_and_or(args, true, env),
+        Ast::Try(ref body, ref name, ref handler) => eval_try(body, name, handler, env),
+        Ast::Finally(ref body, ref cleanup) => eval_finally(body, cleanup, env),
+        Ast::Let(kind, ref bindings, ref body) => eval_let(kind, bindings, body, env),
+        Ast::Case(ref key, ref clauses) => eval_case(key, clauses, env),
+        Ast::Delay(ref expr) => Ok(Value::Promise(Arc::new(Promise::new(expr.clone(), env)))),
+        Ast::Time(ref expr) => eval_time(expr, env)
+    }
+}
+
+fn eval_time(expr: &Ast, env: &mut Environment) -> Result<Value, EvalError> {
+    let start = Instant::now();
+    let val = eval(expr, env)?;
+    writeln!(env.err, "time: {:?}", start.elapsed())?;
+    Ok(val)
+}
+
+// The forms below live outside `eval` to keep its stack frame, which every
+// level of recursion pays for, small.
 
+fn eval_while(cond: &Ast, body: &[Ast], env: &mut Environment) -> Result<Value, EvalError> {
+    while eval(cond, env)?.is_truthy() {
+        for ast in body {
+            eval(ast, env)?;
+        }
+    }
+    Ok(Value::Unit)
+}
 
-    fn lvalue(&self, env: &mut Environment) -> Result<&str, EvalError> {
-        Ok(&*self.name)
+fn eval_cond(clauses: &[(Ast, Vec<Ast>)], env: &mut Environment) -> Result<Value, EvalError> {
+    for (test, body) in clauses {
+        let val = eval(test, env)?;
+        if val.is_truthy() {
+            return if body.is_empty() { Ok(val) } else { eval_body(body, env) };
+        }
     }
+    Ok(Value::Unit)
 }
 
+fn eval_case(key: &Ast, clauses: &[(Option<Vec<Value>>, Vec<Ast>)], env: &mut Environment)
+    -> Result<Value, EvalError>
+{
+    let val = eval(key, env)?;
+    for (keys, body) in clauses {
+        if keys.as_ref().is_none_or(|keys| keys.contains(&val)) {
+            return eval_body(body, env);
+        }
+    }
+    Ok(Value::Unit)
+}
 
-#[derive(Debug)]
-pub struct Set;
+/// `and` stops at the first false value and `or` at the first true one;
+/// either way the last value evaluated is the result.
+fn eval_and_or(args: &[Ast], stop_at: bool, env: &mut Environment) -> Result<Value, EvalError> {
+    let mut val = Value::Bool(!stop_at);
+    for arg in args {
+        val = eval(arg, env)?;
+        if val.is_truthy() == stop_at {
+            break;
+        }
+    }
+    Ok(val)
+}
+
+fn eval_try(body: &Ast, name: &str, handler: &[Ast], env: &mut Environment) -> Result<Value, EvalError> {
+    match eval(body, env) {
+        Ok(val) => Ok(val),
+        Err(err) => {
+            let scope = Scope::child(env.scope.clone(), env);
+            scope.define(name, Value::Str(err.to_string()));
+            let saved = mem::replace(&mut env.scope, Arc::new(scope));
+            let result = eval_body(handler, env);
+            env.scope = saved;
+            result
+        }
+    }
+}
+
+fn eval_finally(body: &Ast, cleanup: &Ast, env: &mut Environment) -> Result<Value, EvalError> {
+    let result = eval(body, env);
+    let cleaned = eval(cleanup, env);
+    result.and_then(|val| cleaned.map(|_| val))
+}
+
+fn eval_let(kind: LetKind, bindings: &[(String, Ast)], body: &[Ast], env: &mut Environment)
+    -> Result<Value, EvalError>
+{
+    let scope = let_scope(kind, bindings, env)?;
+    let saved = mem::replace(&mut env.scope, scope);
+    let result = bind_in_let_scope(kind, bindings, env).and_then(|()| eval_body(body, env));
+    env.scope = saved;
+    result
+}
+
+/// The new scope of a `let`, with the bindings of a plain one, whose
+/// values are evaluated outside it, already made.
+fn let_scope(kind: LetKind, bindings: &[(String, Ast)], env: &mut Environment) -> Result<Arc<Scope>, EvalError> {
+    let scope = Arc::new(Scope::child(env.scope.clone(), env));
+    if kind == LetKind::Parallel {
+        for (name, val) in bindings {
+            scope.define(name, eval(val, env)?);
+        }
+    }
+    Ok(scope)
+}
+
+/// Makes the bindings of a `let*` or `letrec` in its new scope, which is
+/// current.
+fn bind_in_let_scope(kind: LetKind, bindings: &[(String, Ast)], env: &mut Environment) -> Result<(), EvalError> {
+    if kind == LetKind::Recursive {
+        for (name, _) in bindings {
+            env.define_placeholder(name);
+        }
+    }
+    if kind != LetKind::Parallel {
+        for (name, val) in bindings {
+            let val = eval(val, env)?;
+            env.define(name, val)?;
+        }
+    }
+    Ok(())
+}
+
+fn eval_call(expr: &Ast, args: &[Ast], span: Option<&Span>, env: &mut Environment) -> Result<Value, EvalError> {
+    let func = eval(expr, env)?;
+    if let Value::Macro(ref transformer) = func {
+        return eval_macro(transformer, expr, args, env);
+    }
+    let mut vals = Vec::with_capacity(args.len());
+    for arg in args {
+        vals.push(eval(arg, env)?);
+    }
+    let result = if env.trace.is_some() { trace_call(expr, &func, vals, env) } else { apply_indexed(&func, vals, env) };
+    result.map_err(|err| locate(err, &func, span))
+}
+
+/// Points an error that a builtin raised at the argument it says it's
+/// about, or else at the call.
+fn locate(err: EvalError, func: &Value, span: Option<&Span>) -> EvalError {
+    let span = match span {
+        Some(span) if matches!(*func, Value::Builtin(_) | Value::Native(_)) => span,
+        _ => return err.unindexed()
+    };
+    match err {
+        EvalError::Arg(index, err) => EvalError::At(span.args.get(index).copied().unwrap_or(span.call), err),
+        EvalError::Arity(_) | EvalError::TypeMismatch(_) | EvalError::Overflow(_) | EvalError::Invalid(_) |
+        EvalError::Index(_) | EvalError::NoSuchKey(_) => EvalError::At(span.call, Box::new(err)),
+        err => err
+    }
+}
+
+/// Evaluates what a macro expands the call to.
+fn eval_macro(transformer: &Value, expr: &Ast, args: &[Ast], env: &mut Environment) -> Result<Value, EvalError> {
+    // The macro takes and returns forms as data. Going through source text
+    // lets the reader turn code into data and back, special forms and all.
+    let mut call = format!("({}", expr);
+    for arg in args {
+        call = format!("{} {}", call, arg);
+    }
+    call.push(')');
+    let form = read::read_datum_str(&call).map_err(|err| EvalError::Expansion(call, Box::new(err)))?;
+    let expansion = apply(transformer, vec![form], env)?.to_string();
+    let expanded = read::read_str(&expansion).map_err(|err| EvalError::Expansion(expansion, Box::new(err)))?;
+    eval(&expanded, env)
+}
+
+fn trace_call(expr: &Ast, func: &Value, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    let indent = "  ".repeat(env.depth - 1);
+    let mut call = format!("{}({}", indent, expr);
+    for arg in &args {
+        call = format!("{} {}", call, arg);
+    }
+    if let Some(ref mut trace) = env.trace {
+        writeln!(trace, "{})", call)?;
+    }
+    let result = apply_indexed(func, args, env);
+    if let Some(ref mut trace) = env.trace {
+        match result {
+            Ok(ref val) => writeln!(trace, "{}=> {}", indent, val)?,
+            Err(ref err) => writeln!(trace, "{}error: {}", indent, err)?
+        }
+    }
+    result
+}
+
+fn eval_body(body: &[Ast], env: &mut Environment) -> Result<Value, EvalError> {
+    let (last, init) = body.split_last().expect("Empty body");
+    for ast in init {
+        eval(ast, env)?;
+    }
+    eval(last, env)
+}
+
+/// What an expression in tail position comes to: a value, or a closure
+/// still to be called by the caller.
+enum Tail {
+    Value(Value),
+    Call(Arc<Closure>, Vec<Value>)
+}
+
+fn eval_tail_body(body: &[Ast], env: &mut Environment) -> Result<Tail, EvalError> {
+    let (last, init) = body.split_last().expect("Empty body");
+    for ast in init {
+        eval(ast, env)?;
+    }
+    eval_tail(last, env)
+}
+
+/// Evaluates an expression in tail position, leaving a call to a closure,
+/// directly or in the tail position of `if`, `begin`, `cond`, `case`,
+/// `and`, `or` or a `let`, to the caller. Traced calls are made where they
+/// are, to be traced.
+fn eval_tail(ast: &Ast, env: &mut Environment) -> Result<Tail, EvalError> {
+    match *ast {
+        Ast::Call { ref func, ref args, ref span } if env.trace.is_none() => {
+            env.burn_fuel()?;
+            if env.depth >= env.max_depth {
+                return Err(EvalError::RecursionLimit(env.max_depth));
+            }
+            env.depth += 1;
+            let result = eval_tail_call(func, args, span.as_deref(), env);
+            env.depth -= 1;
+            result
+        },
+        Ast::If(ref cond, ref then, ref otherwise) => {
+            env.burn_fuel()?;
+            eval_tail(if eval(cond, env)?.is_truthy() { then } else { otherwise }, env)
+        },
+        Ast::Begin(ref body) if !body.is_empty() => {
+            env.burn_fuel()?;
+            eval_tail_body(body, env)
+        },
+        Ast::Cond(_) | Ast::Case(..) | Ast::Let(..) => eval_tail_form(ast, env),
+        Ast::And(ref args) | Ast::Or(ref args) if !args.is_empty() => eval_tail_form(ast, env),
+        _ => eval(ast, env).map(Tail::Value)
+    }
+}
+
+/// The rest of the forms with a tail position, kept out of `eval_tail` for
+/// the same reason the forms below are kept out of `eval`.
+fn eval_tail_form(ast: &Ast, env: &mut Environment) -> Result<Tail, EvalError> {
+    env.burn_fuel()?;
+    match *ast {
+        Ast::Cond(ref clauses) => eval_tail_cond(clauses, env),
+        Ast::Case(ref key, ref clauses) => eval_tail_case(key, clauses, env),
+        Ast::And(ref args) => eval_tail_and_or(args, false, env),
+        Ast::Or(ref args) => eval_tail_and_or(args, true, env),
+        Ast::Let(kind, ref bindings, ref body) => eval_tail_let(kind, bindings, body, env),
+        _ => unreachable!("Not a tail form")
+    }
+}
+
+fn eval_tail_cond(clauses: &[(Ast, Vec<Ast>)], env: &mut Environment) -> Result<Tail, EvalError> {
+    for (test, body) in clauses {
+        let val = eval(test, env)?;
+        if val.is_truthy() {
+            return if body.is_empty() { Ok(Tail::Value(val)) } else { eval_tail_body(body, env) };
+        }
+    }
+    Ok(Tail::Value(Value::Unit))
+}
+
+fn eval_tail_case(key: &Ast, clauses: &[(Option<Vec<Value>>, Vec<Ast>)], env: &mut Environment)
+    -> Result<Tail, EvalError>
+{
+    let val = eval(key, env)?;
+    for (keys, body) in clauses {
+        if keys.as_ref().is_none_or(|keys| keys.contains(&val)) {
+            return eval_tail_body(body, env);
+        }
+    }
+    Ok(Tail::Value(Value::Unit))
+}
+
+/// `and` or `or` with its last argument in tail position, which is only
+/// reached if none before it stops the evaluation.
+fn eval_tail_and_or(args: &[Ast], stop_at: bool, env: &mut Environment) -> Result<Tail, EvalError> {
+    let (last, init) = args.split_last().expect("No arguments");
+    for arg in init {
+        let val = eval(arg, env)?;
+        if val.is_truthy() == stop_at {
+            return Ok(Tail::Value(val));
+        }
+    }
+    eval_tail(last, env)
+}
+
+/// A `let` with its body in tail position. A call left to the caller has
+/// its arguments evaluated already, so the scope can be left before it.
+fn eval_tail_let(kind: LetKind, bindings: &[(String, Ast)], body: &[Ast], env: &mut Environment)
+    -> Result<Tail, EvalError>
+{
+    let scope = let_scope(kind, bindings, env)?;
+    let saved = mem::replace(&mut env.scope, scope);
+    let result = bind_in_let_scope(kind, bindings, env).and_then(|()| eval_tail_body(body, env));
+    env.scope = saved;
+    result
+}
+
+fn eval_tail_call(expr: &Ast, args: &[Ast], span: Option<&Span>, env: &mut Environment) -> Result<Tail, EvalError> {
+    let func = eval(expr, env)?;
+    if let Value::Macro(ref transformer) = func {
+        return eval_macro(transformer, expr, args, env).map(Tail::Value);
+    }
+    let mut vals = Vec::with_capacity(args.len());
+    for arg in args {
+        vals.push(eval(arg, env)?);
+    }
+    match func {
+        Value::Closure(closure) => Ok(Tail::Call(closure, vals)),
+        func => apply_indexed(&func, vals, env).map(Tail::Value).map_err(|err| locate(err, &func, span))
+    }
+}
 
-impl Set {
-    pub fn new() -> Set {
-        Set
+/// Calls a function value with already evaluated arguments.
+pub fn apply(func: &Value, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match *func {
+        Value::Builtin(func) => func.call(args, env),
+        Value::Closure(ref closure) => closure.call(args, env),
+        Value::Native(ref native) => native.func.call(args, env).map_err(EvalError::unindexed),
+        ref other => Err(EvalError::TypeMismatch(format!("expected function, got {}", other)))
     }
 }
 
-impl Function for Set {
-    fn call(&self, args: &Vec<Box<Expression>>, env: &mut Environment) -> Result<i64, EvalError> {
-        let lvalue = try!(args[0].lvalue(env));
-        let val = try!(args[1].eval(env));
-        Ok(env.set(lvalue.as_ref(), val))
+/// Calls a function value as `apply` does, but leaves errors from builtins
+/// with the index of the argument they're about.
+fn apply_indexed(func: &Value, args: Vec<Value>, env: &mut Environment) -> Result<Value, EvalError> {
+    match *func {
+        Value::Builtin(func) => func.call_indexed(args, env),
+        Value::Native(ref native) => native.func.call(args, env),
+        _ => apply(func, args, env)
     }
 }
 
 
+
 #[cfg(test)]
 mod tests {
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
     use super::Environment;
-    use super::Set;
-    use super::Function;
-    use super::Add;
-    use super::If;
-    use super::Call;
-    use super::Expression;
-    use super::Literal;
-    use super::Reference;
+    use super::EvalError;
+    use super::Ast;
+    use super::eval;
+    use super::Lambda;
+    use super::levenshtein;
+    use lisp::builtins::FuncId;
+    use lisp::value::Value;
+
+    fn lit(val: i64) -> Ast {
+        Ast::Literal(Value::Int(val))
+    }
+
+    fn reference(name: &str) -> Ast {
+        Ast::Reference(String::from(name))
+    }
+
+    fn add(args: Vec<Ast>) -> Ast {
+        Ast::Call {func: Box::new(reference("+")), args, span: None}
+    }
+
+    #[test]
+    fn test_new_environment_is_empty() {
+        let env = Environment::new();
+        assert!(env.get("+").is_err());
+    }
+
+    #[test]
+    fn test_builtins_are_values() {
+        let mut env = Environment::with_builtins();
+        let plus = env.get("+").unwrap();
+        assert_eq!(Value::Builtin(FuncId::Add), plus);
+        assert_eq!(Value::Int(7), super::apply(&plus, vec![Value::Int(3), Value::Int(4)], &mut env).unwrap());
+        assert_eq!(Value::Int(24), eval(&Ast::Call {func: Box::new(reference("*")), args: vec![lit(2), lit(3), lit(4)], span: None}, &mut env).unwrap());
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut env = Environment::with_builtins();
+        let buf = SharedBuf::default();
+        env.set_trace(Some(Box::new(buf.clone())));
+        let expr = add(vec![lit(1), add(vec![lit(2), lit(3)])]);
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+        let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!("  (+ 2 3)\n  => 5\n(+ 1 5)\n=> 6\n", trace);
+
+        buf.0.lock().unwrap().clear();
+        let expr = add(vec![lit(1), Ast::Literal(Value::Bool(true))]);
+        assert!(eval(&expr, &mut env).is_err());
+        let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(trace.starts_with("(+ 1 #t)\nerror: "), "{}", trace);
+
+        env.set_trace(None);
+        eval(&add(vec![lit(1), lit(2)]), &mut env).unwrap();
+        assert!(!String::from_utf8(buf.0.lock().unwrap().clone()).unwrap().contains("(+ 1 2)"));
+    }
 
     #[test]
-    fn test_add_two_and_two() {
-        let mut env = Environment::new();
-        let add = super::Add;
-        let result = add.call(&vec![Box::new(Literal {val:2}), Box::new(Literal{val:2})], &mut env);
-        assert_eq!(4, result.unwrap());
+    fn test_register_native() {
+        let mut env = Environment::with_builtins();
+        env.register_native("my-mul", |args, _env| {
+            match *args {
+                [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a * b)),
+                _ => Err(EvalError::Arity(String::from("my-mul takes 2 integers")))
+            }
+        });
+        let expr = Ast::Call {func: Box::new(reference("my-mul")), args: vec![add(vec![lit(1), lit(2)]), lit(4)], span: None};
+        assert_eq!(Value::Int(12), eval(&expr, &mut env).unwrap());
+        let expr = Ast::Call {func: Box::new(reference("my-mul")), args: vec![lit(1)], span: None};
+        assert!(matches!(eval(&expr, &mut env), Err(EvalError::Arity(_))));
     }
 
     #[test]
-    fn test_add_three_values() {
-        let mut env = Environment::new();
-        let add = super::Add;
-        assert_eq!(6, add.call(&vec![Box::new(Literal {val:1}),
-                                     Box::new(Literal{val:2}),
-                                     Box::new(Literal{val:3})],
-                               &mut env)
-                   .unwrap());
+    fn test_fuel_stops_infinite_loop() {
+        let mut env = Environment::with_builtins();
+        env.set_fuel(100);
+        let expr = Ast::While(Box::new(Ast::Literal(Value::Bool(true))), vec![add(vec![lit(1), lit(2)])]);
+        match eval(&expr, &mut env) {
+            Err(EvalError::OutOfFuel) => (),
+            other => panic!("Expected to run out of fuel, got {:?}", other)
+        }
+        assert_eq!(Some(0), env.fuel());
+    }
+
+    #[test]
+    fn test_fuel_counts_expressions() {
+        let mut env = Environment::with_builtins();
+        env.set_fuel(4);
+        assert_eq!(Value::Int(3), eval(&add(vec![lit(1), lit(2)]), &mut env).unwrap());
+        assert_eq!(Some(0), env.fuel());
+        assert!(matches!(eval(&lit(1), &mut env), Err(EvalError::OutOfFuel)));
     }
 
     #[test]
     fn test_eval_call() {
-        let mut env = Environment::new();
-        let add = super::Add;
-        let one = Box::new(Literal {val:1});
-        let two = Box::new(Literal {val:2});
-        let three = Box::new(Literal {val:3});
-        let expr = Call {function: Box::new(add), args: vec![one, two, three]};
-        assert_eq!(6, expr.eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        let expr = add(vec![lit(1), lit(2), lit(3)]);
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
     }
 
     #[test]
     fn test_eval_recursive() {
-        let mut env = Environment::new();
-        let expr = Call {function: Box::new(Add),
-                         args: vec![Box::new(Literal {val:1}),
-                                    Box::new(Call {function: Box::new(Add),
-                                                   args: vec![Box::new(Literal {val:2}),
-                                                              Box::new(Literal {val:3})]})]};
-        assert_eq!(6, expr.eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        let expr = add(vec![lit(1), add(vec![lit(2), lit(3)])]);
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_eval_builtin_literal() {
+        let mut env = Environment::with_builtins();
+        let expr = Ast::Call {func: Box::new(Ast::Literal(Value::Builtin(FuncId::Add))), args: vec![lit(1), lit(2)], span: None};
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_builtin_is_a_value() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Builtin(FuncId::Add), eval(&reference("+"), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_call_non_function() {
+        let mut env = Environment::with_builtins();
+        let expr = Ast::Call {func: Box::new(lit(1)), args: vec![], span: None};
+        eval(&expr, &mut env).unwrap_err();
     }
 
     #[test]
     fn test_if_nonzero() {
-        let mut env = Environment::new();
-        assert_eq!(4,
-                   If.call(&vec![ Box::new(Literal {val:1}),
-                                  Box::new(Call {function: Box::new(Add),
-                                                 args: vec![Box::new(Literal {val:1}),
-                                                            Box::new(Literal {val:3})]}),
-                                  Box::new(Literal {val:2})],
-                           &mut env)
-                   .unwrap());
+        let mut env = Environment::with_builtins();
+        let expr = Ast::If(Box::new(lit(1)),
+                           Box::new(add(vec![lit(1), lit(3)])),
+                           Box::new(lit(2)));
+        assert_eq!(Value::Int(4), eval(&expr, &mut env).unwrap());
     }
 
     #[test]
     fn test_if_zero() {
-        let mut env = Environment::new();
-        assert_eq!(2, If.call(&vec![ Box::new(Literal {val:0}),
-                                      Box::new(Call {function: Box::new(Add),
-                                                     args: vec![Box::new(Literal {val:1}),
-                                                                Box::new(Literal {val:3})]}),
-                                      Box::new(Literal {val:2})],
-                              &mut env)
-                   .unwrap());
+        let mut env = Environment::with_builtins();
+        let expr = Ast::If(Box::new(lit(0)),
+                           Box::new(add(vec![lit(1), lit(3)])),
+                           Box::new(lit(2)));
+        assert_eq!(Value::Int(2), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_if_false() {
+        let mut env = Environment::with_builtins();
+        let expr = Ast::If(Box::new(Ast::Literal(Value::Bool(false))),
+                           Box::new(lit(1)),
+                           Box::new(lit(2)));
+        assert_eq!(Value::Int(2), eval(&expr, &mut env).unwrap());
     }
 
     #[test]
     fn test_missing_variable() {
-        let mut env = Environment::new();
-        Reference::new("foo").eval(&mut env).unwrap_err();
+        let mut env = Environment::with_builtins();
+        eval(&reference("foo"), &mut env).unwrap_err();
     }
 
     #[test]
     fn test_variable() {
-        let mut env = Environment::new();
-        env.set("foo", 3);
-        assert_eq!(3, Reference::new("foo").eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        env.set("foo", Value::Int(3)).unwrap();
+        assert_eq!(Value::Int(3), eval(&reference("foo"), &mut env).unwrap());
     }
 
     #[test]
     fn test_variable_argument() {
-        let mut env = Environment::new();
-        env.set("foo", 123);
-        let add = super::Add;
-        let one = Box::new(Reference::new("foo"));
-        let two = Box::new(Literal {val:2});
-        let three = Box::new(Literal {val:3});
-        let expr = Call {function: Box::new(add), args: vec![one, two, three]};
-        assert_eq!(128, expr.eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        env.set("foo", Value::Int(123)).unwrap();
+        let expr = add(vec![reference("foo"), lit(2), lit(3)]);
+        assert_eq!(Value::Int(128), eval(&expr, &mut env).unwrap());
     }
 
     #[test]
     fn test_assign_value() {
-        let mut env = Environment::new();
-        let expr = Call {function: Box::new(Set::new()), args: vec![Box::new(Reference::new("bar")),
-                                                                    Box::new(Literal::new(3))]};
-        assert_eq!(3, expr.eval(&mut env).unwrap());
-        let read = Reference::new("bar");
-        assert_eq!(3, read.eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        let expr = Ast::Set(String::from("bar"), Box::new(lit(3)));
+        assert_eq!(Value::Unit, eval(&expr, &mut env).unwrap());
+        assert_eq!(Value::Int(3), eval(&reference("bar"), &mut env).unwrap());
     }
 
     #[test]
     fn test_reassign_value() {
-        let mut env = Environment::new();
-        env.set("bar", 3);
-        let expr = Call {function: Box::new(Set::new()), args: vec![Box::new(Reference::new("bar")),
-                                                                    Box::new(Literal::new(17))]};
-        assert_eq!(17, expr.eval(&mut env).unwrap());
-        let read = Reference::new("bar");
-        assert_eq!(17, read.eval(&mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        env.set("bar", Value::Int(3)).unwrap();
+        let expr = Ast::Set(String::from("bar"), Box::new(lit(17)));
+        assert_eq!(Value::Unit, eval(&expr, &mut env).unwrap());
+        assert_eq!(Value::Int(17), eval(&reference("bar"), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_define_builtin_alias() {
+        let mut env = Environment::with_builtins();
+        let define = Ast::Define(String::from("plus"), Box::new(reference("+")));
+        assert_eq!(Value::Unit, eval(&define, &mut env).unwrap());
+        let expr = Ast::Call {func: Box::new(reference("plus")), args: vec![lit(1), lit(2)], span: None};
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(0, levenshtein("map", "map"));
+        assert_eq!(3, levenshtein("", "abc"));
+        assert_eq!(3, levenshtein("kitten", "sitting"));
+        assert_eq!(2, levenshtein("pl", "plus"));
+    }
+
+    #[test]
+    fn test_suggest_close_name() {
+        let mut env = Environment::with_builtins();
+        eval(&Ast::Define(String::from("plus"), Box::new(reference("+"))), &mut env).unwrap();
+        let expr = Ast::Call {func: Box::new(reference("plu")), args: vec![lit(1), lit(2)], span: None};
+        let err = eval(&expr, &mut env).unwrap_err();
+        assert_eq!("No such name in environment: plu, did you mean 'plus'?", err.to_string());
+        match env.get("fitler") {
+            Err(EvalError::UndefinedName(_, Some(ref close))) if close == "filter" => (),
+            other => panic!("Expected a suggestion, got {:?}", other)
+        }
+        match env.get("xylophone") {
+            Err(EvalError::UndefinedName(_, None)) => (),
+            other => panic!("Expected no suggestion, got {:?}", other)
+        }
+    }
+
+    fn increment() -> Ast {
+        Ast::Lambda(Arc::new(Lambda {params: vec![String::from("x")], defaults: vec![], keys: vec![],
+                                    body: vec![add(vec![reference("x"), lit(1)])]}))
+    }
+
+    #[test]
+    fn test_call_lambda() {
+        let mut env = Environment::with_builtins();
+        let expr = Ast::Call {func: Box::new(increment()), args: vec![lit(41)], span: None};
+        assert_eq!(Value::Int(42), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_closure_does_not_leak_parameters() {
+        let mut env = Environment::with_builtins();
+        let expr = Ast::Call {func: Box::new(increment()), args: vec![lit(1)], span: None};
+        eval(&expr, &mut env).unwrap();
+        env.get("x").unwrap_err();
+    }
+
+    #[test]
+    fn test_empty_begin() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Unit, eval(&Ast::Begin(vec![]), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_while() {
+        let mut env = Environment::with_builtins();
+        env.set("i", Value::Int(0)).unwrap();
+        let cond = Ast::Call {func: Box::new(reference("<")), args: vec![reference("i"), lit(5)], span: None};
+        let step = Ast::Set(String::from("i"), Box::new(add(vec![reference("i"), lit(1)])));
+        let expr = Ast::While(Box::new(cond), vec![step]);
+        assert_eq!(Value::Unit, eval(&expr, &mut env).unwrap());
+        assert_eq!(Value::Int(5), env.get("i").unwrap());
     }
 }
@@ -0,0 +1,739 @@
+//! Expressions as JSON, so that a program can be read once, kept, and
+//! evaluated later without reading it again. The format is this module's
+//! own, read back only by `from_json`, with each variant as a key:
+//! `"Nil"`, `{"Int": 1}`, `{"If": [test, then, else]}`,
+//! `{"Call": {"func": ..., "args": [...]}}`. Lists are written as
+//! `{"List": [[items...], tail]}` so that long ones don't nest deeply.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+use lisp::builtins::FuncId;
+use lisp::expr::{Ast, Lambda, LetKind, Span};
+use lisp::read::Position;
+use lisp::value::{Key, Value};
+
+/// How deeply arrays and objects may nest in a document. Decoding one
+/// recurses, and each call nests three deep, so this allows about 500.
+const MAX_NESTING: usize = 1500;
+
+#[derive(Debug)]
+pub enum JsonError {
+    /// A value that only exists while a program runs, such as a closure.
+    Unserializable(String),
+    /// Text that isn't JSON, or isn't JSON for an expression.
+    Invalid(String)
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::Unserializable(ref val) => write!(f, "Can't serialize {}", val),
+            JsonError::Invalid(ref err) => write!(f, "Invalid JSON: {}", err)
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+fn invalid<T>(err: String) -> Result<T, JsonError> {
+    Err(JsonError::Invalid(err))
+}
+
+/// A parsed JSON document. Numbers are kept as written, so that integers
+/// too big for a float survive.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+/// `expr` as JSON.
+pub fn to_json(expr: &Ast) -> Result<String, JsonError> {
+    let mut out = String::new();
+    write(&encode(expr)?, &mut out);
+    Ok(out)
+}
+
+/// The expression `src` holds, as written by `to_json`.
+pub fn from_json(src: &str) -> Result<Ast, JsonError> {
+    let mut parser = Parser {chars: src.chars().peekable()};
+    let json = parser.document()?;
+    parser.skip_whitespace();
+    if let Some(c) = parser.chars.next() {
+        return invalid(format!("'{}' after the document", c));
+    }
+    decode(&json)
+}
+
+fn tagged(tag: &str, content: Json) -> Json {
+    Json::Object(vec![(String::from(tag), content)])
+}
+
+fn string(s: &str) -> Json {
+    Json::Str(String::from(s))
+}
+
+fn encode_all(exprs: &[Ast]) -> Result<Json, JsonError> {
+    let mut items = Vec::with_capacity(exprs.len());
+    for expr in exprs {
+        items.push(encode(expr)?);
+    }
+    Ok(Json::Array(items))
+}
+
+fn encode(expr: &Ast) -> Result<Json, JsonError> {
+    match *expr {
+        Ast::Literal(ref val) => Ok(tagged("Literal", encode_value(val)?)),
+        Ast::Reference(ref name) => Ok(tagged("Reference", string(name))),
+        Ast::Call {ref func, ref args, ref span} => encode_call(func, args, span.as_deref()),
+        _ => encode_form(expr)
+    }
+}
+
+fn encode_call(func: &Ast, args: &[Ast], span: Option<&Span>) -> Result<Json, JsonError> {
+    Ok(tagged("Call", Json::Object(vec![
+        (String::from("func"), encode(func)?),
+        (String::from("args"), encode_all(args)?),
+        (String::from("span"), span.map_or(Json::Null, encode_span))
+    ])))
+}
+
+/// Any other expression, kept out of `encode` as `decode_form` is kept out
+/// of `decode`.
+fn encode_form(expr: &Ast) -> Result<Json, JsonError> {
+    Ok(match *expr {
+        Ast::Literal(_) | Ast::Reference(_) | Ast::Call {..} => unreachable!("encode handles {:?}", expr),
+        Ast::If(ref test, ref then, ref otherwise) =>
+            tagged("If", Json::Array(vec![encode(test)?, encode(then)?, encode(otherwise)?])),
+        Ast::Set(ref name, ref val) => tagged("Set", Json::Array(vec![string(name), encode(val)?])),
+        Ast::Define(ref name, ref val) => tagged("Define", Json::Array(vec![string(name), encode(val)?])),
+        Ast::Begin(ref body) => tagged("Begin", encode_all(body)?),
+        Ast::While(ref test, ref body) => tagged("While", Json::Array(vec![encode(test)?, encode_all(body)?])),
+        Ast::Cond(ref clauses) => tagged("Cond", Json::Array(clauses.iter()
+            .map(|(test, body)| Ok(Json::Array(vec![encode(test)?, encode_all(body)?])))
+            .collect::<Result<_, _>>()?)),
+        Ast::And(ref exprs) => tagged("And", encode_all(exprs)?),
+        Ast::Or(ref exprs) => tagged("Or", encode_all(exprs)?),
+        Ast::Lambda(ref lambda) => tagged("Lambda", encode_lambda(lambda)?),
+        Ast::Try(ref body, ref name, ref handler) =>
+            tagged("Try", Json::Array(vec![encode(body)?, string(name), encode_all(handler)?])),
+        Ast::Finally(ref body, ref cleanup) => tagged("Finally", Json::Array(vec![encode(body)?, encode(cleanup)?])),
+        Ast::Let(kind, ref bindings, ref body) => {
+            let kind = match kind {
+                LetKind::Parallel => "Parallel",
+                LetKind::Sequential => "Sequential",
+                LetKind::Recursive => "Recursive"
+            };
+            let bindings = bindings.iter()
+                .map(|(name, val)| Ok(Json::Array(vec![string(name), encode(val)?])))
+                .collect::<Result<_, _>>()?;
+            tagged("Let", Json::Array(vec![string(kind), Json::Array(bindings), encode_all(body)?]))
+        },
+        Ast::Case(ref key, ref clauses) => {
+            let clauses = clauses.iter().map(|(keys, body)| {
+                let keys = match *keys {
+                    Some(ref keys) => Json::Array(keys.iter().map(encode_value).collect::<Result<_, _>>()?),
+                    None => Json::Null
+                };
+                Ok(Json::Array(vec![keys, encode_all(body)?]))
+            }).collect::<Result<_, _>>()?;
+            tagged("Case", Json::Array(vec![encode(key)?, Json::Array(clauses)]))
+        },
+        Ast::Delay(ref expr) => tagged("Delay", encode(expr)?),
+        Ast::Time(ref expr) => tagged("Time", encode(expr)?)
+    })
+}
+
+fn encode_lambda(lambda: &Lambda) -> Result<Json, JsonError> {
+    let keys = lambda.keys.iter().map(|(name, default)| {
+        let default = match *default {
+            Some(ref default) => encode(default)?,
+            None => Json::Null
+        };
+        Ok(Json::Array(vec![string(name), default]))
+    }).collect::<Result<_, _>>()?;
+    Ok(Json::Object(vec![
+        (String::from("params"), Json::Array(lambda.params.iter().map(|name| string(name)).collect())),
+        (String::from("defaults"), encode_all(&lambda.defaults)?),
+        (String::from("keys"), Json::Array(keys)),
+        (String::from("body"), encode_all(&lambda.body)?)
+    ]))
+}
+
+fn encode_span(span: &Span) -> Json {
+    let position = |pos: &Position| Json::Object(vec![
+        (String::from("line"), Json::Number(pos.line.to_string())),
+        (String::from("column"), Json::Number(pos.column.to_string()))
+    ]);
+    Json::Object(vec![
+        (String::from("call"), position(&span.call)),
+        (String::from("args"), Json::Array(span.args.iter().map(position).collect()))
+    ])
+}
+
+fn encode_value(val: &Value) -> Result<Json, JsonError> {
+    Ok(match *val {
+        Value::Unit => string("Unit"),
+        Value::Nil => string("Nil"),
+        Value::Int(n) => tagged("Int", Json::Number(n.to_string())),
+        #[cfg(feature = "bignum")]
+        Value::BigInt(ref n) => tagged("BigInt", Json::Str(n.to_string())),
+        // JSON has no numbers for these, so they are written as Rust
+        // prints them.
+        Value::Float(x) if !x.is_finite() => tagged("Float", Json::Str(x.to_string())),
+        Value::Float(x) => tagged("Float", Json::Number(format!("{:?}", x))),
+        Value::Bool(b) => tagged("Bool", Json::Bool(b)),
+        Value::Char(c) => tagged("Char", Json::Str(c.to_string())),
+        Value::Str(ref s) => tagged("Str", string(s)),
+        Value::Symbol(ref name) => tagged("Symbol", string(name)),
+        Value::Pair(_) => {
+            let mut items = vec![];
+            let mut rest = val;
+            while let Value::Pair(ref pair) = *rest {
+                items.push(encode_value(&pair.0)?);
+                rest = &pair.1;
+            }
+            tagged("List", Json::Array(vec![Json::Array(items), encode_value(rest)?]))
+        },
+        Value::Map(ref map) => tagged("Map", Json::Array(map.iter()
+            .map(|(key, val)| Ok(Json::Array(vec![encode_key(key), encode_value(val)?])))
+            .collect::<Result<_, _>>()?)),
+        Value::Vector(ref items) => tagged("Vector", Json::Array(items.iter().map(encode_value).collect::<Result<_, _>>()?)),
+        Value::Builtin(func) => tagged("Builtin", string(func.name())),
+        Value::Closure(_) | Value::Native(_) | Value::Promise(_) | Value::Macro(_) =>
+            return Err(JsonError::Unserializable(val.to_string()))
+    })
+}
+
+fn encode_key(key: &Key) -> Json {
+    match *key {
+        Key::Int(n) => tagged("Int", Json::Number(n.to_string())),
+        Key::Bool(b) => tagged("Bool", Json::Bool(b)),
+        Key::Char(c) => tagged("Char", Json::Str(c.to_string())),
+        Key::Str(ref s) => tagged("Str", string(s)),
+        Key::Symbol(ref name) => tagged("Symbol", string(name))
+    }
+}
+
+/// The variant a value of an enum is, and what it holds, which is null for
+/// a variant holding nothing.
+fn variant(json: &Json) -> Result<(&str, &Json), JsonError> {
+    match *json {
+        Json::Str(ref tag) => Ok((tag, &Json::Null)),
+        Json::Object(ref fields) if fields.len() == 1 => Ok((&fields[0].0, &fields[0].1)),
+        ref other => invalid(format!("expected a variant, got {:?}", other))
+    }
+}
+
+fn array(json: &Json) -> Result<&[Json], JsonError> {
+    match *json {
+        Json::Array(ref items) => Ok(items),
+        ref other => invalid(format!("expected an array, got {:?}", other))
+    }
+}
+
+/// The items of an array that must have `n` of them.
+fn tuple(json: &Json, n: usize) -> Result<&[Json], JsonError> {
+    let items = array(json)?;
+    if items.len() != n {
+        return invalid(format!("expected {} items, got {}", n, items.len()));
+    }
+    Ok(items)
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> Result<&'a Json, JsonError> {
+    match *json {
+        Json::Object(ref fields) => match fields.iter().find(|(key, _)| key == name) {
+            Some((_, val)) => Ok(val),
+            None => invalid(format!("missing field {}", name))
+        },
+        ref other => invalid(format!("expected an object, got {:?}", other))
+    }
+}
+
+fn text(json: &Json) -> Result<String, JsonError> {
+    match *json {
+        Json::Str(ref s) => Ok(s.clone()),
+        ref other => invalid(format!("expected a string, got {:?}", other))
+    }
+}
+
+fn number<T: std::str::FromStr>(json: &Json) -> Result<T, JsonError> {
+    match *json {
+        Json::Number(ref n) => n.parse().or_else(|_| invalid(format!("number out of range: {}", n))),
+        ref other => invalid(format!("expected a number, got {:?}", other))
+    }
+}
+
+// Loops rather than iterator adapters here and in `encode_all` keep each
+// level of a deep expression from taking several frames.
+fn decode_all(json: &Json) -> Result<Vec<Ast>, JsonError> {
+    let items = array(json)?;
+    let mut exprs = Vec::with_capacity(items.len());
+    for item in items {
+        exprs.push(decode(item)?);
+    }
+    Ok(exprs)
+}
+
+fn decode(json: &Json) -> Result<Ast, JsonError> {
+    let (tag, content) = variant(json)?;
+    match tag {
+        "Literal" => Ok(Ast::Literal(decode_value(content)?)),
+        "Reference" => Ok(Ast::Reference(text(content)?)),
+        "Call" => decode_call(content),
+        _ => decode_form(tag, content)
+    }
+}
+
+fn decode_call(json: &Json) -> Result<Ast, JsonError> {
+    Ok(Ast::Call {
+        func: Box::new(decode(field(json, "func")?)?),
+        args: decode_all(field(json, "args")?)?,
+        span: match *field(json, "span")? {
+            Json::Null => None,
+            ref span => Some(Box::new(decode_span(span)?))
+        }
+    })
+}
+
+/// Any other expression, kept out of `decode` so that its frame, which
+/// every level of a deep expression takes, stays small.
+fn decode_form(tag: &str, content: &Json) -> Result<Ast, JsonError> {
+    Ok(match tag {
+        "If" => {
+            let parts = tuple(content, 3)?;
+            Ast::If(Box::new(decode(&parts[0])?), Box::new(decode(&parts[1])?), Box::new(decode(&parts[2])?))
+        },
+        "Set" | "Define" => {
+            let parts = tuple(content, 2)?;
+            let (name, val) = (text(&parts[0])?, Box::new(decode(&parts[1])?));
+            if tag == "Set" { Ast::Set(name, val) } else { Ast::Define(name, val) }
+        },
+        "Begin" => Ast::Begin(decode_all(content)?),
+        "While" => {
+            let parts = tuple(content, 2)?;
+            Ast::While(Box::new(decode(&parts[0])?), decode_all(&parts[1])?)
+        },
+        "Cond" => Ast::Cond(array(content)?.iter().map(|clause| {
+            let parts = tuple(clause, 2)?;
+            Ok((decode(&parts[0])?, decode_all(&parts[1])?))
+        }).collect::<Result<_, _>>()?),
+        "And" => Ast::And(decode_all(content)?),
+        "Or" => Ast::Or(decode_all(content)?),
+        "Lambda" => Ast::Lambda(Arc::new(decode_lambda(content)?)),
+        "Try" => {
+            let parts = tuple(content, 3)?;
+            Ast::Try(Box::new(decode(&parts[0])?), text(&parts[1])?, decode_all(&parts[2])?)
+        },
+        "Finally" => {
+            let parts = tuple(content, 2)?;
+            Ast::Finally(Box::new(decode(&parts[0])?), Box::new(decode(&parts[1])?))
+        },
+        "Let" => {
+            let parts = tuple(content, 3)?;
+            let kind = match variant(&parts[0])?.0 {
+                "Parallel" => LetKind::Parallel,
+                "Sequential" => LetKind::Sequential,
+                "Recursive" => LetKind::Recursive,
+                other => return invalid(format!("unknown kind of let {}", other))
+            };
+            let bindings = array(&parts[1])?.iter().map(|binding| {
+                let parts = tuple(binding, 2)?;
+                Ok((text(&parts[0])?, decode(&parts[1])?))
+            }).collect::<Result<_, _>>()?;
+            Ast::Let(kind, bindings, decode_all(&parts[2])?)
+        },
+        "Case" => {
+            let parts = tuple(content, 2)?;
+            let clauses = array(&parts[1])?.iter().map(|clause| {
+                let parts = tuple(clause, 2)?;
+                let keys = match parts[0] {
+                    Json::Null => None,
+                    ref keys => Some(array(keys)?.iter().map(decode_value).collect::<Result<_, _>>()?)
+                };
+                Ok((keys, decode_all(&parts[1])?))
+            }).collect::<Result<_, _>>()?;
+            Ast::Case(Box::new(decode(&parts[0])?), clauses)
+        },
+        "Delay" => Ast::Delay(Arc::new(decode(content)?)),
+        "Time" => Ast::Time(Box::new(decode(content)?)),
+        other => return invalid(format!("unknown expression {}", other))
+    })
+}
+
+fn decode_lambda(json: &Json) -> Result<Lambda, JsonError> {
+    let keys = array(field(json, "keys")?)?.iter().map(|key| {
+        let parts = tuple(key, 2)?;
+        let default = match parts[1] {
+            Json::Null => None,
+            ref default => Some(decode(default)?)
+        };
+        Ok((text(&parts[0])?, default))
+    }).collect::<Result<_, _>>()?;
+    Ok(Lambda {
+        params: array(field(json, "params")?)?.iter().map(text).collect::<Result<_, _>>()?,
+        defaults: decode_all(field(json, "defaults")?)?,
+        keys,
+        body: decode_all(field(json, "body")?)?
+    })
+}
+
+fn decode_span(json: &Json) -> Result<Span, JsonError> {
+    let position = |json: &Json| Ok(Position {line: number(field(json, "line")?)?, column: number(field(json, "column")?)?});
+    Ok(Span {
+        call: position(field(json, "call")?)?,
+        args: array(field(json, "args")?)?.iter().map(position).collect::<Result<_, _>>()?
+    })
+}
+
+fn decode_value(json: &Json) -> Result<Value, JsonError> {
+    let (tag, content) = variant(json)?;
+    Ok(match tag {
+        "Unit" => Value::Unit,
+        "Nil" => Value::Nil,
+        "Int" => Value::Int(number(content)?),
+        #[cfg(feature = "bignum")]
+        "BigInt" => Value::BigInt(text(content)?.parse::<BigInt>().or_else(|_| invalid(format!("bad integer {:?}", content)))?),
+        "Float" => Value::Float(match *content {
+            Json::Str(ref x) => x.parse().or_else(|_| invalid(format!("bad float {}", x)))?,
+            ref x => number(x)?
+        }),
+        "Bool" => match *content {
+            Json::Bool(b) => Value::Bool(b),
+            ref other => return invalid(format!("expected a boolean, got {:?}", other))
+        },
+        "Char" => Value::Char(character(content)?),
+        "Str" => Value::Str(text(content)?),
+        "Symbol" => Value::Symbol(text(content)?),
+        "List" => {
+            let parts = tuple(content, 2)?;
+            let mut list = decode_value(&parts[1])?;
+            for item in array(&parts[0])?.iter().rev() {
+                list = Value::cons(decode_value(item)?, list);
+            }
+            list
+        },
+        "Map" => Value::Map(Arc::new(array(content)?.iter().map(|entry| {
+            let parts = tuple(entry, 2)?;
+            Ok((decode_key(&parts[0])?, decode_value(&parts[1])?))
+        }).collect::<Result<BTreeMap<_, _>, _>>()?)),
+        "Vector" => Value::Vector(Arc::new(array(content)?.iter().map(decode_value).collect::<Result<_, _>>()?)),
+        "Builtin" => {
+            let name = text(content)?;
+            match FuncId::ALL.iter().find(|func| func.name() == name) {
+                Some(&func) => Value::Builtin(func),
+                None => return invalid(format!("no builtin {}", name))
+            }
+        },
+        other => return invalid(format!("unknown value {}", other))
+    })
+}
+
+fn decode_key(json: &Json) -> Result<Key, JsonError> {
+    let (tag, content) = variant(json)?;
+    Ok(match tag {
+        "Int" => Key::Int(number(content)?),
+        "Bool" => match *content {
+            Json::Bool(b) => Key::Bool(b),
+            ref other => return invalid(format!("expected a boolean, got {:?}", other))
+        },
+        "Char" => Key::Char(character(content)?),
+        "Str" => Key::Str(text(content)?),
+        "Symbol" => Key::Symbol(text(content)?),
+        other => return invalid(format!("unknown key {}", other))
+    })
+}
+
+fn character(json: &Json) -> Result<char, JsonError> {
+    let s = text(json)?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => invalid(format!("expected one character, got {:?}", s))
+    }
+}
+
+fn write(json: &Json, out: &mut String) {
+    fn all<'a, I: Iterator<Item = &'a Json>>(items: I, out: &mut String) {
+        for (i, item) in items.enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write(item, out);
+        }
+    }
+    match *json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Json::Number(ref n) => out.push_str(n),
+        Json::Str(ref s) => write_string(s, out),
+        Json::Array(ref items) => {
+            out.push('[');
+            all(items.iter(), out);
+            out.push(']');
+        },
+        Json::Object(ref fields) => {
+            out.push('{');
+            for (i, (key, val)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(key, out);
+                out.push(':');
+                write(val, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => invalid(format!("expected '{}', got '{}'", expected, c)),
+            None => invalid(format!("expected '{}' at the end", expected))
+        }
+    }
+
+    /// Whether the next thing is `c`, reading it if so.
+    fn next_is(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if_eq(&c).is_some()
+    }
+
+    /// Reads a whole value. Arrays and objects that are still open are kept
+    /// on an explicit stack, so that deep documents can't overflow the
+    /// native one.
+    fn document(&mut self) -> Result<Json, JsonError> {
+        // Each open array or object, with the name of the field being read
+        // if it's an object.
+        let mut stack: Vec<(Json, Option<String>)> = vec![];
+        loop {
+            self.skip_whitespace();
+            let mut val = match self.chars.peek() {
+                Some('[') | Some('{') => {
+                    let object = self.chars.next() == Some('{');
+                    if stack.len() >= MAX_NESTING {
+                        return invalid(format!("nested deeper than {}", MAX_NESTING));
+                    }
+                    if !self.next_is(if object { '}' } else { ']' }) {
+                        let (empty, key) = if object { (Json::Object(vec![]), Some(self.key()?)) } else { (Json::Array(vec![]), None) };
+                        stack.push((empty, key));
+                        continue;
+                    }
+                    if object { Json::Object(vec![]) } else { Json::Array(vec![]) }
+                },
+                _ => self.scalar()?
+            };
+            // Add the value to the innermost open one, closing any it's the
+            // last item of.
+            loop {
+                let (open, key) = match stack.last_mut() {
+                    Some(top) => top,
+                    None => return Ok(val)
+                };
+                let object = match *open {
+                    Json::Array(ref mut items) => { items.push(val); false },
+                    Json::Object(ref mut fields) => { fields.push((key.take().expect("Fields have names"), val)); true },
+                    _ => unreachable!("only arrays and objects are open")
+                };
+                if self.next_is(if object { '}' } else { ']' }) {
+                    val = stack.pop().expect("Open value disappeared").0;
+                    continue;
+                }
+                self.expect(',')?;
+                if object {
+                    *key = Some(self.key()?);
+                }
+                break;
+            }
+        }
+    }
+
+    /// The name of a field and the `:` after it.
+    fn key(&mut self) -> Result<String, JsonError> {
+        self.skip_whitespace();
+        if self.chars.peek() != Some(&'"') {
+            return invalid(String::from("expected a field name"));
+        }
+        let key = self.string()?;
+        self.expect(':')?;
+        Ok(key)
+    }
+
+    /// A value that isn't an array or an object.
+    fn scalar(&mut self) -> Result<Json, JsonError> {
+        match self.chars.peek().copied() {
+            Some('"') => Ok(Json::Str(self.string()?)),
+            Some('-') | Some('0'..='9') => {
+                let mut n = String::new();
+                while let Some(c) = self.chars.next_if(|&c| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')) {
+                    n.push(c);
+                }
+                if n.parse::<f64>().is_err() {
+                    return invalid(format!("bad number {}", n));
+                }
+                Ok(Json::Number(n))
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => invalid(format!("unexpected {}", word))
+                }
+            },
+            Some(c) => invalid(format!("unexpected '{}'", c)),
+            None => invalid(String::from("unexpected end"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.chars.next();
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.escaped()?),
+                    other => return invalid(format!("bad escape {:?}", other))
+                },
+                Some(c) => s.push(c),
+                None => return invalid(String::from("unterminated string"))
+            }
+        }
+    }
+
+    /// The character a `\u` escape stands for, reading the second half of a
+    /// surrogate pair if it's the first.
+    fn escaped(&mut self) -> Result<char, JsonError> {
+        let high = self.hex()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !(self.chars.next() == Some('\\') && self.chars.next() == Some('u')) {
+                return invalid(String::from("unpaired surrogate"));
+            }
+            let low = self.hex()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return invalid(String::from("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).map_or_else(|| invalid(format!("bad character \\u{:04x}", code)), Ok)
+    }
+
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        match u32::from_str_radix(&digits, 16) {
+            Ok(code) if digits.len() == 4 => Ok(code),
+            _ => invalid(format!("bad escape \\u{}", digits))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lisp::read::{eval, read_all, read_str, Environment};
+
+    #[test]
+    fn test_round_trip() {
+        let expr = read_str("(+ 1 2)").unwrap();
+        let json = to_json(&expr).unwrap();
+        let back = from_json(&json).unwrap();
+        assert_eq!(expr, back);
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(3), eval(&back, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip_program() {
+        let src = r#"
+            (define (f x (y 2) :z (:w 1.5)) (* x y z w))
+            (let* ((a 1) (b '(1 "two\n" #\c . sym))) (if (and a b) (set a 2) (or)))
+            (letrec () (while #f (begin)) (cond ((= 1 2) 'no) (else `(1 ,(f 2)))))
+            (case (car '(x)) ((x y) #t) (else #f))
+            (try (delay (time -1e300)) (catch e e))
+            (finally (define z 1) (set z 2))
+            (vector 1 2 "\t\"😀")
+            (map-set (make-map) 'k 1.0)
+        "#;
+        let mut exprs = read_all(src).unwrap();
+        exprs.push(Ast::Literal(Value::Float(f64::INFINITY)));
+        exprs.push(Ast::Literal(Value::Builtin(FuncId::Car)));
+        let mut map = BTreeMap::new();
+        map.insert(Key::Char('k'), Value::Vector(Arc::new(vec![Value::Unit, Value::Nil])));
+        exprs.push(Ast::Literal(Value::Map(Arc::new(map))));
+        for expr in exprs {
+            let json = to_json(&expr).unwrap();
+            assert_eq!(expr, from_json(&json).unwrap(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut env = Environment::with_builtins();
+        let closure = eval(&read_str("(lambda (x) x)").unwrap(), &mut env).unwrap();
+        assert!(matches!(to_json(&Ast::Literal(closure)), Err(JsonError::Unserializable(_))));
+        for src in &["", "{\"Reference\": 1}", "{\"Nope\": []}", "{\"If\": [1]}", "[1,]", "\"Unit\" 1", "{\"Literal\": {\"Int\": 1e999}}",
+                     "{\"Literal\": {\"Str\": \"\\ud800\"}}", "{\"Literal\": {\"Builtin\": \"no-such\"}}"] {
+            assert!(matches!(from_json(src), Err(JsonError::Invalid(_))), "{}", src);
+        }
+        let deep = format!("{}{}", "[".repeat(MAX_NESTING + 1), "]".repeat(MAX_NESTING + 1));
+        assert!(matches!(from_json(&deep), Err(JsonError::Invalid(_))));
+        let nested = read_str(&format!("{}0{}", "(+ 1 ".repeat(300), ")".repeat(300))).unwrap();
+        assert_eq!(nested, from_json(&to_json(&nested).unwrap()).unwrap());
+    }
+}
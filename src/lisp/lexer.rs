@@ -0,0 +1,565 @@
+use std::cell::Cell;
+use std::iter::{Iterator,Peekable};
+use std::io;
+use lisp::read::{Position, ReadError};
+
+macro_rules! try_peek {
+    ($expr:expr) => ({{let stupid_rust = {
+                          let peek = $expr.peek();
+                          match peek {
+                              Some(Result::Ok(val)) => Some(*val),
+                              Some(Result::Err(_)) => None,
+                              None => None
+                          }
+                      };
+                      match stupid_rust {
+                          Some(c) => Some(c),
+                          None => {
+                              match $expr.next() {
+                                  Some(Err(e)) => return Err(From::from(e)),
+                                  None => None,
+                                  Some(Ok(_)) => panic!("peek and next disagree")
+                              }
+                          }
+                      }
+                      }}
+                    )
+}
+
+/// The smallest meaningful pieces of source text. Whitespace and comments
+/// never make it into tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    LParen,
+    RParen,
+    /// A `[`, which groups like `(` but must be closed by `]`.
+    LBracket,
+    RBracket,
+    Quote,
+    /// The backquote starting a template.
+    Quasiquote,
+    /// A `,` in a template, or `,@` if it splices a list in.
+    Unquote(bool),
+    /// A `.` on its own, before the last element of a dotted list.
+    Dot,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Symbol(String)
+}
+
+/// Reads the next token, or `None` at the end of input.
+pub fn next_token(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Option<Token>, ReadError> {
+    Ok(next_token_at(input, None)?.map(|(token, _)| token))
+}
+
+/// Reads the next token along with where it starts, if `pos` follows the
+/// position of the input.
+pub fn next_token_at(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>,
+                     pos: Option<&Cell<Position>>)
+    -> Result<Option<(Token, Option<Position>)>, ReadError>
+{
+    loop {
+        skip_whitespace(input)?;
+        let c = match try_peek!(input) {
+            Some(c) => c,
+            None => return Ok(None)
+        };
+        // Peeking has just read the first character of the token.
+        let start = pos.map(Cell::get);
+        let token = match c {
+            '(' => { input.next(); Token::LParen },
+            ')' => { input.next(); Token::RParen },
+            '[' => { input.next(); Token::LBracket },
+            ']' => { input.next(); Token::RBracket },
+            '\'' => { input.next(); Token::Quote },
+            '`' => { input.next(); Token::Quasiquote },
+            ',' => {
+                input.next();
+                let splice = try_peek!(input) == Some('@');
+                if splice {
+                    input.next();
+                }
+                Token::Unquote(splice)
+            },
+            '.' => {
+                input.next();
+                match try_peek!(input) {
+                    Some(c) if !is_delimiter(c) => Token::Symbol(format!(".{}", read_symbol(input)?)),
+                    _ => Token::Dot
+                }
+            },
+            '"' => { input.next(); Token::Str(read_string(input)?) },
+            '0'..='9' => read_number(input)?,
+            '+'|'-' => read_signed(input, c)?,
+            '#' => match read_hash(input)? {
+                Some(token) => token,
+                None => continue
+            },
+            _ => Token::Symbol(read_symbol(input)?)
+        };
+        return Ok(Some((token, start)));
+    }
+}
+
+/// Whether `c` ends a symbol or number token.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '('|')'|'['|']')
+}
+
+/// Reads a symbol up to the next delimiter or the end of input, leaving the
+/// delimiter unread.
+pub fn read_symbol(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<String, ReadError> {
+    let mut name = String::new();
+    while let Some(c) = try_peek!(input) {
+        if is_delimiter(c) {
+            break;
+        }
+        name.push(c);
+        input.next();
+    }
+
+    Ok(name)
+}
+
+pub fn read_number(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Token, ReadError> {
+    let mut buf = String::new();
+    if try_peek!(input) == Some('-') {
+        buf.push('-');
+        input.next();
+    }
+    read_digits(input, buf)
+}
+
+fn read_digits(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, mut buf: String) -> Result<Token, ReadError> {
+    loop {
+        let c = try_peek!(input);
+        match c {
+            Some(c @ '0'..='9') |
+            Some(c @ '.') |
+            Some(c @ '_') |
+            Some(c @ 'a'..='z') |
+            Some(c @ 'A'..='Z') => { buf.push(c); input.next(); },
+            // The sign of an exponent.
+            Some(c @ '+') | Some(c @ '-') if buf.ends_with(['e', 'E']) => { buf.push(c); input.next(); },
+            Some(c) if is_delimiter(c) => break,
+            None            => break,
+            Some(c)         => { input.next(); return Err(ReadError::Invalid(format!("Invalid input '{}'", c))) }
+        }
+    }
+    parse_number(&buf)
+}
+
+/// Parses an integer as `parse_integer` does, or a float with digits on
+/// both sides of its decimal point, an exponent such as `e-3`, or both.
+/// Single underscores may separate digits.
+pub fn parse_number(buf: &str) -> Result<Token, ReadError> {
+    if buf.contains('_') {
+        return parse_number(&without_separators(buf)?);
+    }
+    let unsigned = buf.strip_prefix('-').unwrap_or(buf);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(_) if matches!(unsigned.get(..2), Some("0x")) => (unsigned, None),
+        Some(i) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+        None => (unsigned, None)
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None if exponent.is_none() => return Ok(Token::Int(parse_integer(buf)?)),
+        None => (mantissa, None)
+    };
+    let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    let exponent = exponent.map(|exponent| exponent.strip_prefix(['+', '-']).unwrap_or(exponent));
+    if !digits(whole) || !fraction.is_none_or(digits) || !exponent.is_none_or(digits) {
+        return Err(ReadError::Invalid(format!("Invalid number '{}'", buf)));
+    }
+    Ok(Token::Float(buf.parse().map_err(|_| ReadError::Invalid(format!("Invalid number '{}'", buf)))?))
+}
+
+/// `buf` without the underscores in it, each of which must be between two
+/// digits of the number's radix.
+fn without_separators(buf: &str) -> Result<String, ReadError> {
+    let radix = match buf.strip_prefix('-').unwrap_or(buf).get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10
+    };
+    let digit = |c: &char| c.is_digit(radix);
+    let chars: Vec<char> = buf.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let between = i > 0 && digit(&chars[i - 1]) && chars.get(i + 1).is_some_and(digit);
+        if c == '_' && !between {
+            return Err(ReadError::Invalid(format!("Misplaced _ in number '{}'", buf)));
+        }
+    }
+    Ok(buf.replace('_', ""))
+}
+
+/// Parses an optionally signed integer, which may have a `0x`, `0o` or `0b`
+/// prefix selecting hexadecimal, octal or binary.
+pub fn parse_integer(buf: &str) -> Result<i64, ReadError> {
+    let (sign, unsigned) = match buf.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", buf)
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        _ => (10, unsigned)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(ReadError::Invalid(format!("Invalid number '{}'", buf)));
+    }
+    Ok(i64::from_str_radix(&format!("{}{}", sign, digits), radix)?)
+}
+
+/// Reads something starting with `+` or `-`, which is a number if a digit
+/// follows and otherwise a symbol such as the subtraction function.
+fn read_signed(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, sign: char) -> Result<Token, ReadError> {
+    input.next();
+    match try_peek!(input) {
+        Some('0'..='9') => {
+            let prefix = if sign == '-' { String::from("-") } else { String::new() };
+            read_digits(input, prefix)
+        },
+        Some(c) if is_delimiter(c) => Ok(Token::Symbol(sign.to_string())),
+        None => Ok(Token::Symbol(sign.to_string())),
+        Some(_) => Ok(Token::Symbol(format!("{}{}", sign, read_symbol(input)?)))
+    }
+}
+
+/// Reads the rest of a string literal after its opening quote, handling the
+/// escapes `\n`, `\t`, `\\` and `\"`.
+fn read_string(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<String, ReadError> {
+    let mut s = String::new();
+    loop {
+        match input.next() {
+            Some(Ok('"')) => return Ok(s),
+            Some(Ok('\\')) => match input.next() {
+                Some(Ok('n')) => s.push('\n'),
+                Some(Ok('t')) => s.push('\t'),
+                Some(Ok(c @ '\\')) | Some(Ok(c @ '"')) => s.push(c),
+                Some(Ok(c)) => return Err(ReadError::Invalid(format!("Unknown escape '\\{}'", c))),
+                Some(Err(e)) => return Err(From::from(e)),
+                None => return Err(ReadError::UnexpectedEof)
+            },
+            Some(Ok(c)) => s.push(c),
+            Some(Err(e)) => return Err(From::from(e)),
+            None => return Err(ReadError::UnexpectedEof)
+        }
+    }
+}
+
+/// Reads a `#` syntax: a boolean `#t` or `#f`, a character literal such as
+/// `#\a` or `#\space`, or a `#| ... |#` block comment, for which nothing is
+/// returned.
+fn read_hash(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Option<Token>, ReadError> {
+    input.next();
+    match try_peek!(input) {
+        Some('\\') => { input.next(); Ok(Some(Token::Char(read_char(input)?))) },
+        Some('|') => { input.next(); skip_block_comment(input)?; Ok(None) },
+        Some(c) if c.is_alphabetic() => {
+            let mut name = String::new();
+            while let Some(c) = try_peek!(input) {
+                if !c.is_alphanumeric() {
+                    break;
+                }
+                name.push(c);
+                input.next();
+            }
+            match name.as_ref() {
+                "t" | "true" => Ok(Some(Token::Bool(true))),
+                "f" | "false" => Ok(Some(Token::Bool(false))),
+                _ => Err(ReadError::Invalid(format!("Invalid input '#{}'", name)))
+            }
+        },
+        Some(c) => Err(ReadError::Invalid(format!("Invalid input '#{}'", c))),
+        None => Err(ReadError::UnexpectedEof)
+    }
+}
+
+/// Skips the rest of a `#|` block comment, including any nested ones.
+fn skip_block_comment(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<(), ReadError> {
+    let mut depth = 1;
+    let mut prev = None;
+    for c in input {
+        let c = c?;
+        prev = match (prev, c) {
+            (Some('|'), '#') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+                None
+            },
+            (Some('#'), '|') => { depth += 1; None },
+            _ => Some(c)
+        };
+    }
+    Err(ReadError::UnexpectedEof)
+}
+
+/// Skips whitespace and `;` comments.
+fn skip_whitespace(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<(), ReadError> {
+    while let Some(c) = try_peek!(input) {
+        match c {
+            c if c.is_whitespace() => { input.next(); },
+            ';' => skip_line(input)?,
+            _ => break
+        }
+    }
+    Ok(())
+}
+
+/// Skips a `;` comment up to and including the end of the line.
+fn skip_line(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<(), ReadError> {
+    for c in input {
+        if c? == '\n' {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_char(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<char, ReadError> {
+    let mut name = String::new();
+    match try_peek!(input) {
+        Some(c) if !c.is_whitespace() => { name.push(c); input.next(); },
+        _ => return Err(ReadError::Invalid(String::from("empty character name")))
+    }
+    if name.chars().all(char::is_alphabetic) {
+        while let Some(c) = try_peek!(input) {
+            if !c.is_alphanumeric() {
+                break;
+            }
+            name.push(c);
+            input.next();
+        }
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+    match name.as_ref() {
+        "space" => Ok(' '),
+        "newline" => Ok('\n'),
+        "tab" => Ok('\t'),
+        "return" => Ok('\r'),
+        _ => Err(ReadError::Invalid(format!("Unknown character name '{}'", name)))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter::{Iterator,Map};
+    use std::str::{Chars};
+    use std::io::Error;
+
+    fn char_to_result(c: char) -> Result<char, Error> {
+        Ok(c)
+    }
+
+    type Input = Map<Chars<'static>, fn(char)->Result<char, Error>>;
+
+    fn input(s: &'static str) -> Input {
+        s.chars().map(char_to_result)
+    }
+
+    fn iterator<T>(iterator: &mut dyn Iterator<Item=T>) -> &mut dyn Iterator<Item=T> {
+        iterator
+    }
+
+    fn tokens(s: &'static str) -> Vec<Token> {
+        let mut m = input(s);
+        let peekable = &mut iterator(&mut m).peekable();
+        let mut tokens = vec![];
+        while let Some(token) = next_token(peekable).unwrap() {
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    fn symbol(name: &str) -> Token {
+        Token::Symbol(String::from(name))
+    }
+
+    #[test]
+    fn test_mixed_input() {
+        assert_eq!(vec![Token::LParen, symbol("define"), symbol("x"),
+                        Token::LParen, symbol("+"), Token::Int(1), Token::Int(-2), Token::RParen,
+                        Token::RParen,
+                        Token::Quote, Token::LParen, symbol("a"), Token::Bool(true), Token::Char(' '), Token::RParen,
+                        Token::Str(String::from("hi \"there\"\n")), symbol("-x"), Token::Int(0x1F)],
+                   tokens("(define x ; the answer\n (+ 1 -2)) #| skipped |# '(a #t #\\space)\"hi \\\"there\\\"\\n\" -x 0x1F"));
+    }
+
+    #[test]
+    fn test_booleans() {
+        assert_eq!(vec![Token::Bool(true), Token::Bool(true), Token::Bool(false), Token::Bool(false), Token::RParen],
+                   tokens("#t #true #f #false)"));
+        let mut m = input("#tru");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert!(matches!(next_token(peekable), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_whitespace() {
+        assert_eq!(vec![Token::LParen, symbol("+"), Token::Int(1), Token::Float(2.5), Token::Char('a'), Token::RParen],
+                   tokens("(+\t1\x0c2.5\u{a0}#\\a\x0b)"));
+        let mut m = input("#\\\t");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert!(matches!(next_token(peekable), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert_eq!(vec![Token::LBracket, symbol("a"), Token::RBracket, Token::LParen, symbol("b"), Token::RBracket],
+                   tokens("[a](b]"));
+    }
+
+    #[test]
+    fn test_template() {
+        assert_eq!(vec![Token::Quasiquote, Token::LParen, symbol("a"), Token::Unquote(false), symbol("b"),
+                        Token::Unquote(true), Token::LParen, symbol("c"), Token::RParen, symbol("d,e"), Token::RParen],
+                   tokens("`(a ,b ,@(c) d,e)"));
+    }
+
+    #[test]
+    fn test_dot() {
+        assert_eq!(vec![Token::LParen, symbol("a"), Token::Dot, symbol("b"), Token::RParen, symbol("..."), symbol(".x"), Token::Dot],
+                   tokens("(a . b) ... .x ."));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(Vec::<Token>::new(), tokens("  ; nothing here\n #| or here |#"));
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut m = input("\"abc");
+        let peekable = &mut iterator(&mut m).peekable();
+        match next_token(peekable) {
+            Err(ReadError::UnexpectedEof) => (),
+            other => panic!("Expected unexpected EOF, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_read_symbol() {
+        for src in &["foo", "foo bar", "foo)", "foo(", "foo\n", "foo\t"] {
+            let mut m = input(src);
+            let peekable = &mut iterator(&mut m).peekable();
+            assert_eq!("foo", read_symbol(peekable).unwrap());
+            assert_eq!(src.chars().nth(3), peekable.next().map(Result::unwrap));
+        }
+    }
+
+    #[test]
+    fn test_read_number() {
+        let mut m = input("14 ");
+        let peekable = &mut iterator(&mut m).peekable();
+        let val = read_number(peekable).unwrap();
+        assert_eq!(Token::Int(14), val);
+    }
+
+    #[test]
+    fn test_read_number_at_end_of_input() {
+        let mut m = input("14");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Token::Int(14), read_number(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_negative_number() {
+        let mut m = input("-14 ");
+        let peekable = &mut iterator(&mut m).peekable();
+        let val = read_number(peekable).unwrap();
+        assert_eq!(Token::Int(-14), val);
+    }
+
+    #[test]
+    fn test_read_number_right_paren() {
+        let mut m = input("2701)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let val = read_number(peekable).unwrap();
+        assert_eq!(Token::Int(2701), val);
+        let next = peekable.next().expect("Right paren was consumed");
+        assert_eq!(')', next.unwrap());
+    }
+
+    #[test]
+    fn test_read_hex_number() {
+        let mut m = input("0x1F ");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Token::Int(31), read_number(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_octal_number() {
+        let mut m = input("0o17)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Token::Int(15), read_number(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_malformed_binary_number() {
+        let mut m = input("0b12 ");
+        let peekable = &mut iterator(&mut m).peekable();
+        match read_number(peekable) {
+            Err(ReadError::Invalid(_)) => (),
+            other => panic!("Expected invalid number, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_read_float() {
+        let mut m = input("3.75 -0.5)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Some(Token::Float(3.75)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(-0.5)), next_token(peekable).unwrap());
+        for bad in ["1.", "1..2", "1.2.3", "0x1.5", "1.5a"] {
+            assert!(matches!(parse_number(bad), Err(ReadError::Invalid(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_read_separated_number() {
+        let mut m = input("1_000_000 -1_0 0xFF_FF 1_000.2_5)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Some(Token::Int(1000000)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Int(-10)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Int(0xFFFF)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(1000.25)), next_token(peekable).unwrap());
+        for bad in ["_1", "1_", "1__000", "-_1", "0x_1", "1_.5", "1._5", "1_e3", "1e_3"] {
+            assert!(matches!(parse_number(bad), Err(ReadError::Invalid(_))), "{}", bad);
+        }
+        let mut m = input("1__0 ");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert!(matches!(read_number(peekable), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_read_exponent() {
+        let mut m = input("1e3 1.5e-2 2E10 -2.5e+1 1_0e2 0x1E)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Some(Token::Float(1000.0)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(0.015)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(2e10)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(-25.0)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Float(1000.0)), next_token(peekable).unwrap());
+        assert_eq!(Some(Token::Int(30)), next_token(peekable).unwrap());
+        for bad in ["1e", "1e+", "1e-", "1.e3", "e3", "1e3.5", "1e3e4", "1ee3", "0o1e3"] {
+            assert!(matches!(parse_number(bad), Err(ReadError::Invalid(_))), "{}", bad);
+        }
+        let mut m = input("1e+ ");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert!(matches!(read_number(peekable), Err(ReadError::Invalid(_))));
+    }
+}
@@ -1,2 +1,9 @@
+pub mod builtins;
+pub mod error;
 pub mod expr;
+#[cfg(feature = "ast-json")]
+pub mod json;
+pub mod lexer;
 pub mod read;
+pub mod utf8;
+pub mod value;
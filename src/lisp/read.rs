@@ -1,18 +1,188 @@
+use std::error::Error;
 use std::iter::{Iterator,Peekable};
 use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::num;
+use std::cell::Cell;
+use std::collections::VecDeque;
 use std::fmt;
-use std::error::{Error};
-pub use lisp::expr::{Add,Expression,Function,Call,Literal,If,Environment,Reference,Set};
+use std::fs;
+use std::panic;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+pub use lisp::builtins::FuncId;
+pub use lisp::error::LustError;
+pub use lisp::expr::{Ast,Environment,Function,Lambda,LetKind,Span,eval};
+pub use lisp::expr::EvalError;
+pub use lisp::lexer::Token;
+pub use lisp::value::Value;
+use lisp::lexer::{next_token, next_token_at};
+use lisp::utf8::Utf8Chars;
+use lisp::value::keyword;
 
 #[derive(Debug)]
 pub enum ReadError {
     Io(io::Error),
     Invalid(String),
     Parse(num::ParseIntError),
+    /// The input ran out in the middle of an expression.
+    UnexpectedEof,
+    /// Forms nested deeper than the given limit.
+    NestingLimit(usize),
+    /// A `)` that closes nothing, and where it was if known.
+    UnexpectedCloseParen(Option<Position>),
     Eof
 }
 
+impl ReadError {
+    /// Fills in where the error happened, if it's one that says.
+    pub fn at(self, pos: Position) -> ReadError {
+        match self {
+            ReadError::UnexpectedCloseParen(None) => ReadError::UnexpectedCloseParen(Some(pos)),
+            err => err
+        }
+    }
+}
+
+/// A place in the input, counting lines and columns from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize
+}
+
+/// Passes characters through, keeping track of where the last one read was.
+pub struct Tracked<I> {
+    chars: I,
+    pos: Rc<Cell<Position>>,
+    newline: bool
+}
+
+impl<I> Tracked<I> {
+    pub fn new(chars: I) -> Tracked<I> {
+        Tracked {chars, pos: Rc::new(Cell::new(Position {line: 1, column: 0})), newline: false}
+    }
+
+    /// A handle that follows the position as characters are read.
+    pub fn position(&self) -> Rc<Cell<Position>> {
+        self.pos.clone()
+    }
+}
+
+impl<I: Iterator<Item = Result<char, io::Error>>> Iterator for Tracked<I> {
+    type Item = Result<char, io::Error>;
+
+    fn next(&mut self) -> Option<Result<char, io::Error>> {
+        let next = self.chars.next();
+        if let Some(Ok(c)) = next {
+            let mut pos = self.pos.get();
+            if self.newline {
+                pos = Position {line: pos.line + 1, column: 1};
+            } else {
+                pos.column += 1;
+            }
+            self.pos.set(pos);
+            self.newline = c == '\n';
+        }
+        next
+    }
+}
+
+/// Where a `Balanced` is in the syntax, as far as telling whether parens
+/// balance goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scan {
+    Code,
+    Hash,
+    CharLit,
+    Str,
+    Escape,
+    Comment,
+    Block(usize),
+    BlockBar(usize),
+    BlockHash(usize)
+}
+
+/// Passes characters through a line at a time, holding lines back until
+/// the parens in them balance so that a form is only parsed once it's
+/// complete. `prompt` is called each time another line is needed to finish
+/// one.
+pub struct Balanced<I, F> {
+    chars: I,
+    prompt: F,
+    buffer: VecDeque<Result<char, io::Error>>,
+    pending: Rc<Cell<usize>>,
+    depth: usize,
+    scan: Scan
+}
+
+impl<I, F> Balanced<I, F> {
+    pub fn new(chars: I, prompt: F) -> Balanced<I, F> {
+        Balanced {chars, prompt, buffer: VecDeque::new(), pending: Rc::new(Cell::new(0)), depth: 0, scan: Scan::Code}
+    }
+
+    /// A handle on how many characters are left of the lines held back
+    /// so far.
+    pub fn pending(&self) -> Rc<Cell<usize>> {
+        self.pending.clone()
+    }
+
+    fn scan(&mut self, c: char) {
+        self.scan = match (self.scan, c) {
+            (Scan::Code, '(') | (Scan::Code, '[') => { self.depth += 1; Scan::Code },
+            (Scan::Code, ')') | (Scan::Code, ']') => { self.depth = self.depth.saturating_sub(1); Scan::Code },
+            (Scan::Code, '"') => Scan::Str,
+            (Scan::Code, ';') => Scan::Comment,
+            (Scan::Code, '#') => Scan::Hash,
+            (Scan::Hash, '\\') => Scan::CharLit,
+            (Scan::Hash, '|') => Scan::Block(1),
+            (Scan::Hash, _) => { self.scan = Scan::Code; return self.scan(c); },
+            (Scan::Str, '\\') => Scan::Escape,
+            (Scan::Str, '"') | (Scan::CharLit, _) | (Scan::Comment, '\n') => Scan::Code,
+            (Scan::Escape, _) => Scan::Str,
+            (Scan::BlockHash(depth), '|') => Scan::Block(depth + 1),
+            (Scan::Block(depth), '|') | (Scan::BlockBar(depth), '|') => Scan::BlockBar(depth),
+            (Scan::BlockBar(1), '#') => Scan::Code,
+            (Scan::BlockBar(depth), '#') => Scan::Block(depth - 1),
+            (Scan::Block(depth), '#') | (Scan::BlockHash(depth), '#') => Scan::BlockHash(depth),
+            (Scan::Block(depth), _) | (Scan::BlockBar(depth), _) | (Scan::BlockHash(depth), _) => Scan::Block(depth),
+            (scan, _) => scan
+        };
+    }
+
+    fn complete(&self) -> bool {
+        self.depth == 0 && self.scan == Scan::Code
+    }
+}
+
+impl<I: Iterator<Item = Result<char, io::Error>>, F: FnMut()> Iterator for Balanced<I, F> {
+    type Item = Result<char, io::Error>;
+
+    fn next(&mut self) -> Option<Result<char, io::Error>> {
+        if self.buffer.is_empty() {
+            while let Some(next) = self.chars.next() {
+                let c = match next {
+                    Ok(c) => c,
+                    Err(e) => { self.buffer.push_back(Err(e)); break; }
+                };
+                self.buffer.push_back(Ok(c));
+                self.scan(c);
+                if c == '\n' {
+                    if self.complete() {
+                        break;
+                    }
+                    (self.prompt)();
+                }
+            }
+        }
+        let next = self.buffer.pop_front();
+        self.pending.set(self.buffer.len());
+        next
+    }
+}
+
 impl From<io::Error> for ReadError {
     fn from(err: io::Error) -> ReadError {
         ReadError::Io(err)
@@ -31,284 +201,3015 @@ impl fmt::Display for ReadError {
             ReadError::Io(ref err) => write!(f, "IO error: {}", err),
             ReadError::Parse(ref err) => write!(f, "Parse error: {}", err),
             ReadError::Invalid(ref err) => write!(f, "Invalid input: {}", err),
+            ReadError::UnexpectedCloseParen(Some(pos)) =>
+                write!(f, "Unexpected ')' at line {}, column {}", pos.line, pos.column),
+            ReadError::UnexpectedCloseParen(None) => write!(f, "Unexpected ')'"),
+            ReadError::NestingLimit(max) => write!(f, "Forms nested deeper than {}", max),
+            ReadError::UnexpectedEof => write!(f, "Unexpected end of file in the middle of an expression"),
             ReadError::Eof => write!(f, "End of file")
         }
     }
 }
 
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ReadError::Io(ref err) => Some(err),
+            ReadError::Parse(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// Reads, evaluates and prints expressions from stdin until the input ends.
+/// While an expression is unfinished at the end of a line, `...` is shown
+/// on stdout to ask for more.
+pub fn repl(input: &mut dyn Iterator<Item = Result<char, io::Error>>) {
+    let mut env = Environment::with_builtins();
+    run(input, Shared::new(io::stdout()), &mut env).expect("Couldn't write to stdout");
+}
 
-pub fn repl(input: &mut Iterator<Item = Result<char, io::Error>>) {
-    let peekable = &mut input.peekable();
-    let mut env = Environment::new();
-    loop {
-        let expr = read_expr(peekable);
-        match expr {
-            Ok(expr) => match expr.eval(&mut env) {
-                Ok(val) => println!("{}", val),
-                Err(e) => println!("Error: {}", e)
-            },
-            Err(ReadError::Eof) => return,
-            Err(e) => println!("Error: {}", e)
+/// A read-eval-print loop over any input and output, such as a socket.
+///
+/// ```
+/// use lust::lisp::read::Repl;
+///
+/// let out = Repl::new("(+ 1 2)\n".as_bytes(), vec![]).run().unwrap();
+/// assert_eq!("3\n", String::from_utf8(out).unwrap());
+/// ```
+pub struct Repl<R, W> {
+    input: Shared<R>,
+    output: Shared<W>,
+    env: Environment
+}
+
+impl<R: BufRead + Send + 'static, W: Write + Send + 'static> Repl<R, W> {
+    /// A REPL reading UTF-8 source from `input` and writing results,
+    /// errors, prompts and what the program prints to `output`, in an
+    /// environment with the builtins. `read-line` reads the lines of
+    /// `input` after the expression calling it.
+    pub fn new(input: R, output: W) -> Repl<R, W> {
+        Repl {input: Shared::new(input), output: Shared::new(output), env: Environment::with_builtins()}
+    }
+
+    /// Evaluates in `env` instead, whose input and output are pointed at
+    /// the REPL's when it runs.
+    pub fn environment(mut self, env: Environment) -> Repl<R, W> {
+        self.env = env;
+        self
+    }
+
+    /// Runs until the input ends, failing only if the output can't be
+    /// written to, and hands back the output.
+    pub fn run(mut self) -> io::Result<W> {
+        // A byte at a time on both sides, so that neither reads ahead what
+        // the other should get.
+        self.env.set_input(Box::new(BufReader::with_capacity(1, self.input.clone())));
+        self.env.set_output(Box::new(self.output.clone()));
+        let source = BufReader::with_capacity(1, self.input);
+        run(&mut Utf8Chars::new(source), self.output.clone(), &mut self.env)?;
+        drop(self.env);
+        Ok(self.output.into_inner())
+    }
+}
+
+/// A reader or writer used in turn by more than one owner, such as a REPL
+/// and the environment it evaluates in.
+struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Shared<T> {
+    fn new(inner: T) -> Shared<T> {
+        Shared(Arc::new(Mutex::new(inner)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// What is shared, once nothing else holds on to it.
+    fn into_inner(self) -> T {
+        match Arc::try_unwrap(self.0) {
+            Ok(inner) => inner.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(_) => panic!("Still shared")
         }
     }
 }
 
-pub fn read_expr(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>)
-    -> Result<Box<Expression>, ReadError>
-{
-    let c = match input.peek() {
-        Some(&Ok(ref c)) => Some(*c),
-        Some(&Err(_)) => None,
-        None => return Err(ReadError::Eof)
-    };
-    match c {
-        Some(c) => match c {
-            '(' => {input.next();
-                    return Ok(Box::new(Call::new(try!(read_function_name(input)),
-                                                  try!(read_function_params(input)))))
-                   },
-            '0'...'9'|'+'|'-' => Ok(Box::new(Literal::new(try!(read_number(input))))),
-            ' '|'\n'|'\r' => {input.next(); Ok(try!(read_expr(input))) },
-            _ => read_reference(input)
-        },
-        None => return Err(From::from(input.next().expect("Input disappeared!").err().expect("Error disappeared!")))
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared(self.0.clone())
     }
 }
 
-pub fn read_reference(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>)
-    -> Result<Box<Expression>, ReadError>
+impl<T: Read> Read for Shared<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<T: Write> Write for Shared<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+fn run<W: Write>(input: &mut dyn Iterator<Item = Result<char, io::Error>>, mut output: Shared<W>, env: &mut Environment)
+    -> io::Result<()>
 {
-    let sym: String = try!(read_symbol(input));
-    Ok(Box::new(Reference::new(&sym)))
-}
-
-pub fn read_function_name(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>) -> Result<Box<Function>, ReadError> {
-    let name = try!(read_symbol(input));
-    match name.as_ref() {
-        "+" => return Ok(Box::new(Add::new())),
-        "if" => return Ok(Box::new(If::new())),
-        "set" => return Ok(Box::new(Set::new())),
-        _ => Err(ReadError::Invalid(format!("Unknown function '{}'", name)))
-    }
-}
-
-pub fn read_symbol(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>) -> Result<String, ReadError> {
-    let mut name = String::new();
-    for c in input {
-        match try!(c) {
-            ' '|')'|'\n'|'\r' => break,
-            c => name.push(c)
-        }
-    }
-
-    return Ok(name);
-}
-
-macro_rules! try_peek {
-    ($expr:expr) => ({{let stupid_rust = {
-                          let peek = $expr.peek();
-                          match peek {
-                              Some(&Result::Ok(ref val)) => Some(*val),
-                              Some(&Result::Err(_)) => None,
-                              None => None
-                          }
-                      };
-                      match stupid_rust {
-                          Some(c) => Some(c),
-                          None => {
-                              match $expr.next() {
-                                  Some(Err(e)) => return Err(From::from(e)),
-                                  None => None,
-                                  Some(Ok(_)) => panic!("peek and next disagree")
-                              }
-                          }
-                      }
-                      }}
-                    )
-}
-
-pub fn read_function_params(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>) -> Result<Vec<Box<Expression>>, ReadError> {
-    let mut params: Vec<Box<Expression>> = vec![];
-    let mut acc = String::new();
+    let mut prompts = output.clone();
+    // An output that fails here fails again on the next result.
+    let balanced = Balanced::new(input, move || { let _ = write!(prompts, "... ").and_then(|()| prompts.flush()); });
+    let output: &mut dyn Write = &mut output;
+    let pending = balanced.pending();
+    let mut tracked = Tracked::new(balanced);
+    let pos = tracked.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut tracked;
+    let peekable = &mut input.peekable();
     loop {
-        let c = match try_peek!(input) {
-            Some(c) => c,
-            None => break
-        };
-        acc.push(c);
-        println!("Reading param starting with {}", c);
-        match c {
-            '0'...'9'|'-' => params.push(Box::new(Literal::new(try!(read_number(input))))),
-            '(' => params.push(try!(read_expr(input))),
-            ' '|'\n'|'\r'|'\t' => { input.next(); continue },
-            ')' => { input.next(); return Ok(params) },
-            _ => { params.push(try!(read_reference(input))) }
+        if let Some(command) = read_command(peekable) {
+            if !run_command(&command, output, env)? {
+                return Ok(());
+            }
+            output.flush()?;
+            continue;
+        }
+        let result = read_and_eval(peekable, &pos, env);
+        for warning in env.take_warnings() {
+            writeln!(output, "Warning: {}", warning)?;
         }
+        match result {
+            Ok(Value::Unit) => (),
+            Ok(val) => writeln!(output, "{}", val)?,
+            Err(LustError::Read(ReadError::Eof)) => return Ok(()),
+            Err(e) => {
+                writeln!(output, "Error: {}", e)?;
+                if let LustError::Read(ReadError::Invalid(_)) | LustError::Read(ReadError::Parse(_)) = e {
+                    skip_pending(peekable, &pending);
+                }
+            }
+        }
+        output.flush()?;
     }
-    Err(ReadError::Eof)
 }
 
-pub fn read_number(input: &mut Peekable<&mut Iterator<Item = Result<char, io::Error>>>) -> Result<i64, ReadError> {
-    let mut buf = String::new();
-    loop {
-        let c = try_peek!(input);
+/// The rest of the line, if the next thing in the input is a `:` that
+/// starts a REPL command instead of an expression.
+fn read_command(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Option<String> {
+    while let Some(&Ok(c)) = input.peek() {
         match c {
-            Some(c @ '-')       => { buf.push(c); input.next(); if buf.len() > 1 { return Err(ReadError::Invalid(format!("invalid number {}", buf))); } },
-            Some(c @ '0'...'9') => { buf.push(c); input.next(); },
-            Some(' ')       => break,
-            Some(')')       => break,
-            None            => { input.next(); return Err(ReadError::Eof) },
-            Some(c)         => { input.next(); return Err(ReadError::Invalid(format!("Invalid input '{}'", c))) }
+            c if c.is_whitespace() => { input.next(); },
+            ':' => break,
+            _ => return None
+        }
+    }
+    input.peek()?.as_ref().ok()?;
+    let mut command = String::new();
+    while let Some(Ok(c)) = input.next() {
+        if c == '\n' {
+            break;
         }
+        command.push(c);
     }
-    Ok(try!(buf.parse()))
+    Some(command.trim_end().to_string())
 }
 
+/// Runs a REPL command, returning whether to go on.
+fn run_command(command: &str, output: &mut dyn Write, env: &mut Environment) -> io::Result<bool> {
+    match command {
+        ":help" => {
+            writeln!(output, ":help  List these commands\n:env   Show what each name is bound to\n:quit  Stop")?;
+            writeln!(output, "Calls other than tail calls nest at most {} deep.", env.max_depth())?;
+        },
+        ":env" => for (name, val) in env.bindings() {
+            // Leave out the builtins, which are all bound to their names.
+            if !matches!(val, Value::Builtin(func) if func.name() == name) {
+                writeln!(output, "{} = {}", name, val)?;
+            }
+        },
+        ":quit" => return Ok(false),
+        _ => writeln!(output, "Unknown command {}, see :help", command)?
+    }
+    Ok(true)
+}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::iter::{Iterator,Map};
-    use std::str::{Chars};
-    use std::io::Error;
+/// Throws away the rest of the lines that held the form that failed to
+/// read, so that reading starts afresh on the next one instead of in the
+/// middle of the broken form.
+fn skip_pending(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, pending: &Cell<usize>) {
+    while pending.get() > 0 {
+        input.next();
+    }
+}
 
-    fn char_to_result(c: char) -> Result<char, Error> {
-        Ok(c)
+fn read_and_eval(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, pos: &Cell<Position>,
+                 env: &mut Environment)
+    -> Result<Value, LustError>
+{
+    let expr = read_expr_at(input, pos).map_err(|err| err.at(pos.get()))?;
+    Ok(eval(&expr, env)?)
+}
+
+/// Reads and evaluates the single expression in `src`.
+///
+/// ```
+/// use lust::lisp::read::{eval_str, Environment, Value};
+///
+/// let mut env = Environment::with_builtins();
+/// eval_str("(define (double x) (+ x x))", &mut env).unwrap();
+/// assert_eq!(Value::Int(6), eval_str("(double 3)", &mut env).unwrap());
+/// assert!(eval_str("(double", &mut env).is_err());
+/// ```
+pub fn eval_str(src: &str, env: &mut Environment) -> Result<Value, LustError> {
+    let mut chars = Tracked::new(src.chars().map(Ok));
+    let pos = chars.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut chars;
+    let peekable = &mut input.peekable();
+    let expr = read_expr_at(peekable, &pos).map_err(|err| err.at(pos.get()))?;
+    match next_token(peekable)? {
+        None => (),
+        Some(Token::RParen) | Some(Token::RBracket) =>
+            return Err(From::from(ReadError::UnexpectedCloseParen(Some(pos.get())))),
+        Some(token) => return Err(From::from(ReadError::Invalid(format!("Unexpected {:?} after expression", token))))
     }
+    Ok(eval(&expr, env)?)
+}
 
-    fn input(s: &'static str) -> Map<Chars<'static>, fn(char)->Result<char, Error>> {
-        return s.chars().map(char_to_result);
+/// Reads and evaluates every expression in `src`, returning the value of
+/// the last one, or unit if there are none.
+pub fn eval_all(src: &str, env: &mut Environment) -> Result<Value, LustError> {
+    let mut chars = Tracked::new(src.chars().map(Ok));
+    let pos = chars.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut chars;
+    let peekable = &mut input.peekable();
+    let mut last = Value::Unit;
+    loop {
+        match read_expr_at(peekable, &pos) {
+            Ok(expr) => last = eval(&expr, env)?,
+            Err(ReadError::Eof) => return Ok(last),
+            Err(err) => return Err(From::from(err.at(pos.get())))
+        }
     }
+}
 
-    fn iterator<'a, T>(iterator: &'a mut Iterator<Item=T>) -> &'a mut Iterator<Item=T> {
-        iterator
+/// Reads every expression in `src` and evaluates them in order as
+/// `eval_all` does, but first declares every name defined at the top level,
+/// so that one used before its definition fails with `NotYetDefined`
+/// rather than `UndefinedName`.
+pub fn eval_all_hoisted(src: &str, env: &mut Environment) -> Result<Value, LustError> {
+    let exprs = read_all(src)?;
+    for expr in &exprs {
+        if let Ast::Define(ref name, _) = *expr {
+            env.declare(name);
+        }
+    }
+    let mut last = Value::Unit;
+    for expr in &exprs {
+        last = eval(expr, env)?;
     }
+    Ok(last)
+}
 
-    #[test]
-    fn test_read_add_function() {
-        let mut m = input("+");
-        let peekable = &mut iterator(&mut m).peekable();
-        match read_function_name(peekable) {
-            Err(_) => panic!("Didn't get function"),
-            _ => ()
-        };
+/// Reads every expression in `src`, then evaluates them in order and
+/// returns their values, or the first error. Runs of pure expressions, as
+/// told by `Ast::is_pure`, are evaluated at the same time on a few threads,
+/// in forks of `env` that don't trace. The rest, and all of them when fuel
+/// is limited, are evaluated one at a time in `env` itself.
+pub fn eval_program_parallel(src: &str, env: &mut Environment) -> Result<Vec<Value>, LustError> {
+    let exprs = read_all(src)?;
+    let mut values = Vec::with_capacity(exprs.len());
+    let mut start = 0;
+    while start < exprs.len() {
+        let pure = exprs[start..].iter().take_while(|expr| env.fuel().is_none() && expr.is_pure(env)).count();
+        if pure < 2 {
+            values.push(eval(&exprs[start], env)?);
+            start += 1;
+            continue;
+        }
+        for result in eval_parallel(&exprs[start..start + pure], env) {
+            values.push(result?);
+        }
+        start += pure;
     }
+    Ok(values)
+}
 
-    #[test]
-    fn test_read_unknown_function() {
-        let mut m = input("apa");
-        let peekable = &mut iterator(&mut m).peekable();
-        match read_function_name(peekable) {
-            Ok(_) => panic!("Should get error"),
-            _ => ()
+/// The results of `exprs`, evaluated on up to a thread per core, each with
+/// its own fork of `env`.
+fn eval_parallel(exprs: &[Ast], env: &Environment) -> Vec<Result<Value, EvalError>> {
+    let threads = thread::available_parallelism().map_or(1, usize::from).min(exprs.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<Value, EvalError>>> = exprs.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let mut fork = env.fork();
+            let next = &next;
+            scope.spawn(move || {
+                let mut done = vec![];
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= exprs.len() {
+                        return done;
+                    }
+                    done.push((i, eval(&exprs[i], &mut fork)));
+                }
+            })
+        }).collect();
+        for worker in workers {
+            let done = worker.join().unwrap_or_else(|panic| panic::resume_unwind(panic));
+            for (i, result) in done {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results.into_iter().map(|result| result.expect("Every expression is evaluated")).collect()
+}
+
+/// Reads every expression in `src` without evaluating any of them.
+pub fn read_all(src: &str) -> Result<Vec<Ast>, ReadError> {
+    let mut chars = Tracked::new(src.chars().map(Ok));
+    let pos = chars.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut chars;
+    let peekable = &mut input.peekable();
+    let mut exprs = vec![];
+    loop {
+        match read_expr_at(peekable, &pos) {
+            Ok(expr) => exprs.push(expr),
+            Err(ReadError::Eof) => return Ok(exprs),
+            Err(err) => return Err(err.at(pos.get()))
         }
     }
+}
 
-    #[test]
-    fn test_read_number() {
-        let mut m = input("14 ");
-        let peekable = &mut iterator(&mut m).peekable();
-        let val = read_number(peekable).unwrap();
+/// Reads the single expression in `src`.
+pub fn read_str(src: &str) -> Result<Ast, ReadError> {
+    let mut exprs = read_all(src)?;
+    if exprs.len() != 1 {
+        return Err(ReadError::Invalid(format!("Expected one expression, got {}", exprs.len())));
+    }
+    Ok(exprs.pop().expect("Expression disappeared"))
+}
 
-        assert_eq!(14, val);
+/// Reads the single datum in `src`, as `quote` would.
+pub fn read_datum_str(src: &str) -> Result<Value, ReadError> {
+    let mut chars = Tracked::new(src.chars().map(Ok));
+    let pos = chars.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut chars;
+    let peekable = &mut input.peekable();
+    let datum = read_datum(peekable).map_err(|err| err.at(pos.get()))?;
+    match next_token(peekable)? {
+        None => Ok(datum),
+        Some(token) => Err(ReadError::Invalid(format!("Unexpected {:?} after datum", token)))
     }
+}
 
-    #[test]
-    fn test_read_negative_number() {
-        let mut m = input("-14 ");
-        let peekable = &mut iterator(&mut m).peekable();
-        let val = read_number(peekable).unwrap();
+/// Output that can still be read after it's been handed to an environment.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
 
-        assert_eq!(-14, val);
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).write(buf)
     }
 
-    #[test]
-    fn test_read_number_right_paren() {
-        let mut m = input("2701)");
-        let peekable = &mut iterator(&mut m).peekable();
-        let val = read_number(peekable).unwrap();
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Evaluates the program in `src` in a new environment, for hosts without
+/// stdin, stdout or stderr. What the program writes to its output or error
+/// output, `print` and `time` among them, is captured, and `read-line`
+/// finds its input empty. Returns the captured text followed by the value
+/// of its last expression, or by the error that stopped it, the way the
+/// REPL would show them.
+pub fn eval_program(src: &str) -> String {
+    let mut env = Environment::with_builtins();
+    let output = Captured::default();
+    env.set_output(Box::new(output.clone()));
+    env.set_error_output(Box::new(output.clone()));
+    env.set_input(Box::new(io::empty()));
+    let result = eval_all(src, &mut env);
+    let mut text = String::from_utf8_lossy(&output.0.lock().unwrap_or_else(PoisonError::into_inner)).into_owned();
+    match result {
+        Ok(Value::Unit) => (),
+        Ok(val) => text.push_str(&val.to_string()),
+        Err(err) => text.push_str(&format!("Error: {}", err))
+    }
+    text
+}
+
+/// Reads and evaluates every expression in the file at `path`, as the
+/// `load` builtin does.
+pub fn load(path: &str, env: &mut Environment) -> Result<(), EvalError> {
+    let failed = |err| EvalError::Load(String::from(path), err);
+    let src = fs::read_to_string(path).map_err(|err| failed(ReadError::Io(err)))?;
+    env.start_load(fs::canonicalize(path).map_err(|err| failed(ReadError::Io(err)))?)?;
+    let mut chars = Tracked::new(src.chars().map(Ok));
+    let pos = chars.position();
+    let input: &mut dyn Iterator<Item = Result<char, io::Error>> = &mut chars;
+    let peekable = &mut input.peekable();
+    let result = loop {
+        match read_expr_at(peekable, &pos) {
+            Ok(expr) => if let Err(err) = eval(&expr, env) {
+                break Err(err);
+            },
+            Err(ReadError::Eof) => break Ok(()),
+            Err(err) => break Err(failed(err.at(pos.get())))
+        }
+    };
+    env.finish_load();
+    result
+}
+
+/// Writes `expr` back as source text that `read_expr` turns into the same
+/// expression.
+pub fn write_sexp(expr: &Ast) -> String {
+    expr.to_string()
+}
+
+/// How deeply forms may nest before reading fails with
+/// `ReadError::NestingLimit`. The parser itself doesn't recurse, but
+/// evaluating and dropping an expression does.
+pub const DEFAULT_MAX_NESTING: usize = 1000;
+
+pub fn read_expr(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>)
+    -> Result<Ast, ReadError>
+{
+    read_expr_with_max_nesting(input, DEFAULT_MAX_NESTING)
+}
+
+/// Like `read_expr`, but with a different limit on how deeply forms nest.
+pub fn read_expr_with_max_nesting(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, max_nesting: usize)
+    -> Result<Ast, ReadError>
+{
+    parse(vec![], None, input, max_nesting, None)
+}
+
+/// Like `read_expr`, but with where calls and their arguments start taken
+/// from `pos`, which follows the position of the input, for errors to
+/// point at.
+pub fn read_expr_at(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, pos: &Cell<Position>)
+    -> Result<Ast, ReadError>
+{
+    parse(vec![], None, input, DEFAULT_MAX_NESTING, Some(pos))
+}
+
+/// Turns `token`, and whatever the rest of the input adds to it, into an
+/// expression.
+pub fn parse_expr(token: Token, input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>)
+    -> Result<Ast, ReadError>
+{
+    parse(vec![], Some(token), input, DEFAULT_MAX_NESTING, None)
+}
 
-        assert_eq!(2701, val);
+/// The head of a parenthesized form, which decides how its parameters are
+/// turned into an `Ast` node: either a special form, or an expression
+/// evaluating to the function to call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Form {
+    Call(Ast),
+    If,
+    Set,
+    Define,
+    Lambda,
+    Quote,
+    Begin,
+    While,
+    Cond,
+    And,
+    Or,
+    Try,
+    Let(LetKind),
+    When,
+    Unless,
+    Case,
+    Delay,
+    Time,
+    DefineSyntax,
+    DefineConstant,
+    Unset,
+    Finally,
+    Do,
+    Assert,
+    AssertEqual
+}
 
-        let next = peekable.next().expect("Right paren was consumed");
-        assert_eq!(')', next.unwrap());
+fn special_form(name: &str) -> Option<Form> {
+    match name {
+        "if" => Some(Form::If),
+        "set" => Some(Form::Set),
+        "define" => Some(Form::Define),
+        "lambda" => Some(Form::Lambda),
+        "quote" => Some(Form::Quote),
+        "begin" => Some(Form::Begin),
+        "while" => Some(Form::While),
+        "cond" => Some(Form::Cond),
+        "and" => Some(Form::And),
+        "or" => Some(Form::Or),
+        "try" => Some(Form::Try),
+        "let" => Some(Form::Let(LetKind::Parallel)),
+        "let*" => Some(Form::Let(LetKind::Sequential)),
+        "letrec" => Some(Form::Let(LetKind::Recursive)),
+        "when" => Some(Form::When),
+        "unless" => Some(Form::Unless),
+        "case" => Some(Form::Case),
+        "delay" => Some(Form::Delay),
+        "time" => Some(Form::Time),
+        "define-syntax" => Some(Form::DefineSyntax),
+        "define-constant" => Some(Form::DefineConstant),
+        "unset!" => Some(Form::Unset),
+        "finally" => Some(Form::Finally),
+        "do" => Some(Form::Do),
+        "assert" => Some(Form::Assert),
+        "assert-equal" => Some(Form::AssertEqual),
+        _ => None
     }
+}
 
-    #[test]
-    fn test_read_number_params() {
-        let mut env = Environment::new();
-        let mut m = input("1 2)");
-        let peekable = &mut iterator(&mut m).peekable();
-        let params = read_function_params(peekable).unwrap();
-        assert_eq!(2, params.len());
-        assert_eq!(1, params[0].eval(&mut env).unwrap());
-        assert_eq!(2, params[1].eval(&mut env).unwrap());
+/// What `define` is binding: a name, or a `(name params...)` header.
+enum Target {
+    Expr(Ast),
+    Header(Params)
+}
+
+/// A parameter list as it's read, with the parts of a `Lambda`.
+#[derive(Default)]
+struct Params {
+    names: Vec<String>,
+    defaults: Vec<Ast>,
+    keys: Vec<(String, Option<Ast>)>
+}
+
+impl Params {
+    /// Adds a parameter, with its default if it has one.
+    fn push(&mut self, name: String, default: Option<Ast>) -> Result<(), ReadError> {
+        if let Some(key) = keyword(&name) {
+            self.keys.push((String::from(key), default));
+            return Ok(());
+        }
+        if !self.keys.is_empty() {
+            return Err(ReadError::Invalid(format!("Parameter {} after keyword parameters", name)));
+        }
+        match default {
+            Some(default) => self.defaults.push(default),
+            None if !self.defaults.is_empty() =>
+                return Err(ReadError::Invalid(format!("Parameter {} without a default after one with", name))),
+            None => ()
+        }
+        self.names.push(name);
+        Ok(())
     }
 
-    #[test]
-    fn test_read_expr() {
-        let mut env = Environment::new();
-        let mut m = input("(+ 1 2)");
-        let peekable = &mut iterator(&mut m).peekable();
+    fn into_lambda(self, body: Vec<Ast>) -> Lambda {
+        Lambda {params: self.names, defaults: self.defaults, keys: self.keys, body}
+    }
+}
 
-        let expr = read_expr(peekable).unwrap();
-        assert_eq!(3, expr.eval(&mut env).unwrap());
+/// A form that is still being read. Nested forms are kept on an explicit
+/// stack of these rather than on the native one, so deep input can't
+/// overflow it.
+enum Frame {
+    /// Just after a `(` where an expression is expected, and where the `(`
+    /// is if known.
+    Head(Option<Position>),
+    /// A call or a special form whose parts are all expressions, and for a
+    /// call, where it and its arguments so far start if known.
+    Form(Form, Vec<Ast>, Option<Span>),
+    Lambda(Option<Params>, Vec<Ast>),
+    Define(Option<Target>, Vec<Ast>),
+    /// A parameter list or `define` header.
+    Names(Params),
+    Cond(Vec<(Ast, Vec<Ast>)>),
+    Clause(Vec<Ast>),
+    Try(Option<Ast>, Option<(String, Vec<Ast>)>),
+    /// `(catch name handler...)`, with whether `catch` has been read.
+    Catch(bool, Option<String>, Vec<Ast>),
+    /// `(quote datum)`.
+    Quote(Option<Value>),
+    /// After a `'`, waiting for the datum it quotes.
+    Quoted,
+    /// A list inside a quoted datum.
+    Datum(Vec<Value>),
+    /// A list inside a quoted datum after the `.` before its last element,
+    /// and that element once read.
+    Dotted(Vec<Value>, Option<Value>),
+    /// After a backquote, waiting for the template it quotes.
+    Quasiquoted,
+    /// A list inside a template.
+    Template(Vec<Part>),
+    /// A list inside a template after the `.`, and its last part once read.
+    DottedTemplate(Vec<Part>, Option<Part>),
+    /// After a `,`, or `,@` if splicing, waiting for the expression.
+    Unquote(bool),
+    Let(LetKind, Option<Vec<(String, Ast)>>, Vec<Ast>),
+    /// `(let name (bindings...) body...)`, a loop calling `name`.
+    NamedLet(String, Option<Vec<(String, Ast)>>, Vec<Ast>),
+    /// The list of bindings in a `let`.
+    Bindings(Vec<(String, Ast)>),
+    /// `(name value)` in a `let`.
+    Binding(Option<String>, Option<Ast>),
+    Case(Option<Ast>, Vec<(Option<Vec<Value>>, Vec<Ast>)>),
+    /// `((keys...) body...)` or `(else body...)`, with whether the keys
+    /// have been read.
+    CaseClause(bool, Option<Vec<Value>>, Vec<Ast>),
+    /// `(do (steps...) (test result...) body...)`.
+    Do(Option<Vec<(String, Ast, Ast)>>, Option<(Ast, Vec<Ast>)>, Vec<Ast>),
+    /// The list of loop variables in a `do`.
+    Steps(Vec<(String, Ast, Ast)>),
+    /// `(name init step)` in a `do`, where the step may be left out.
+    Step(Option<String>, Vec<Ast>)
+}
+
+/// Something finished, to be handed to the frame it belongs to.
+enum Done {
+    Expr(Ast),
+    Value(Value),
+    Names(Params),
+    Clause(Ast, Vec<Ast>),
+    Catch(String, Vec<Ast>),
+    Bindings(Vec<(String, Ast)>),
+    Binding(String, Ast),
+    CaseClause(Option<Vec<Value>>, Vec<Ast>),
+    Steps(Vec<(String, Ast, Ast)>),
+    Step(String, Ast, Ast),
+    Part(Part)
+}
+
+/// Part of a template: data that stands for itself, or an expression whose
+/// value goes in its place or, if spliced, whose elements do.
+enum Part {
+    Datum(Value),
+    Unquote(Ast),
+    Splice(Ast)
+}
+
+impl Part {
+    /// The expression building what the part stands for.
+    fn into_expr(self) -> Result<Ast, ReadError> {
+        match self {
+            Part::Datum(val) => Ok(Ast::Literal(val)),
+            Part::Unquote(expr) => Ok(expr),
+            Part::Splice(_) => Err(ReadError::Invalid(String::from(",@ outside a list")))
+        }
+    }
+}
+
+/// The part standing for a list template, which is data if nothing in it
+/// is unquoted. Otherwise it's an expression appending the runs of data,
+/// the unquoted values each in a list of their own, and the spliced lists.
+fn template_list(parts: Vec<Part>) -> Part {
+    if parts.iter().all(|part| matches!(*part, Part::Datum(_))) {
+        let items = parts.into_iter().map(|part| match part {
+            Part::Datum(val) => val,
+            _ => unreachable!("all parts are data")
+        });
+        return Part::Datum(Value::list(items.collect()));
+    }
+    let builtin = |func| Box::new(Ast::Literal(Value::Builtin(func)));
+    let mut lists = vec![];
+    let mut data = vec![];
+    for part in parts {
+        match part {
+            Part::Datum(val) => { data.push(val); continue },
+            _ if !data.is_empty() => lists.push(Ast::Literal(Value::list(data.split_off(0)))),
+            _ => ()
+        }
+        lists.push(match part {
+            Part::Unquote(expr) => Ast::Call { func: builtin(FuncId::Cons), args: vec![expr, Ast::Literal(Value::Nil)], span: None },
+            Part::Splice(expr) => expr,
+            Part::Datum(_) => unreachable!("data is collected above")
+        });
+    }
+    if !data.is_empty() {
+        lists.push(Ast::Literal(Value::list(data)));
     }
+    Part::Unquote(Ast::Call { func: builtin(FuncId::Append), args: lists, span: None })
+}
 
-    #[test]
-    fn test_read_nested_expr() {
-        let mut env = Environment::new();
-        let mut m = input("(+ 1 (+ 1 1))");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable);
-        assert_eq!(3, expr.unwrap().eval(&mut env).unwrap());
+/// The part standing for a dotted list template, which is data if nothing
+/// in it is unquoted. Otherwise it's an expression consing each part onto
+/// the rest, ending with the last part. Nothing can be spliced into it,
+/// since what it would be appended to needn't be a list.
+fn template_dotted(parts: Vec<Part>, tail: Part) -> Result<Part, ReadError> {
+    let data = |part: &Part| matches!(*part, Part::Datum(_));
+    if data(&tail) && parts.iter().all(data) {
+        let mut items = parts.into_iter().chain(Some(tail)).map(|part| match part {
+            Part::Datum(val) => val,
+            _ => unreachable!("all parts are data")
+        }).collect::<Vec<_>>();
+        let tail = items.pop().expect("The tail disappeared");
+        return Ok(Part::Datum(Value::dotted(items, tail)));
     }
+    let mut list = tail.into_expr()?;
+    for part in parts.into_iter().rev() {
+        let car = match part {
+            Part::Datum(val) => Ast::Literal(val),
+            Part::Unquote(expr) => expr,
+            Part::Splice(_) => return Err(ReadError::Invalid(String::from(",@ in a dotted list")))
+        };
+        let func = Box::new(Ast::Literal(Value::Builtin(FuncId::Cons)));
+        list = Ast::Call { func, args: vec![car, list], span: None };
+    }
+    Ok(Part::Unquote(list))
+}
 
-    #[test]
-    fn test_read_if_nonzero() {
-        let mut env = Environment::new();
-        let mut m = input("(if (+ 1 1) 1 2)");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable).unwrap();
-        assert_eq!(1, expr.eval(&mut env).unwrap());
+impl Frame {
+    fn for_form(form: Form) -> Frame {
+        match form {
+            Form::Lambda => Frame::Lambda(None, vec![]),
+            Form::Define => Frame::Define(None, vec![]),
+            Form::Cond => Frame::Cond(vec![]),
+            Form::Try => Frame::Try(None, None),
+            Form::Quote => Frame::Quote(None),
+            Form::Let(kind) => Frame::Let(kind, None, vec![]),
+            Form::Case => Frame::Case(None, vec![]),
+            Form::Do => Frame::Do(None, None, vec![]),
+            form => Frame::Form(form, vec![], None)
+        }
     }
 
-    #[test]
-    fn test_read_if_zero() {
-        let mut env = Environment::new();
-        let mut m = input("(if (+ 1 -1) 1 (+ 2 3))");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable).unwrap();
-        assert_eq!(5, expr.eval(&mut env).unwrap());
+    /// Whether the frame's parts are quoted data rather than expressions.
+    fn reads_data(&self) -> bool {
+        matches!(*self, Frame::Quote(_) | Frame::Quoted | Frame::Datum(_) | Frame::Dotted(..) | Frame::Quasiquoted
+                        | Frame::Template(_) | Frame::DottedTemplate(..))
     }
 
-    #[test]
-    fn test_read_variable() {
-        let mut env = Environment::new();
-        env.set("a", 3);
-        let mut m = input("a");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable).unwrap();
-        assert_eq!(3, expr.eval(&mut env).unwrap());
+    /// Whether the frame's parts are in a template, where lists may have
+    /// unquoted parts.
+    fn reads_template(&self) -> bool {
+        matches!(*self, Frame::Quasiquoted | Frame::Template(_) | Frame::DottedTemplate(..))
     }
 
-    #[test]
-    fn test_read_variable_argument() {
-        let mut env = Environment::new();
-        env.set("a", 3);
-        let mut m = input("(+ a 1)");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable).unwrap();
-        assert_eq!(4, expr.eval(&mut env).unwrap());
+    /// Builds what the frame stands for once its closing paren is read.
+    fn close(self) -> Result<Done, ReadError> {
+        match self {
+            Frame::Head(_) => Err(ReadError::Invalid(String::from("Empty form ()"))),
+            Frame::Form(Form::Call(func), args, span) =>
+                Ok(Done::Expr(Ast::Call {func: Box::new(func), args, span: span.map(Box::new)})),
+            Frame::Form(form, params, _) => Ok(Done::Expr(build_form(form, params)?)),
+            Frame::Lambda(Some(params), body) => {
+                if body.is_empty() {
+                    return Err(ReadError::Invalid(String::from("lambda without a body")));
+                }
+                Ok(Done::Expr(Ast::Lambda(Arc::new(params.into_lambda(body)))))
+            },
+            Frame::Lambda(None, _) => Err(ReadError::Invalid(String::from("Expected parameter list, got ')'"))),
+            Frame::Define(Some(Target::Header(mut params)), body) => {
+                if params.names.len() <= params.defaults.len() {
+                    return Err(ReadError::Invalid(String::from("define without a name")));
+                }
+                if body.is_empty() {
+                    return Err(ReadError::Invalid(String::from("lambda without a body")));
+                }
+                let name = params.names.remove(0);
+                let lambda = Ast::Lambda(Arc::new(params.into_lambda(body)));
+                Ok(Done::Expr(Ast::Define(name, Box::new(lambda))))
+            },
+            Frame::Define(Some(Target::Expr(target)), mut params) => {
+                if params.len() != 1 {
+                    return Err(ReadError::Invalid(format!("define takes 2 arguments, got {}", params.len() + 1)));
+                }
+                let val = params.pop().expect("define without value");
+                match target {
+                    Ast::Reference(name) => Ok(Done::Expr(Ast::Define(name, Box::new(val)))),
+                    other => Err(ReadError::Invalid(format!("Can't define {:?}", other)))
+                }
+            },
+            Frame::Define(None, _) => Err(ReadError::Invalid(String::from("define takes 2 arguments, got 0"))),
+            Frame::Names(params) => Ok(Done::Names(params)),
+            Frame::Cond(clauses) => Ok(Done::Expr(Ast::Cond(clauses))),
+            Frame::Clause(mut clause) => {
+                if clause.is_empty() {
+                    return Err(ReadError::Invalid(String::from("Empty cond clause")));
+                }
+                let test = match clause.remove(0) {
+                    Ast::Reference(ref name) if name == "else" => Ast::Literal(Value::Bool(true)),
+                    test => test
+                };
+                Ok(Done::Clause(test, clause))
+            },
+            Frame::Try(Some(body), Some((name, handler))) => Ok(Done::Expr(Ast::Try(Box::new(body), name, handler))),
+            Frame::Try(..) => Err(ReadError::Invalid(String::from("Expected (catch name handler...) in try"))),
+            Frame::Catch(true, Some(name), handler) => {
+                if handler.is_empty() {
+                    return Err(ReadError::Invalid(String::from("catch without a handler")));
+                }
+                Ok(Done::Catch(name, handler))
+            },
+            Frame::Catch(..) => Err(ReadError::Invalid(String::from("Expected (catch name handler...) in try"))),
+            Frame::Quote(Some(datum)) => Ok(Done::Expr(Ast::Literal(datum))),
+            Frame::Quote(None) | Frame::Quoted | Frame::Quasiquoted | Frame::Unquote(_) =>
+                Err(ReadError::UnexpectedCloseParen(None)),
+            Frame::Datum(items) => Ok(Done::Value(Value::list(items))),
+            Frame::Template(parts) => Ok(Done::Part(template_list(parts))),
+            Frame::Dotted(items, Some(tail)) => Ok(Done::Value(Value::dotted(items, tail))),
+            Frame::DottedTemplate(parts, Some(tail)) => Ok(Done::Part(template_dotted(parts, tail)?)),
+            Frame::Dotted(_, None) | Frame::DottedTemplate(_, None) =>
+                Err(ReadError::Invalid(String::from("Expected an element after ."))),
+            Frame::Let(kind, Some(bindings), body) => {
+                if body.is_empty() {
+                    return Err(ReadError::Invalid(format!("{} without a body", kind.name())));
+                }
+                Ok(Done::Expr(Ast::Let(kind, bindings, body)))
+            },
+            Frame::Let(kind, None, _) => Err(ReadError::Invalid(format!("Expected bindings in {}", kind.name()))),
+            // ((letrec ((name (lambda (vars...) body...))) name) inits...)
+            Frame::NamedLet(name, Some(bindings), body) => {
+                if body.is_empty() {
+                    return Err(ReadError::Invalid(String::from("let without a body")));
+                }
+                let (params, inits) = bindings.into_iter().unzip();
+                let lambda = Ast::Lambda(Arc::new(Lambda {params, defaults: vec![], keys: vec![], body}));
+                let func = Ast::Let(LetKind::Recursive, vec![(name.clone(), lambda)], vec![Ast::Reference(name)]);
+                Ok(Done::Expr(Ast::Call {func: Box::new(func), args: inits, span: None}))
+            },
+            Frame::NamedLet(_, None, _) => Err(ReadError::Invalid(String::from("Expected bindings in let"))),
+            Frame::Bindings(bindings) => Ok(Done::Bindings(bindings)),
+            Frame::Binding(Some(name), Some(val)) => Ok(Done::Binding(name, val)),
+            Frame::Binding(..) => Err(ReadError::Invalid(String::from("Expected (name value) binding"))),
+            Frame::Case(Some(key), clauses) => Ok(Done::Expr(Ast::Case(Box::new(key), clauses))),
+            Frame::Case(None, _) => Err(ReadError::Invalid(String::from("case without a key"))),
+            Frame::CaseClause(true, keys, body) => {
+                if body.is_empty() {
+                    return Err(ReadError::Invalid(String::from("case clause without a body")));
+                }
+                Ok(Done::CaseClause(keys, body))
+            },
+            Frame::CaseClause(false, ..) => Err(ReadError::Invalid(String::from("Expected keys or else in case clause"))),
+            // ((letrec ((#do-loop (lambda (vars...)
+            //                       (if test (begin result...) (begin body... (#do-loop steps...))))))
+            //    #do-loop)
+            //  inits...)
+            // where no program can name #do-loop.
+            Frame::Do(Some(steps), Some((test, result)), mut body) => {
+                let name = String::from("#do-loop");
+                let mut params = vec![];
+                let mut inits = vec![];
+                let mut next = vec![];
+                for (param, init, step) in steps {
+                    params.push(param);
+                    inits.push(init);
+                    next.push(step);
+                }
+                body.push(Ast::Call {func: Box::new(Ast::Reference(name.clone())), args: next, span: None});
+                let loop_body = Ast::If(Box::new(test), Box::new(Ast::Begin(result)), Box::new(Ast::Begin(body)));
+                let lambda = Ast::Lambda(Arc::new(Lambda {params, defaults: vec![], keys: vec![], body: vec![loop_body]}));
+                let func = Ast::Let(LetKind::Recursive, vec![(name.clone(), lambda)], vec![Ast::Reference(name)]);
+                Ok(Done::Expr(Ast::Call {func: Box::new(func), args: inits, span: None}))
+            },
+            Frame::Do(..) => Err(ReadError::Invalid(String::from("Expected (steps...) (test result...) in do"))),
+            Frame::Steps(steps) => Ok(Done::Steps(steps)),
+            Frame::Step(Some(name), mut exprs) => {
+                if exprs.is_empty() || exprs.len() > 2 {
+                    return Err(ReadError::Invalid(String::from("Expected (name init step) in do")));
+                }
+                let step = if exprs.len() == 2 { exprs.pop().expect("step disappeared") } else { Ast::Reference(name.clone()) };
+                let init = exprs.pop().expect("init disappeared");
+                Ok(Done::Step(name, init, step))
+            },
+            Frame::Step(None, _) => Err(ReadError::Invalid(String::from("Expected (name init step) in do")))
+        }
     }
 
-    #[test]
-    fn test_read_assignment() {
-        let mut env = Environment::new();
-        let mut m = input("(set a 1)");
-        let peekable = &mut iterator(&mut m).peekable();
-        let expr = read_expr(peekable).unwrap();
-        expr.eval(&mut env);
-        assert_eq!(1, env.get("a").unwrap());
+    /// Takes a finished part, or hands it back if the frame is done too.
+    fn accept(&mut self, done: Done) -> Result<Option<Done>, ReadError> {
+        match (self, done) {
+            (frame @ &mut Frame::Head(_), Done::Expr(head)) => {
+                let span = match *frame {
+                    Frame::Head(at) => at.map(Span::new),
+                    _ => unreachable!()
+                };
+                *frame = Frame::Form(Form::Call(head), vec![], span);
+            },
+            (&mut Frame::Form(_, ref mut params, _), Done::Expr(expr)) |
+            (&mut Frame::Lambda(Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Define(Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Clause(ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Catch(true, Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Let(_, Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::NamedLet(_, Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::CaseClause(true, _, ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Do(Some(_), Some(_), ref mut params), Done::Expr(expr)) |
+            (&mut Frame::Step(Some(_), ref mut params), Done::Expr(expr)) => params.push(expr),
+            (&mut Frame::Do(ref mut steps @ None, None, _), Done::Steps(list)) => *steps = Some(list),
+            (&mut Frame::Do(Some(_), ref mut exit @ None, _), Done::Clause(test, result)) => *exit = Some((test, result)),
+            (&mut Frame::Steps(ref mut steps), Done::Step(name, init, step)) => steps.push((name, init, step)),
+            (&mut Frame::Case(ref mut key @ None, _), Done::Expr(expr)) => *key = Some(expr),
+            (&mut Frame::Case(Some(_), ref mut clauses), Done::CaseClause(keys, body)) => clauses.push((keys, body)),
+            (&mut Frame::CaseClause(ref mut read @ false, ref mut keys, _), Done::Value(list)) => {
+                let list = list.to_vec().map_err(|_| ReadError::Invalid(format!("Expected a list of keys, got {}", list)))?;
+                *keys = Some(list);
+                *read = true;
+            },
+            (&mut Frame::Let(_, ref mut bindings @ None, _), Done::Bindings(list)) |
+            (&mut Frame::NamedLet(_, ref mut bindings @ None, _), Done::Bindings(list)) => *bindings = Some(list),
+            (&mut Frame::Bindings(ref mut bindings), Done::Binding(name, val)) => bindings.push((name, val)),
+            (&mut Frame::Binding(Some(_), ref mut val @ None), Done::Expr(expr)) => *val = Some(expr),
+            (&mut Frame::Binding(Some(_), Some(_)), _) =>
+                return Err(ReadError::Invalid(String::from("Expected (name value) binding"))),
+            (&mut Frame::Lambda(ref mut params @ None, _), Done::Names(names)) => *params = Some(names),
+            (&mut Frame::Define(ref mut target @ None, _), Done::Names(names)) => *target = Some(Target::Header(names)),
+            (&mut Frame::Names(ref mut params), Done::Binding(name, default)) => params.push(name, Some(default))?,
+            (&mut Frame::Define(ref mut target @ None, _), Done::Expr(expr)) => *target = Some(Target::Expr(expr)),
+            (&mut Frame::Cond(ref mut clauses), Done::Clause(test, body)) => clauses.push((test, body)),
+            (&mut Frame::Try(ref mut body @ None, None), Done::Expr(expr)) => *body = Some(expr),
+            (&mut Frame::Try(Some(_), ref mut catch @ None), Done::Catch(name, handler)) => *catch = Some((name, handler)),
+            (&mut Frame::Quote(ref mut datum @ None), Done::Value(val)) => *datum = Some(val),
+            (&mut Frame::Quote(Some(_)), Done::Value(_)) => return Err(ReadError::Invalid(String::from("quote takes 1 argument"))),
+            (&mut Frame::Datum(ref mut items), Done::Value(val)) => items.push(val),
+            (&mut Frame::Dotted(_, ref mut tail @ None), Done::Value(val)) => *tail = Some(val),
+            (&mut Frame::DottedTemplate(_, ref mut tail @ None), Done::Value(val)) => *tail = Some(Part::Datum(val)),
+            (&mut Frame::DottedTemplate(_, ref mut tail @ None), Done::Part(part)) => *tail = Some(part),
+            (&mut Frame::Dotted(_, Some(_)), _) | (&mut Frame::DottedTemplate(_, Some(_)), _) =>
+                return Err(ReadError::Invalid(String::from("Expected ) after the element after ."))),
+            (&mut Frame::Quoted, Done::Value(val)) => return Ok(Some(Done::Value(val))),
+            (&mut Frame::Template(ref mut parts), Done::Value(val)) => parts.push(Part::Datum(val)),
+            (&mut Frame::Template(ref mut parts), Done::Part(part)) => parts.push(part),
+            (&mut Frame::Quasiquoted, Done::Value(val)) => return Ok(Some(Done::Expr(Ast::Literal(val)))),
+            (&mut Frame::Quasiquoted, Done::Part(part)) => return Ok(Some(Done::Expr(part.into_expr()?))),
+            (&mut Frame::Unquote(splice), Done::Expr(expr)) =>
+                return Ok(Some(Done::Part(if splice { Part::Splice(expr) } else { Part::Unquote(expr) }))),
+            (&mut Frame::Lambda(None, _), _) => return Err(ReadError::Invalid(String::from("Expected parameter list"))),
+            (&mut Frame::Try(Some(_), _), _) => return Err(ReadError::Invalid(String::from("Expected (catch name handler...) in try"))),
+            (_, _) => return Err(ReadError::Invalid(String::from("Unexpected expression")))
+        }
+        Ok(None)
+    }
+}
+
+fn build_form(form: Form, mut params: Vec<Ast>) -> Result<Ast, ReadError> {
+    match form {
+        Form::Call(func) => Ok(Ast::Call {func: Box::new(func), args: params, span: None}),
+        Form::If => {
+            if params.len() != 3 {
+                return Err(ReadError::Invalid(format!("if takes 3 arguments, got {}", params.len())));
+            }
+            let otherwise = params.pop().expect("if without else");
+            let then = params.pop().expect("if without then");
+            let cond = params.pop().expect("if without condition");
+            Ok(Ast::If(Box::new(cond), Box::new(then), Box::new(otherwise)))
+        },
+        Form::Set => {
+            if params.len() != 2 {
+                return Err(ReadError::Invalid(format!("set takes 2 arguments, got {}", params.len())));
+            }
+            let val = params.pop().expect("set without value");
+            match params.pop() {
+                Some(Ast::Reference(name)) => Ok(Ast::Set(name, Box::new(val))),
+                Some(other) => Err(ReadError::Invalid(format!("Can't assign to {:?}", other))),
+                None => unreachable!()
+            }
+        },
+        Form::DefineSyntax => {
+            if params.len() != 2 {
+                return Err(ReadError::Invalid(format!("define-syntax takes 2 arguments, got {}", params.len())));
+            }
+            let transformer = params.pop().expect("define-syntax without transformer");
+            match params.pop() {
+                // (define name (macro transformer)), with the builtin itself
+                // so that rebinding `macro` doesn't change what this means.
+                Some(Ast::Reference(name)) => {
+                    let func = Box::new(Ast::Literal(Value::Builtin(FuncId::Macro)));
+                    Ok(Ast::Define(name, Box::new(Ast::Call { func, args: vec![transformer], span: None })))
+                },
+                Some(other) => Err(ReadError::Invalid(format!("Can't define syntax {}", other))),
+                None => unreachable!()
+            }
+        },
+        // A call to the builtin, with the name as a symbol.
+        Form::DefineConstant => {
+            if params.len() != 2 {
+                return Err(ReadError::Invalid(format!("define-constant takes 2 arguments, got {}", params.len())));
+            }
+            match params[0] {
+                Ast::Reference(ref name) => params[0] = Ast::Literal(Value::Symbol(name.clone())),
+                ref other => return Err(ReadError::Invalid(format!("Can't define {} as a constant", other)))
+            }
+            Ok(Ast::Call {func: Box::new(Ast::Literal(Value::Builtin(FuncId::DefineConstant))), args: params, span: None})
+        },
+        Form::Unset => match params.pop() {
+            Some(Ast::Reference(name)) if params.is_empty() => {
+                let name = Ast::Literal(Value::Symbol(name));
+                Ok(Ast::Call {func: Box::new(Ast::Literal(Value::Builtin(FuncId::Unset))), args: vec![name], span: None})
+            },
+            _ => Err(ReadError::Invalid(String::from("unset! takes 1 name")))
+        },
+        Form::Finally => {
+            if params.len() != 2 {
+                return Err(ReadError::Invalid(format!("finally takes 2 arguments, got {}", params.len())));
+            }
+            let cleanup = params.pop().expect("Argument disappeared");
+            let body = params.pop().expect("Argument disappeared");
+            Ok(Ast::Finally(Box::new(body), Box::new(cleanup)))
+        },
+        // Calls to the builtins with the text of what's checked as the
+        // message, unless one is given.
+        Form::Assert | Form::AssertEqual => {
+            let (func, n) = if form == Form::Assert { (FuncId::Assert, 1) } else { (FuncId::AssertEqual, 2) };
+            if params.len() == n {
+                let checked = if n == 1 { params[0].to_string() } else { format!("(equal? {} {})", params[0], params[1]) };
+                params.push(Ast::Literal(Value::Str(checked)));
+            }
+            if params.len() != n + 1 {
+                return Err(ReadError::Invalid(format!("{} takes {} or {} arguments, got {}", func.name(), n, n + 1, params.len())));
+            }
+            Ok(Ast::Call {func: Box::new(Ast::Literal(Value::Builtin(func))), args: params, span: None})
+        },
+        Form::Begin => Ok(Ast::Begin(params)),
+        Form::While => {
+            if params.is_empty() {
+                return Err(ReadError::Invalid(String::from("while without a condition")));
+            }
+            let cond = params.remove(0);
+            Ok(Ast::While(Box::new(cond), params))
+        },
+        // Sugar for an `if` with an empty `begin`, which is unit, on one side.
+        Form::When | Form::Unless => {
+            let when = form == Form::When;
+            if params.is_empty() {
+                return Err(ReadError::Invalid(format!("{} without a condition", if when { "when" } else { "unless" })));
+            }
+            let cond = params.remove(0);
+            let (then, otherwise) = if when { (params, vec![]) } else { (vec![], params) };
+            Ok(Ast::If(Box::new(cond), Box::new(Ast::Begin(then)), Box::new(Ast::Begin(otherwise))))
+        },
+        Form::Delay | Form::Time => {
+            let delay = form == Form::Delay;
+            if params.len() != 1 {
+                let name = if delay { "delay" } else { "time" };
+                return Err(ReadError::Invalid(format!("{} takes 1 argument, got {}", name, params.len())));
+            }
+            let expr = params.pop().expect("Argument disappeared");
+            Ok(if delay { Ast::Delay(Arc::new(expr)) } else { Ast::Time(Box::new(expr)) })
+        },
+        Form::And => Ok(Ast::And(params)),
+        Form::Or => Ok(Ast::Or(params)),
+        Form::Define | Form::Lambda | Form::Quote | Form::Cond | Form::Try | Form::Let(_) | Form::Case | Form::Do =>
+            unreachable!("{:?} has its own frame", form)
+    }
+}
+
+/// Reads tokens until the bottom of `stack` is finished, starting with
+/// `first` if given. An empty stack reads a single expression. If `pos`
+/// follows the input, calls record where they and their arguments start.
+/// Brackets are read as parens once they are known to be matched.
+fn parse(mut stack: Vec<Frame>, first: Option<Token>,
+         input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>, max_nesting: usize,
+         pos: Option<&Cell<Position>>)
+    -> Result<Ast, ReadError>
+{
+    let mut next = first;
+    // Whether each group opened so far, and not yet closed, is bracketed.
+    let mut brackets = vec![];
+    loop {
+        let started = !stack.is_empty();
+        let (token, at) = match next.take() {
+            Some(token) => (token, None),
+            None => match next_token_at(input, pos)? {
+                Some(token) => token,
+                None if started => return Err(ReadError::UnexpectedEof),
+                None => return Err(ReadError::Eof)
+            }
+        };
+        let token = match token {
+            Token::LParen | Token::LBracket => { brackets.push(token == Token::LBracket); Token::LParen },
+            Token::RParen | Token::RBracket => {
+                let bracket = token == Token::RBracket;
+                match brackets.pop() {
+                    Some(true) if !bracket => return Err(ReadError::Invalid(String::from("Expected ] to close [, got )"))),
+                    Some(false) if bracket => return Err(ReadError::Invalid(String::from("Expected ) to close (, got ]"))),
+                    _ => Token::RParen
+                }
+            },
+            token => token
+        };
+        let mut done = match step(&mut stack, token, at)? {
+            Some(done) => done,
+            None => {
+                if stack.len() > max_nesting {
+                    return Err(ReadError::NestingLimit(max_nesting));
+                }
+                continue;
+            }
+        };
+        // Hand the finished part up the stack, closing any `'`, backquote or
+        // `,` it completes.
+        loop {
+            let frame = match stack.last_mut() {
+                Some(frame) => frame,
+                None => match done {
+                    Done::Expr(expr) => return Ok(expr),
+                    _ => unreachable!("parts are only finished inside a frame")
+                }
+            };
+            match frame.accept(done)? {
+                None => break,
+                Some(finished) => {
+                    stack.pop();
+                    let in_data = stack.last().is_some_and(Frame::reads_data);
+                    done = match finished {
+                        Done::Value(val) if !in_data => Done::Expr(Ast::Literal(val)),
+                        finished => finished
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Feeds one token to the innermost frame, returning anything that token
+/// finished.
+fn step(stack: &mut Vec<Frame>, token: Token, at: Option<Position>) -> Result<Option<Done>, ReadError> {
+    let top = stack.pop();
+    let push = match (top, token) {
+        (Some(frame), Token::RParen) => return frame.close().map(Some),
+        (None, Token::RParen) => return Err(ReadError::UnexpectedCloseParen(None)),
+        (Some(Frame::Head(at)), Token::Symbol(name)) => match special_form(&name) {
+            Some(form) => vec![Frame::for_form(form)],
+            None => vec![Frame::Form(Form::Call(Ast::Reference(name)), vec![], at.map(Span::new))]
+        },
+        (Some(frame @ Frame::Names(_)), token) => match (frame, token) {
+            (Frame::Names(mut params), Token::Symbol(name)) => { params.push(name, None)?; vec![Frame::Names(params)] },
+            (frame, Token::LParen) => vec![frame, Frame::Binding(None, None)],
+            (_, token) => return Err(ReadError::Invalid(format!("Invalid parameter {:?}", token)))
+        },
+        (Some(frame @ Frame::Lambda(None, _)), Token::LParen) |
+        (Some(frame @ Frame::Define(None, _)), Token::LParen) => vec![frame, Frame::Names(Params::default())],
+        (Some(Frame::Lambda(None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected parameter list, got {:?}", token))),
+        (Some(frame @ Frame::Cond(_)), Token::LParen) => vec![frame, Frame::Clause(vec![])],
+        (Some(Frame::Cond(_)), token) =>
+            return Err(ReadError::Invalid(format!("Expected cond clause, got {:?}", token))),
+        (Some(frame @ Frame::Try(Some(_), None)), Token::LParen) => vec![frame, Frame::Catch(false, None, vec![])],
+        (Some(Frame::Try(Some(_), _)), _) =>
+            return Err(ReadError::Invalid(String::from("Expected (catch name handler...) in try"))),
+        (Some(Frame::Catch(false, None, handler)), Token::Symbol(ref catch)) if catch == "catch" =>
+            vec![Frame::Catch(true, None, handler)],
+        (Some(Frame::Catch(true, None, handler)), Token::Symbol(name)) => vec![Frame::Catch(true, Some(name), handler)],
+        (Some(Frame::Catch(_, None, _)), _) =>
+            return Err(ReadError::Invalid(String::from("Expected (catch name handler...) in try"))),
+        (Some(frame @ Frame::Let(_, None, _)), Token::LParen) |
+        (Some(frame @ Frame::NamedLet(_, None, _)), Token::LParen) => vec![frame, Frame::Bindings(vec![])],
+        (Some(Frame::Let(LetKind::Parallel, None, _)), Token::Symbol(name)) => vec![Frame::NamedLet(name, None, vec![])],
+        (Some(Frame::Let(kind, None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected bindings in {}, got {:?}", kind.name(), token))),
+        (Some(Frame::NamedLet(_, None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected bindings in let, got {:?}", token))),
+        (Some(frame @ Frame::Bindings(_)), Token::LParen) => vec![frame, Frame::Binding(None, None)],
+        (Some(Frame::Binding(None, None)), Token::Symbol(name)) => vec![Frame::Binding(Some(name), None)],
+        (Some(Frame::Bindings(_)), token) | (Some(Frame::Binding(None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected (name value) binding, got {:?}", token))),
+        (Some(frame @ Frame::Do(None, None, _)), Token::LParen) => vec![frame, Frame::Steps(vec![])],
+        (Some(frame @ Frame::Do(Some(_), None, _)), Token::LParen) => vec![frame, Frame::Clause(vec![])],
+        (Some(Frame::Do(_, None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected (steps...) (test result...) in do, got {:?}", token))),
+        (Some(frame @ Frame::Steps(_)), Token::LParen) => vec![frame, Frame::Step(None, vec![])],
+        (Some(Frame::Step(None, exprs)), Token::Symbol(name)) => vec![Frame::Step(Some(name), exprs)],
+        (Some(Frame::Steps(_)), token) | (Some(Frame::Step(None, _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected (name init step) in do, got {:?}", token))),
+        (Some(frame @ Frame::Case(Some(_), _)), Token::LParen) => vec![frame, Frame::CaseClause(false, None, vec![])],
+        (Some(Frame::Case(Some(_), _)), token) =>
+            return Err(ReadError::Invalid(format!("Expected case clause, got {:?}", token))),
+        (Some(frame @ Frame::CaseClause(false, None, _)), Token::LParen) => vec![frame, Frame::Datum(vec![])],
+        (Some(Frame::CaseClause(false, None, body)), Token::Symbol(ref name)) if name == "else" =>
+            vec![Frame::CaseClause(true, None, body)],
+        (Some(Frame::CaseClause(false, ..)), token) =>
+            return Err(ReadError::Invalid(format!("Expected keys or else in case clause, got {:?}", token))),
+        (Some(Frame::Datum(items)), Token::Dot) if !items.is_empty() => vec![Frame::Dotted(items, None)],
+        (Some(Frame::Template(parts)), Token::Dot) if !parts.is_empty() => vec![Frame::DottedTemplate(parts, None)],
+        (Some(frame), Token::LParen) if frame.reads_template() => vec![frame, Frame::Template(vec![])],
+        (Some(frame), Token::Unquote(splice)) if frame.reads_template() => vec![frame, Frame::Unquote(splice)],
+        (Some(mut frame), token) => {
+            if let (&mut Frame::Form(Form::Call(_), _, Some(ref mut span)), Some(at)) = (&mut frame, at) {
+                span.args.push(at);
+            }
+            let reads_data = frame.reads_data();
+            stack.push(frame);
+            return start(stack, token, at, reads_data);
+        },
+        (None, token) => return start(stack, token, at, false)
+    };
+    stack.extend(push);
+    Ok(None)
+}
+
+/// Starts reading an expression, or a datum if `reads_data`, at `token`,
+/// which is `at` if known.
+fn start(stack: &mut Vec<Frame>, token: Token, at: Option<Position>, reads_data: bool) -> Result<Option<Done>, ReadError> {
+    let val = match token {
+        Token::LParen if reads_data => { stack.push(Frame::Datum(vec![])); return Ok(None) },
+        Token::LParen => { stack.push(Frame::Head(at)); return Ok(None) },
+        Token::Quote => { stack.push(Frame::Quoted); return Ok(None) },
+        Token::Quasiquote if reads_data => return Err(ReadError::Invalid(String::from("Templates can't be quoted or nested"))),
+        Token::Quasiquote => { stack.push(Frame::Quasiquoted); return Ok(None) },
+        Token::Unquote(_) => return Err(ReadError::Invalid(String::from(", outside a template"))),
+        Token::Dot => return Err(ReadError::Invalid(String::from("Misplaced ."))),
+        Token::RParen => unreachable!("closing parens are handled by step"),
+        Token::LBracket | Token::RBracket => unreachable!("brackets are handled by parse"),
+        Token::Int(n) => Value::Int(n),
+        Token::Float(x) => Value::Float(x),
+        Token::Bool(b) => Value::Bool(b),
+        Token::Char(c) => Value::Char(c),
+        Token::Str(s) => Value::Str(s),
+        Token::Symbol(name) if reads_data || keyword(&name).is_some() => Value::Symbol(name),
+        Token::Symbol(name) => return Ok(Some(Done::Expr(Ast::Reference(name))))
+    };
+    Ok(Some(if reads_data { Done::Value(val) } else { Done::Expr(Ast::Literal(val)) }))
+}
+
+/// Reads a quoted datum, where lists and symbols stand for themselves
+/// instead of being calls and references.
+pub fn read_datum(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Value, ReadError> {
+    match parse(vec![Frame::Quoted], None, input, DEFAULT_MAX_NESTING, None)? {
+        Ast::Literal(val) => Ok(val),
+        other => unreachable!("quote produced {:?}", other)
+    }
+}
+
+impl Form {
+    /// Reads the rest of a form whose head has been read.
+    pub fn read(self, input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Ast, ReadError> {
+        parse(vec![Frame::for_form(self)], None, input, DEFAULT_MAX_NESTING, None)
+    }
+}
+
+/// Reads the head of a form after its opening paren.
+pub fn read_function_name(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Form, ReadError> {
+    match next_token(input)? {
+        Some(Token::Symbol(name)) => Ok(special_form(&name).unwrap_or(Form::Call(Ast::Reference(name)))),
+        Some(Token::RParen) | Some(Token::RBracket) => Err(ReadError::Invalid(String::from("Empty form ()"))),
+        Some(token) => Ok(Form::Call(parse_expr(token, input)?)),
+        None => Err(ReadError::UnexpectedEof)
+    }
+}
+
+/// Reads expressions up to and including a closing paren.
+pub fn read_function_params(input: &mut Peekable<&mut dyn Iterator<Item = Result<char, io::Error>>>) -> Result<Vec<Ast>, ReadError> {
+    match parse(vec![Frame::Form(Form::Begin, vec![], None)], None, input, DEFAULT_MAX_NESTING, None)? {
+        Ast::Begin(params) => Ok(params),
+        other => unreachable!("params produced {:?}", other)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter::{Iterator,Map};
+    use std::str::{Chars};
+    use std::io::Error;
+
+    fn char_to_result(c: char) -> Result<char, Error> {
+        Ok(c)
+    }
+
+    /// What evaluating `src` fails with, leaving out where.
+    fn eval_error(src: &str, env: &mut Environment) -> EvalError {
+        match eval_str(src, env) {
+            Err(LustError::Eval(EvalError::At(_, err))) => *err,
+            Err(LustError::Eval(err)) => err,
+            other => panic!("Expected an evaluation error, got {:?}", other)
+        }
+    }
+
+    type Input = Map<Chars<'static>, fn(char)->Result<char, Error>>;
+
+    fn input(s: &'static str) -> Input {
+        s.chars().map(char_to_result)
+    }
+
+    fn iterator<T>(iterator: &mut dyn Iterator<Item=T>) -> &mut dyn Iterator<Item=T> {
+        iterator
+    }
+
+    #[test]
+    fn test_eval_str() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(3), eval_str("(+ 1 2)", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str(" (define x 4) ; four", &mut env).unwrap());
+        assert_eq!(Value::Int(4), eval_str("x", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_eval_str_errors() {
+        let mut env = Environment::with_builtins();
+        match eval_str("(+ 1", &mut env) {
+            Err(LustError::Read(ReadError::UnexpectedEof)) => (),
+            other => panic!("Expected unexpected EOF, got {:?}", other)
+        }
+        match eval_str("1 2", &mut env) {
+            Err(LustError::Read(ReadError::Invalid(_))) => (),
+            other => panic!("Expected trailing input error, got {:?}", other)
+        }
+        match eval_str("(apa)", &mut env) {
+            Err(LustError::Eval(EvalError::UndefinedName(..))) => (),
+            other => panic!("Expected undefined name, got {:?}", other)
+        }
+    }
+
+    struct Double;
+
+    impl Function for Double {
+        fn call(&self, args: Vec<Value>, _env: &mut Environment) -> Result<Value, EvalError> {
+            match args.as_slice() {
+                [Value::Int(n)] => Ok(Value::Int(n * 2)),
+                _ => Err(EvalError::Arity(String::from("double takes 1 integer")))
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_registered_function() {
+        let mut env = Environment::with_builtins();
+        env.register("double", Double);
+        let mut m = input("(double (+ 1 20))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(42), eval(&expr, &mut env).unwrap());
+        assert_eq!("#<native double>", env.get("double").unwrap().to_string());
+        assert_eq!(Value::Int(4), eval_str("(reduce + 0 (map double '(1 1)))", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_error() {
+        let mut env = Environment::with_builtins();
+        match eval_str("(if #f 0 (error \"boom\"))", &mut env) {
+            Err(LustError::Eval(EvalError::Custom(ref message))) if message == "boom" => (),
+            other => panic!("Expected boom, got {:?}", other)
+        }
+        eval_str("(define (guard x) (if (< x 0) (error \"negative\") x))", &mut env).unwrap();
+        match eval_str("(+ 1 (guard (- 0 (guard 2))))", &mut env) {
+            Err(e) => assert_eq!("negative", e.to_string()),
+            other => panic!("Expected negative, got {:?}", other)
+        }
+        assert!(matches!(eval_error("(error 1)", &mut env), EvalError::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_read_try() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Str(String::from("caught boom")),
+                   eval_str("(try (+ 1 (error \"boom\")) (catch e (+ 0 0) (if (= 1 1) \"caught boom\" e)))", &mut env).unwrap());
+        assert_eq!(Value::Str(String::from("boom")), eval_str("(try (error \"boom\") (catch e e))", &mut env).unwrap());
+        assert_eq!(Value::Int(3), eval_str("(try (+ 1 2) (catch e (error \"unreachable\")))", &mut env).unwrap());
+        assert!(env.get("e").is_err());
+    }
+
+    #[test]
+    fn test_read_try_handler_is_skipped() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define ran #f)", &mut env).unwrap();
+        eval_str("(try 1 (catch e (set ran #t)))", &mut env).unwrap();
+        assert_eq!(Value::Bool(false), env.get("ran").unwrap());
+        eval_str("(try (undefined) (catch e (set ran e)))", &mut env).unwrap();
+        assert_eq!(Value::Str(String::from("No such name in environment: undefined")), env.get("ran").unwrap());
+    }
+
+    #[test]
+    fn test_finally() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define cleaned 0)", &mut env).unwrap();
+        assert_eq!(Value::Int(3), eval_str("(finally (+ 1 2) (set cleaned (+ cleaned 1)))", &mut env).unwrap());
+        assert_eq!(Value::Int(1), env.get("cleaned").unwrap());
+        match eval_str("(finally (car (error \"boom\")) (set cleaned (+ cleaned 1)))", &mut env) {
+            Err(LustError::Eval(EvalError::Custom(ref message))) if message == "boom" => (),
+            other => panic!("Expected boom, got {:?}", other)
+        }
+        assert_eq!(Value::Int(2), env.get("cleaned").unwrap());
+        assert_eq!(Value::Str(String::from("boom")),
+                   eval_str("(try (finally (error \"boom\") (set cleaned 0)) (catch e e))", &mut env).unwrap());
+        assert_eq!(Value::Int(0), env.get("cleaned").unwrap());
+        assert!(matches!(eval_str("(finally 1 (error \"cleanup\"))", &mut env), Err(LustError::Eval(EvalError::Custom(_)))));
+        // The body's error is the one kept when the cleanup fails too.
+        match eval_str("(finally (error \"a\") (error \"b\"))", &mut env) {
+            Err(LustError::Eval(EvalError::Custom(ref message))) if message == "a" => (),
+            other => panic!("Expected a, got {:?}", other)
+        }
+        // Both parts are evaluated in the current scope, so definitions
+        // in them stay.
+        eval_str("(finally (define z 1) (define w 2))", &mut env).unwrap();
+        assert_eq!(Value::Int(3), eval_str("(+ z w)", &mut env).unwrap());
+        assert_eq!("(finally 1 2)", read_str("(finally 1 2)").unwrap().to_string());
+        assert!(matches!(eval_str("(finally 1)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_read_malformed_try() {
+        let mut env = Environment::with_builtins();
+        assert!(matches!(eval_str("(try 1 2)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        assert!(matches!(eval_str("(try 1 (catch e))", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))", &mut env).unwrap();
+        assert_eq!(Value::Int(5050), eval_str("(sum 100)", &mut env).unwrap());
+        match eval_str("(sum 100000)", &mut env) {
+            Err(LustError::Eval(EvalError::RecursionLimit(_))) => (),
+            other => panic!("Expected recursion limit, got {:?}", other)
+        }
+        assert_eq!(Value::Int(5050), eval_str("(sum 100)", &mut env).unwrap());
+        env.set_max_depth(50);
+        assert!(matches!(eval_str("(sum 100)", &mut env), Err(LustError::Eval(EvalError::RecursionLimit(50)))));
+    }
+
+    fn read_one(src: &str) -> Ast {
+        let mut chars = src.chars().map(char_to_result);
+        let peekable = &mut iterator(&mut chars).peekable();
+        read_expr(peekable).unwrap()
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let sources = [
+            "(+ 1 (+ 2 3))",
+            "(if (< x 0) (- x) x)",
+            "(define (f x y) (set x (+ x y)) x)",
+            "(begin (while (> n 0) (set n (- n 1))) 'done)",
+            "((lambda (x) (* x x)) -4)",
+            "(cond ((= x 1) 'one) ((= x 2)) (else '(a b (#t #\\space))))",
+            "(and (or #f '()) (not #\\a) \"a string\")",
+            "(try (error \"boom\") (catch e (print e) e))",
+            "(let ((x 1) (y '(2))) (print y) x)",
+            "(let* () 1)",
+            "(letrec ((f (lambda (n) (if (= n 0) 0 (f (- n 1)))))) (f 3))",
+            "(case (f x) ((1 a \"b\") 'one) (() 2 3) (else x))",
+            "(force (delay (+ 1 2)))",
+            "(time (f 1))",
+        ];
+        for src in sources.iter() {
+            let expr = read_one(src);
+            let text = expr.to_string();
+            assert_eq!(expr, read_one(&text), "{} displayed as {}", src, text);
+        }
+        assert_eq!("(+ 1 (+ 2 3))", read_one("(+ 1\n  (+ 2 3))").to_string());
+    }
+
+    #[test]
+    fn test_write_sexp_round_trip() {
+        let sources = [
+            "'(1 (2 (3 (4 ()))) \"five\" #\\6)",
+            "(print \"quote \\\" backslash \\\\ newline \\n tab \\t\")",
+            "'(\"nested \\\"strings\\\"\" (#\\( #\\) #\\return #\\tab))",
+            "(define (fact n) (if (<= n 1) 1 (* n (fact (- n 1)))))",
+            "(map (lambda (x) (cond ((< x 0) 'neg) (else x))) '(-1 0 1))",
+            "(begin)",
+            "''a",
+            "'((a . 1) (b 2 . 3))",
+        ];
+        for src in sources.iter() {
+            let expr = read_one(src);
+            let text = write_sexp(&expr);
+            assert_eq!(expr, read_one(&text), "{} written as {}", src, text);
+            assert_eq!(text, write_sexp(&read_one(&text)));
+        }
+    }
+
+    #[test]
+    fn test_write_sexp_escapes_strings() {
+        let expr = Ast::Literal(Value::Str(String::from("a \"b\"\\\n")));
+        assert_eq!("\"a \\\"b\\\"\\\\\\n\"", write_sexp(&expr));
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("{}0{}", "(- ".repeat(depth), ")".repeat(depth))
+    }
+
+    #[test]
+    fn test_read_deeply_nested() {
+        let src = nested(50000);
+        let mut chars = src.chars().map(char_to_result);
+        let peekable = &mut iterator(&mut chars).peekable();
+        let mut expr = read_expr_with_max_nesting(peekable, usize::MAX).unwrap();
+        let mut depth = 0;
+        while let Ast::Call {ref mut args, ..} = expr {
+            expr = args.pop().unwrap();
+            depth += 1;
+        }
+        assert_eq!(50000, depth);
+        assert_eq!(Ast::Literal(Value::Int(0)), expr);
+    }
+
+    #[test]
+    fn test_read_nesting_limit() {
+        let src = nested(DEFAULT_MAX_NESTING + 1);
+        let mut chars = src.chars().map(char_to_result);
+        let peekable = &mut iterator(&mut chars).peekable();
+        match read_expr(peekable) {
+            Err(ReadError::NestingLimit(DEFAULT_MAX_NESTING)) => (),
+            other => panic!("Expected nesting limit, got {:?}", other)
+        }
+        let src = nested(DEFAULT_MAX_NESTING);
+        let mut chars = src.chars().map(char_to_result);
+        let peekable = &mut iterator(&mut chars).peekable();
+        assert!(read_expr(peekable).is_ok());
+    }
+
+    #[test]
+    fn test_read_add_function() {
+        let mut m = input("+");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Form::Call(Ast::Reference(String::from("+"))), read_function_name(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_function_name_stops_at_delimiter() {
+        let mut m = input("(+ a)");
+        let peekable = &mut iterator(&mut m).peekable();
+        peekable.next();
+        assert_eq!(Form::Call(Ast::Reference(String::from("+"))), read_function_name(peekable).unwrap());
+        assert_eq!(vec![Ast::Reference(String::from("a"))], read_function_params(peekable).unwrap());
+
+        let mut m = input("(list)");
+        let peekable = &mut iterator(&mut m).peekable();
+        peekable.next();
+        assert_eq!(Form::Call(Ast::Reference(String::from("list"))), read_function_name(peekable).unwrap());
+        assert_eq!(Some(')'), peekable.next().map(Result::unwrap));
+    }
+
+
+    #[test]
+    fn test_read_number_before_newline() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(+ 1\n2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_unknown_function() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(apa 1)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        match eval(&expr, &mut env) {
+            Err(EvalError::UndefinedName(..)) => (),
+            other => panic!("Should get error, got {:?}", other)
+        }
+    }
+
+
+
+
+    #[test]
+    fn test_read_number_params() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let params = read_function_params(peekable).unwrap();
+        assert_eq!(2, params.len());
+        assert_eq!(Value::Int(1), eval(&params[0], &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval(&params[1], &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_expr() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(+ 1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_nested_expr() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(+ 1 (+ 1 1))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable);
+        assert_eq!(Value::Int(3), eval(&expr.unwrap(), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_if_nonzero() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(if (+ 1 1) 1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(1), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_if_without_spaces() {
+        assert_eq!(Value::Int(1), read_eval("(if(< 1 2)1(+ 2 3))"));
+        assert_eq!(Value::Int(5), read_eval("(if(> 1 2)(+ 0 1)(+ 2 3))"));
+    }
+
+    #[test]
+    fn test_read_call_without_spaces() {
+        assert_eq!(Value::Int(3), read_eval("(+(+ 1 1)1)"));
+        assert_eq!(Value::Int(4), read_eval("((lambda(x)(+ x 1))3)"));
+    }
+
+    #[test]
+    fn test_read_signed_number_head() {
+        let mut m = input("(+1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Ast::Call {
+            func: Box::new(Ast::Literal(Value::Int(1))),
+            args: vec![Ast::Literal(Value::Int(2))],
+            span: None
+        }, read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_if_zero() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(if (+ 1 -1) 1 (+ 2 3))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(5), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_variable() {
+        let mut env = Environment::with_builtins();
+        env.set("a", Value::Int(3)).unwrap();
+        let mut m = input("a");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_variable_after_set() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(set x 5)\nx");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        eval(&expr, &mut env).unwrap();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Ast::Reference(String::from("x")), expr);
+        assert_eq!(Value::Int(5), eval(&expr, &mut env).unwrap());
+        assert!(matches!(read_expr(peekable), Err(ReadError::Eof)));
+    }
+
+    #[test]
+    fn test_read_long_variable_name() {
+        let mut env = Environment::with_builtins();
+        env.set("foo-bar?", Value::Int(7)).unwrap();
+        let mut m = input("\tfoo-bar?\n");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(7), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_unexpected_close_paren() {
+        let mut m = input(") x");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert!(matches!(read_expr(peekable), Err(ReadError::UnexpectedCloseParen(None))));
+        assert_eq!(Ast::Reference(String::from("x")), read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_extra_close_paren_position() {
+        let mut env = Environment::with_builtins();
+        match eval_str("(+ 1 2))", &mut env) {
+            Err(LustError::Read(ReadError::UnexpectedCloseParen(Some(pos)))) =>
+                assert_eq!(Position {line: 1, column: 8}, pos),
+            other => panic!("Expected unexpected ')', got {:?}", other)
+        }
+        match eval_str("\n  )", &mut env) {
+            Err(e) => assert_eq!("Unexpected ')' at line 2, column 3", e.to_string()),
+            other => panic!("Expected unexpected ')', got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_balanced_holds_lines_back() {
+        let prompts = Rc::new(Cell::new(0));
+        let count = prompts.clone();
+        let mut chars = Balanced::new(input("(+\n1\n2)\n3\n"), move || count.set(count.get() + 1));
+        assert_eq!(Some('('), chars.next().map(Result::unwrap));
+        assert_eq!(2, prompts.get());
+        let rest: String = chars.map(Result::unwrap).collect();
+        assert_eq!("+\n1\n2)\n3\n", rest);
+        assert_eq!(2, prompts.get());
+    }
+
+    #[test]
+    fn test_balanced_ignores_parens_in_literals_and_comments() {
+        for src in &["\"(\"\n", "#\\(\n", "; (\n", "#| ( #| ) |# |#\n", "\"\\\"(\"\n", "(foo))\n"] {
+            let prompts = Rc::new(Cell::new(0));
+            let count = prompts.clone();
+            let chars = Balanced::new(input(src), move || count.set(count.get() + 1));
+            assert_eq!(src.to_string(), chars.map(Result::unwrap).collect::<String>());
+            assert_eq!(0, prompts.get(), "{:?}", src);
+        }
+        for src in &["\"\n\"", "#| |\n|#", "(#\\)\n)"] {
+            let prompts = Rc::new(Cell::new(0));
+            let count = prompts.clone();
+            let chars = Balanced::new(input(src), move || count.set(count.get() + 1));
+            assert_eq!(src.to_string(), chars.map(Result::unwrap).collect::<String>());
+            assert_eq!(1, prompts.get(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_balanced_pending() {
+        let mut chars = Balanced::new(input("(a\nb)\nc\n"), || ());
+        let pending = chars.pending();
+        chars.next();
+        assert_eq!(5, pending.get());
+        for _ in 0..5 {
+            chars.next();
+        }
+        assert_eq!(0, pending.get());
+        chars.next();
+        assert_eq!(1, pending.get());
+    }
+
+    /// Writes `src` to a file in the temp directory, returning its path.
+    fn temp_file(name: &str, src: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lust-{}-{}", std::process::id(), name));
+        fs::write(&path, src).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn test_load() {
+        let mut env = Environment::with_builtins();
+        let lib = temp_file("lib.lisp", "(define (double x) (+ x x))\n(define answer (double 21))\n");
+        assert_eq!(Value::Unit, eval_str(&format!("(load {:?})", lib), &mut env).unwrap());
+        assert_eq!(Value::Int(42), eval_str("answer", &mut env).unwrap());
+        assert_eq!(Value::Int(10), eval_str("(double 5)", &mut env).unwrap());
+        // Loading the same file again, not from inside itself, is fine.
+        assert_eq!(Value::Unit, eval_str(&format!("(load {:?})", lib), &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut env = Environment::with_builtins();
+        let missing = std::env::temp_dir().join("lust-no-such-file.lisp").display().to_string();
+        match eval_str(&format!("(load {:?})", missing), &mut env) {
+            Err(LustError::Eval(EvalError::Load(ref path, ReadError::Io(_)))) if *path == missing => (),
+            other => panic!("Expected load error, got {:?}", other)
+        }
+        let broken = temp_file("broken.lisp", "(define a 1)\n(+ a))");
+        match eval_str(&format!("(load {:?})", broken), &mut env) {
+            Err(LustError::Eval(EvalError::Load(_, ReadError::UnexpectedCloseParen(Some(pos))))) =>
+                assert_eq!(Position {line: 2, column: 6}, pos),
+            other => panic!("Expected load error, got {:?}", other)
+        }
+        let path = std::env::temp_dir().join(format!("lust-{}-self.lisp", std::process::id()));
+        let own = temp_file("self.lisp", &format!("(load {:?})", path.display().to_string()));
+        match eval_str(&format!("(load {:?})", own), &mut env) {
+            Err(LustError::Eval(EvalError::LoadCycle(_))) => (),
+            other => panic!("Expected load cycle, got {:?}", other)
+        }
+        // The failed load doesn't count as still loading.
+        fs::write(&path, "(define loaded #t)").unwrap();
+        assert_eq!(Value::Unit, eval_str(&format!("(load {:?})", own), &mut env).unwrap());
+        assert_eq!(Value::Bool(true), eval_str("loaded", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_repl_output() {
+        let script = "(define (double x) (+ x x))\n(double\n 4)\n(+ 1 #z)\n(double 1))\n";
+        let out = Repl::new(script.as_bytes(), vec![]).run().unwrap();
+        assert_eq!("... 8\nError: Invalid input: Invalid input '#z'\n2\nError: Unexpected ')' at line 5, column 11\n",
+                   String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_repl_program_output() {
+        let out = Repl::new(&b"(print 1)\n(display \"two\")\n"[..], Vec::new()).run().unwrap();
+        // `print` returns what it printed, which the REPL prints again.
+        assert_eq!("1\n1\ntwo", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_repl_read_line() {
+        let input = &b"(read-line)\nhello there\n(define x (read-line))\nlast\nx\n(read-line)\n"[..];
+        let out = Repl::new(input, Vec::new()).run().unwrap();
+        assert_eq!("\"hello there\"\n\"last\"\n#f\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_repl_environment() {
+        let mut env = Environment::with_builtins();
+        env.set("x", Value::Int(5)).unwrap();
+        let out = Repl::new(&b"x\n(print x)\n"[..], Vec::new()).environment(env).run().unwrap();
+        assert_eq!("5\n5\n5\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "bignum")]
+    fn test_big_factorial() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))", &mut env).unwrap();
+        assert_eq!("30414093201713378043612608166064768844377641568960512000000000000",
+                   eval_str("(fact 50)", &mut env).unwrap().to_string());
+        assert_eq!(Value::Int(50), eval_str("(- (fact 50) (fact 50) -50)", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_tracked_position() {
+        let mut chars = Tracked::new(input("ab\nc"));
+        let pos = chars.position();
+        chars.next();
+        assert_eq!(Position {line: 1, column: 1}, pos.get());
+        chars.next();
+        chars.next();
+        assert_eq!(Position {line: 1, column: 3}, pos.get());
+        chars.next();
+        assert_eq!(Position {line: 2, column: 1}, pos.get());
+    }
+
+    #[test]
+    fn test_read_variable_argument() {
+        let mut env = Environment::with_builtins();
+        env.set("a", Value::Int(3)).unwrap();
+        let mut m = input("(+ a 1)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(4), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_bound_variable_argument() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(define foo 3)\n(+ foo 2)\n(+ foo\tfoo)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        eval(&expr, &mut env).unwrap();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Ast::Call {
+            func: Box::new(Ast::Reference(String::from("+"))),
+            args: vec![Ast::Reference(String::from("foo")), Ast::Literal(Value::Int(2))],
+            span: None
+        }, expr);
+        assert_eq!(Value::Int(5), eval(&expr, &mut env).unwrap());
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_assignment() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(set a 1)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        eval(&expr, &mut env).unwrap();
+        assert_eq!(Value::Int(1), env.get("a").unwrap());
+    }
+
+    #[test]
+    fn test_read_assignment_to_non_name() {
+        let mut m = input("(set 1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        match read_expr(peekable) {
+            Err(ReadError::Invalid(_)) => (),
+            other => panic!("Expected invalid assignment, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_read_print() {
+        let mut m = input("(print 7)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Ast::Call {func: Box::new(Ast::Reference(String::from("print"))),
+                              args: vec![Ast::Literal(Value::Int(7))], span: None},
+                   expr);
+    }
+
+    #[test]
+    fn test_read_negative_argument() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(+ -3 4)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(1), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_minus_argument() {
+        let mut m = input("- 3)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let params = read_function_params(peekable).unwrap();
+        assert_eq!(vec![Ast::Reference(String::from("-")), Ast::Literal(Value::Int(3))], params);
+    }
+
+    #[test]
+    fn test_read_subtraction() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(- 10 (- 3) 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(11), eval(&expr, &mut env).unwrap());
+    }
+
+
+
+    #[test]
+    fn test_read_binary_number() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(+ 0b1010 -0b1)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(9), eval(&expr, &mut env).unwrap());
+    }
+
+
+    #[test]
+    fn test_read_char() {
+        let mut m = input("#\\a");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Ast::Literal(Value::Char('a')), read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_string() {
+        let mut m = input("\"a (b)\"");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Ast::Literal(Value::Str(String::from("a (b)"))), read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_named_chars() {
+        let mut m = input("#\\space #\\newline)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let params = read_function_params(peekable).unwrap();
+        assert_eq!(vec![Ast::Literal(Value::Char(' ')), Ast::Literal(Value::Char('\n'))], params);
+    }
+
+    #[test]
+    fn test_read_unknown_char_name() {
+        let mut m = input("#\\bogus");
+        let peekable = &mut iterator(&mut m).peekable();
+        match read_expr(peekable) {
+            Err(ReadError::Invalid(_)) => (),
+            other => panic!("Expected invalid character, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_read_nested_block_comment() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("#| a #| b |# c |# (+ 1 #| 5 |# 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_clean_eof() {
+        for src in &["", "  \n", "; just a comment", "#| block |#"] {
+            let mut m = input(src);
+            let peekable = &mut iterator(&mut m).peekable();
+            assert!(matches!(read_expr(peekable), Err(ReadError::Eof)), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_read_incomplete_expression() {
+        for src in &["(+ 1", "(", "'", "(lambda (x", "'(1 (2)", "(cond (#t", "\"abc", "#"] {
+            let mut m = input(src);
+            let peekable = &mut iterator(&mut m).peekable();
+            assert!(matches!(read_expr(peekable), Err(ReadError::UnexpectedEof)), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_read_unterminated_block_comment() {
+        let mut m = input("#| a #| b |# c");
+        let peekable = &mut iterator(&mut m).peekable();
+        match read_expr(peekable) {
+            Err(ReadError::UnexpectedEof) => (),
+            other => panic!("Expected unexpected EOF, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_read_line_comment() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("; one\n(+ 1 ; two\n 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_comparison() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(if (< 1 2 3) (>= 3 3) #f)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Bool(true), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_chained_comparison() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(< 1 3 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Bool(false), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_quoted_list() {
+        let mut m = input("'(1 a (#t))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expected = Value::list(vec![Value::Int(1),
+                                        Value::Symbol(String::from("a")),
+                                        Value::list(vec![Value::Bool(true)])]);
+        assert_eq!(Ast::Literal(expected), read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_dotted_list() {
+        let int = Value::Int;
+        assert_eq!(Ast::Literal(Value::cons(int(1), int(2))), read_str("'(1 . 2)").unwrap());
+        assert_eq!(Ast::Literal(Value::dotted(vec![int(1), int(2)], int(3))), read_str("'(1 2 . 3)").unwrap());
+        assert_eq!(Ast::Literal(Value::list(vec![int(1), int(2)])), read_str("'(1 . (2))").unwrap());
+        let symbols = ["a", "...", ".b"].iter().map(|name| Value::Symbol(name.to_string())).collect();
+        assert_eq!(Ast::Literal(Value::list(symbols)), read_str("'(a ... .b)").unwrap());
+        let mut env = Environment::with_builtins();
+        assert!(matches!(eval_str("(length '(1 2 . 3))", &mut env), Err(LustError::Eval(EvalError::At(..)))));
+        for src in ["'(. 1)", "'(1 .)", "'(1 . 2 3)", "'(1 . 2 . 3)", "(f . x)", "."] {
+            assert!(matches!(read_str(src), Err(ReadError::Invalid(_))), "{}", src);
+        }
+        assert!(matches!(read_str("(case 1 ((1 . 2) 'a))"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_cons_pairs() {
+        let mut env = Environment::with_builtins();
+        assert_eq!("(1 . 2)", eval_str("(cons 1 2)", &mut env).unwrap().to_string());
+        assert_eq!("(1 2)", eval_str("(cons 1 (cons 2 nil))", &mut env).unwrap().to_string());
+        assert_eq!(Value::Nil, eval_str("nil", &mut env).unwrap());
+        let dotted = eval_str("(cons 1 (cons 2 3))", &mut env).unwrap();
+        assert_eq!(Ast::Literal(dotted.clone()), read_str(&format!("'{}", dotted)).unwrap());
+    }
+
+    #[test]
+    fn test_read_quote_form() {
+        let mut m = input("(quote foo)");
+        let peekable = &mut iterator(&mut m).peekable();
+        assert_eq!(Ast::Literal(Value::Symbol(String::from("foo"))), read_expr(peekable).unwrap());
+    }
+
+    #[test]
+    fn test_read_map() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(map (lambda (x) (+ x x)) '(1 2 3))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::list(vec![Value::Int(2), Value::Int(4), Value::Int(6)]), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_filter() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(filter (lambda (x) (> x 1)) '(3 1 2))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::list(vec![Value::Int(3), Value::Int(2)]), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_filter_non_list() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(filter (lambda (x) x) 5)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        eval(&expr, &mut env).unwrap_err();
+    }
+
+    #[test]
+    fn test_read_reduce_sum() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(reduce (lambda (acc x) (+ acc x)) 0 '(1 2 3))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_reduce_order() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(reduce (lambda (acc x) (- acc x)) 10 '(1 2 3))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(4), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_reduce_empty() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(reduce (lambda (acc x) (+ acc x)) 7 '())");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(7), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_builtin_argument() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(reduce + 0 '(1 2 3))");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_define_alias() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(define plus +) (plus 1 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let define = read_expr(peekable).unwrap();
+        assert_eq!(Value::Unit, eval(&define, &mut env).unwrap());
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_define_function() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(define (twice f x) (f (f x))) (twice - 5)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let define = read_expr(peekable).unwrap();
+        eval(&define, &mut env).unwrap();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(5), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_lambda_call() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("((lambda (x) (+ x 1)) 2)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(3), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_closure_captures_argument() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (adder n) (lambda (x) (+ x n)))", &mut env).unwrap();
+        assert_eq!(Value::Int(8), eval_str("((adder 5) 3)", &mut env).unwrap());
+        eval_str("(define add2 (adder 2))", &mut env).unwrap();
+        eval_str("(define add10 (adder 10))", &mut env).unwrap();
+        assert_eq!(Value::Int(3), eval_str("(add2 1)", &mut env).unwrap());
+        assert_eq!(Value::Int(11), eval_str("(add10 1)", &mut env).unwrap());
+        assert_eq!(Value::Int(4), eval_str("(add2 2)", &mut env).unwrap());
+        eval_str("(define (curry f) (lambda (a) (lambda (b) (f a b))))", &mut env).unwrap();
+        assert_eq!(Value::Int(-1), eval_str("(((curry -) 2) 3)", &mut env).unwrap());
+        assert!(env.get("n").is_err());
+    }
+
+    #[test]
+    fn test_closure_counter() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (make-counter) (define count 0) (lambda () (set count (+ count 1)) count))", &mut env).unwrap();
+        eval_str("(define c1 (make-counter))", &mut env).unwrap();
+        eval_str("(define c2 (make-counter))", &mut env).unwrap();
+        assert_eq!(Value::Int(1), eval_str("(c1)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("(c1)", &mut env).unwrap());
+        assert_eq!(Value::Int(1), eval_str("(c2)", &mut env).unwrap());
+        assert_eq!(Value::Int(3), eval_str("(c1)", &mut env).unwrap());
+        assert!(env.get("count").is_err());
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define add (lambda (x (y 10)) (+ x y)))", &mut env).unwrap();
+        assert_eq!(Value::Int(3), eval_str("(add 1 2)", &mut env).unwrap());
+        assert_eq!(Value::Int(11), eval_str("(add 1)", &mut env).unwrap());
+        assert!(matches!(eval_str("(add)", &mut env), Err(LustError::Eval(EvalError::Arity(_)))));
+        assert!(matches!(eval_str("(add 1 2 3)", &mut env), Err(LustError::Eval(EvalError::Arity(_)))));
+
+        // Defaults are evaluated for each call, after the parameters before
+        // them are bound.
+        eval_str("(define calls 0)", &mut env).unwrap();
+        eval_str("(define (range-of lo (hi (+ lo 5)) (n (set calls (+ calls 1)))) (- hi lo))", &mut env).unwrap();
+        assert_eq!(Value::Int(5), eval_str("(range-of 1)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("(range-of 1 3)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("(range-of 1 3 'given)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), env.get("calls").unwrap());
+        assert_eq!(Value::Int(7), eval_str("((lambda ((a 3) (b 4)) (+ a b)))", &mut env).unwrap());
+
+        assert_eq!("(lambda (x (y 10)) (+ x y))", read_str("(lambda (x (y 10)) (+ x y))").unwrap().to_string());
+        assert!(matches!(read_str("(lambda ((y 10) x) x)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(lambda ((y)) y)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(define ((f 1)) 2)"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_keyword_arguments() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (make-point :x :y (:z 0)) (cons x (cons y (cons z '()))))", &mut env).unwrap();
+        assert_eq!("(1 2 0)", eval_str("(make-point :x 1 :y 2)", &mut env).unwrap().to_string());
+        assert_eq!("(1 2 0)", eval_str("(make-point :y 2 :x 1)", &mut env).unwrap().to_string());
+        assert_eq!("(1 2 3)", eval_str("(make-point :z 3 :y 2 :x 1)", &mut env).unwrap().to_string());
+        assert!(matches!(eval_str("(make-point :x 1 :y 2 :w 3)", &mut env),
+                         Err(LustError::Eval(EvalError::Invalid(ref err))) if err == "unknown keyword :w"));
+        assert!(matches!(eval_str("(make-point :x 1)", &mut env),
+                         Err(LustError::Eval(EvalError::Arity(ref err))) if err == "missing keyword :y"));
+        assert!(matches!(eval_str("(make-point :x 1 :x 2 :y 3)", &mut env), Err(LustError::Eval(EvalError::Invalid(_)))));
+        assert!(matches!(eval_str("(make-point :x 1 :y)", &mut env), Err(LustError::Eval(EvalError::Arity(_)))));
+        assert!(matches!(eval_str("(make-point 1 :x 1 :y 2)", &mut env), Err(LustError::Eval(EvalError::Arity(_)))));
+
+        // Positional arguments come first, and keywords evaluate to
+        // themselves.
+        eval_str("(define (scale v (by 2) :offset) (+ offset (* v by)))", &mut env).unwrap();
+        assert_eq!(Value::Int(7), eval_str("(scale 3 :offset 1)", &mut env).unwrap());
+        assert_eq!(Value::Int(10), eval_str("(scale 3 3 :offset 1)", &mut env).unwrap());
+        assert_eq!(Value::Symbol(String::from(":offset")), eval_str(":offset", &mut env).unwrap());
+        assert_eq!(Value::Bool(true), eval_str("(eq? :a ':a)", &mut env).unwrap());
+
+        assert_eq!("(lambda (a :b (:c 1)) a)", read_str("(lambda (a :b (:c 1)) a)").unwrap().to_string());
+        assert!(matches!(read_str("(lambda (:a b) b)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(define (:f) 1)"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_partial() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(6), eval_str("((partial + 1 2) 3)", &mut env).unwrap());
+        assert_eq!(Value::Int(3), eval_str("((partial +) 1 2)", &mut env).unwrap());
+        eval_str("(define (between lo hi x) (and (<= lo x) (<= x hi)))", &mut env).unwrap();
+        eval_str("(define digit? (partial between 0 9))", &mut env).unwrap();
+        assert_eq!(Value::Bool(true), eval_str("(digit? 7)", &mut env).unwrap());
+        assert_eq!(Value::Bool(false), eval_str("(digit? 10)", &mut env).unwrap());
+        assert_eq!(Value::Bool(true), eval_str("((partial digit?) 0)", &mut env).unwrap());
+        assert_eq!(Value::Int(9), eval_str("((partial (partial - 10) 0) 1)", &mut env).unwrap());
+        assert!(matches!(eval_error("(digit? 1 2)", &mut env), EvalError::Arity(_)));
+        assert!(matches!(eval_error("(partial 1 2)", &mut env), EvalError::TypeMismatch(_)));
+        assert!(matches!(eval_error("(partial)", &mut env), EvalError::Arity(_)));
+    }
+
+    #[test]
+    fn test_memoize() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define calls 0)", &mut env).unwrap();
+        eval_str("(define fib (memoize (lambda (n)
+                    (set calls (+ calls 1))
+                    (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))", &mut env).unwrap();
+        assert_eq!(Value::Int(6765), eval_str("(fib 20)", &mut env).unwrap());
+        assert_eq!(Value::Int(21), env.get("calls").unwrap());
+        assert_eq!(Value::Int(6765), eval_str("(fib 20)", &mut env).unwrap());
+        assert_eq!(Value::Int(832040), eval_str("(fib 30)", &mut env).unwrap());
+        assert_eq!(Value::Int(31), env.get("calls").unwrap());
+        // Arguments that can't be keys are passed through every time.
+        eval_str("(define len (memoize (lambda (l) (set calls (+ calls 1)) (length l))))", &mut env).unwrap();
+        assert_eq!(Value::Int(2), eval_str("(len '(a b))", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("(len '(a b))", &mut env).unwrap());
+        assert_eq!(Value::Int(33), env.get("calls").unwrap());
+        assert!(matches!(eval_error("(memoize 1)", &mut env), EvalError::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_delay_force() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define count 0)", &mut env).unwrap();
+        eval_str("(define p (let ((x 5)) (delay (begin (set count (+ count 1)) (* x 2)))))", &mut env).unwrap();
+        assert_eq!(Value::Int(0), env.get("count").unwrap());
+        assert_eq!("#<promise>", env.get("p").unwrap().to_string());
+        assert_eq!(Value::Int(10), eval_str("(force p)", &mut env).unwrap());
+        assert_eq!(Value::Int(10), eval_str("(force p)", &mut env).unwrap());
+        assert_eq!(Value::Int(1), env.get("count").unwrap());
+        assert_eq!(Value::Int(7), eval_str("(force 7)", &mut env).unwrap());
+        assert_eq!(Value::Bool(true), eval_str("(equal? (force '(a b)) '(a b))", &mut env).unwrap());
+        assert!(matches!(eval_str("(delay)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_failed_force_can_be_retried() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define ready #f)", &mut env).unwrap();
+        eval_str("(define p (delay (if ready 'done (error \"not yet\"))))", &mut env).unwrap();
+        assert!(eval_str("(force p)", &mut env).is_err());
+        eval_str("(set ready #t)", &mut env).unwrap();
+        assert_eq!(Value::Symbol(String::from("done")), eval_str("(force p)", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_eval_program_parallel() {
+        let src: String = (0..50).map(|i| format!("(* {} (let ((n (+ {} 1))) (- n 1))) ", i, i)).collect();
+        let mut env = Environment::with_builtins();
+        let sequential: Vec<Value> = read_all(&src).unwrap().iter().map(|expr| eval(expr, &mut env).unwrap()).collect();
+        assert_eq!(sequential, eval_program_parallel(&src, &mut env).unwrap());
+        assert_eq!(Value::Int(49 * 49), sequential[49]);
+
+        let src = "(define x 2) (* x 3) (+ x 1) (set x 10) (* x x) (print x)";
+        env.set_output(Box::new(io::sink()));
+        let values = eval_program_parallel(src, &mut env).unwrap();
+        let expected = [Value::Unit, Value::Int(6), Value::Int(3), Value::Unit, Value::Int(100), Value::Int(10)];
+        assert_eq!(expected.to_vec(), values);
+        assert!(matches!(eval_program_parallel("(+ 1 2) (car 5) (+ 3 4) (define y 1)", &mut env),
+                         Err(LustError::Eval(EvalError::At(_, _)))));
+        assert!(env.get("y").is_err());
+    }
+
+    #[test]
+    fn test_is_pure() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (f) 1)", &mut env).unwrap();
+        let pure = |src| read_all(src).unwrap().remove(0).is_pure(&env);
+        assert!(pure("(+ 1 (if (< 1 2) (car '(1)) x))"));
+        assert!(pure("(let ((x 1)) (* x x))"));
+        assert!(pure("(lambda () (print 1))"));
+        assert!(!pure("(print 1)"));
+        assert!(!pure("(+ 1 (f))"));
+        assert!(!pure("(let ((+ print)) (+ 1))"));
+        assert!(!pure("(map car '((1)))"));
+        assert!(!pure("(begin (set x 1) x)"));
+        assert!(!pure("(define z 1)"));
+    }
+
+    #[test]
+    fn test_shared_environment_across_threads() {
+        let mut base = Environment::with_builtins();
+        eval_str("(define (square x) (* x x))", &mut base).unwrap();
+        eval_str("(define sq (memoize square))", &mut base).unwrap();
+        let handles: Vec<_> = (0..4).map(|i| {
+            let mut env = base.share();
+            thread::spawn(move || {
+                let result = eval_str(&format!("(let ((n {})) (sq (+ n 1)))", i), &mut env).unwrap();
+                env.define(&format!("result-{}", i), result.clone()).unwrap();
+                result
+            })
+        }).collect();
+        let results: Vec<Value> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(vec![Value::Int(1), Value::Int(4), Value::Int(9), Value::Int(16)], results);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(*result, base.get(&format!("result-{}", i)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_fork_does_not_change_parent() {
+        let mut base = Environment::with_builtins();
+        eval_str("(define count 0)", &mut base).unwrap();
+        eval_str("(define (bump) (set count (+ count 1)))", &mut base).unwrap();
+        eval_str("(define counter (let ((n 0)) (lambda () (set n (+ n 1)) n)))", &mut base).unwrap();
+        let mut fork = base.fork();
+        eval_str("(define extra 1)", &mut fork).unwrap();
+        eval_str("(set count 10)", &mut fork).unwrap();
+        eval_str("(bump)", &mut fork).unwrap();
+        eval_str("(counter)", &mut fork).unwrap();
+        eval_str("(define (bump) 'gone)", &mut fork).unwrap();
+        assert_eq!(Value::Int(11), fork.get("count").unwrap());
+        assert_eq!(Value::Int(2), eval_str("(counter)", &mut fork).unwrap());
+        assert!(base.get("extra").is_err());
+        assert_eq!(Value::Int(0), base.get("count").unwrap());
+        assert_eq!(Value::Int(1), eval_str("(counter)", &mut base).unwrap());
+        eval_str("(bump)", &mut base).unwrap();
+        assert_eq!(Value::Int(1), base.get("count").unwrap());
+        assert_eq!(Value::Int(11), fork.get("count").unwrap());
+    }
+
+    #[test]
+    fn test_bindings_shadow_outer_scopes() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define x 1)", &mut env).unwrap();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let capture = seen.clone();
+        env.register_native("capture", move |_args, env| {
+            let visible = env.bindings().into_iter()
+                .filter(|(name, _)| name == "x" || name == "y")
+                .collect::<Vec<_>>();
+            capture.lock().unwrap().push(visible);
+            Ok(Value::Unit)
+        });
+        eval_str("(let ((x 2) (y 3)) (let ((x 4)) (capture)) (capture))", &mut env).unwrap();
+        eval_str("(capture)", &mut env).unwrap();
+        let x = |n| (String::from("x"), Value::Int(n));
+        let y = (String::from("y"), Value::Int(3));
+        assert_eq!(vec![vec![x(4), y.clone()], vec![x(2), y], vec![x(1)]], *seen.lock().unwrap());
+        let names = env.names();
+        assert!(names.contains(&String::from("x")) && names.contains(&String::from("capture")));
+        assert!(!names.contains(&String::from("y")));
+        assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_fork_of_fork() {
+        let mut base = Environment::with_builtins();
+        eval_str("(define x 1)", &mut base).unwrap();
+        let mut outer = base.fork();
+        eval_str("(set x 2)", &mut outer).unwrap();
+        let mut inner = outer.fork();
+        assert_eq!(Value::Int(2), inner.get("x").unwrap());
+        eval_str("(set x 3)", &mut inner).unwrap();
+        assert_eq!(Value::Int(3), inner.get("x").unwrap());
+        assert_eq!(Value::Int(2), outer.get("x").unwrap());
+        assert_eq!(Value::Int(1), base.get("x").unwrap());
+        eval_str("(define wibble 1)", &mut inner).unwrap();
+        assert!(matches!(inner.get("wibbel"), Err(EvalError::UndefinedName(_, Some(ref name))) if name == "wibble"));
+    }
+
+    #[test]
+    fn test_snapshot_is_independent() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define x 1)", &mut env).unwrap();
+        let mut snapshot = env.snapshot();
+        eval_str("(set x 2)", &mut env).unwrap();
+        assert_eq!(Value::Int(1), snapshot.get("x").unwrap());
+        eval_str("(set x 3)", &mut snapshot).unwrap();
+        assert_eq!(Value::Int(2), env.get("x").unwrap());
+        assert_eq!(Value::Int(3), snapshot.get("x").unwrap());
+    }
+
+    #[test]
+    fn test_eval_all() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(6), eval_all("(define (double x) (+ x x))\n(double 3)", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_all(" ; nothing", &mut env).unwrap());
+        assert!(matches!(eval_all("(double 1) (double", &mut env), Err(LustError::Read(ReadError::UnexpectedEof))));
+    }
+
+    #[test]
+    fn test_tabs_and_form_feeds() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(3), eval_all("(+\t1\t2)", &mut env).unwrap());
+        assert_eq!(Value::Int(6), eval_all("(define\t(f x)\x0c\t(* x 2))\t(f\t3)", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_eval_all_hoisted() {
+        let even = "(define (my-even? n) (if (= n 0) #t (my-odd? (- n 1))))";
+        let odd = "(define (my-odd? n) (if (= n 0) #f (my-even? (- n 1))))";
+        for src in &[format!("{} {} (my-even? 10)", even, odd), format!("{} {} (my-even? 10)", odd, even)] {
+            let mut env = Environment::with_builtins();
+            assert_eq!(Value::Bool(true), eval_all_hoisted(src, &mut env).unwrap(), "{}", src);
+            assert_eq!(Value::Bool(false), eval_str("(my-odd? 10)", &mut env).unwrap());
+        }
+        let mut env = Environment::with_builtins();
+        match eval_all_hoisted(&format!("(define ten (my-even? 10)) {} {} ten", even, odd), &mut env) {
+            Err(LustError::Eval(EvalError::NotYetDefined(ref name))) if name == "my-even?" => (),
+            other => panic!("Expected my-even? to be not yet defined, got {:?}", other)
+        }
+        // Definitions run in order, so a later one doesn't change what an
+        // earlier call got.
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(1), eval_all_hoisted("(define (f) 1) (define r (f)) (define (f) 2) r", &mut env).unwrap());
+        let mut env = Environment::with_builtins();
+        match eval_all_hoisted("(define a (+ b 1)) (define b 1)", &mut env) {
+            Err(LustError::Eval(EvalError::NotYetDefined(ref name))) if name == "b" => (),
+            other => panic!("Expected b to be not yet defined, got {:?}", other)
+        }
+        assert!(matches!(env.get("a"), Err(EvalError::NotYetDefined(_))));
+        assert!(env.bindings().iter().all(|(name, _)| name != "a"));
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Int(2), eval_all_hoisted("(define x (car (list 2))) (define list 3) x", &mut env).unwrap());
+        assert!(matches!(eval_all("(define y (z)) (define (z) 1)", &mut env), Err(LustError::Eval(EvalError::UndefinedName(..)))));
+    }
+
+    #[test]
+    fn test_read_all() {
+        let exprs = read_all("(define x 1) x ; done").unwrap();
+        assert_eq!(vec![String::from("(define x 1)"), String::from("x")], exprs.iter().map(write_sexp).collect::<Vec<_>>());
+        assert!(read_all("").unwrap().is_empty());
+        assert!(matches!(read_all("x (y"), Err(ReadError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_eval_program_needs_no_stdio() {
+        assert_eq!("3", eval_program("(+ 1 2)"));
+        assert_eq!("\"hello\"\n5", eval_program("(print \"hello\") (define x 5) x"));
+        assert_eq!("", eval_program("(define x 5)"));
+        assert_eq!("1\nError: No such name in environment: y", eval_program("(print 1) (+ y 1) (print 2)"));
+        assert_eq!("#f", eval_program("(read-line)"));
+        assert!(eval_program("(time (+ 1 2))").starts_with("time: "));
+    }
+
+    #[test]
+    fn test_time() {
+        let mut env = Environment::with_builtins();
+        let log = Captured::default();
+        env.set_error_output(Box::new(log.clone()));
+        eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))", &mut env).unwrap();
+        let direct = eval_str("(sum 100)", &mut env).unwrap();
+        assert!(log.0.lock().unwrap().is_empty());
+        assert_eq!(direct, eval_str("(time (sum 100))", &mut env).unwrap());
+        let message = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(message.starts_with("time: ") && message.ends_with("\n"), "{}", message);
+        assert!(matches!(eval_str("(time)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        assert!(matches!(eval_str("(time 1 2)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_read_floats() {
+        assert_eq!(Value::Float(4.25), read_eval("(+ 1.5 2.75)"));
+        assert_eq!(Value::Int(3), read_eval("(floor (sqrt 10))"));
+        assert_eq!(Value::Float(-0.5), read_eval("(string->number \"-0.5\")"));
+        assert_eq!(Value::Float(1.5), read_eval("'1.5"));
+    }
+
+    #[test]
+    fn test_for_each() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define digits 0)", &mut env).unwrap();
+        let each = "(for-each (lambda (d) (set digits (+ (* digits 10) d))) '(1 2 3))";
+        assert_eq!(Value::Unit, eval_str(each, &mut env).unwrap());
+        assert_eq!(Value::Int(123), env.get("digits").unwrap());
+        assert_eq!(Value::Unit, eval_str("(for-each (lambda (d) (error \"called\")) '())", &mut env).unwrap());
+        assert!(matches!(eval_error("(for-each print 5)", &mut env), EvalError::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_assoc() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define config '((\"name\" . lust) (port . 8080) ((1 2) . pair) (port . 9090)))", &mut env).unwrap();
+        assert_eq!("(port . 8080)", eval_str("(assoc 'port config)", &mut env).unwrap().to_string());
+        assert_eq!(Value::Int(8080), eval_str("(cdr (assoc 'port config))", &mut env).unwrap());
+        assert_eq!("(\"name\" . lust)", eval_str("(assoc \"name\" config)", &mut env).unwrap().to_string());
+        assert_eq!("((1 2) . pair)", eval_str("(assoc '(1 2) config)", &mut env).unwrap().to_string());
+        assert_eq!(Value::Bool(false), eval_str("(assoc 'host config)", &mut env).unwrap());
+        assert_eq!(Value::Bool(false), eval_str("(assoc 'host '())", &mut env).unwrap());
+        assert!(matches!(eval_error("(assoc 'a '((a . 1) 2))", &mut env), EvalError::TypeMismatch(_)));
+        let map = eval_str("(alist->map '((b . 2) (a . 1) (b . 3)))", &mut env).unwrap();
+        assert_eq!("{a 1, b 2}", map.to_string());
+        // An entry that is a list has the rest of it as its value.
+        assert_eq!("{a (1)}", eval_str("(alist->map '((a 1)))", &mut env).unwrap().to_string());
+        assert!(matches!(eval_error("(alist->map config)", &mut env), EvalError::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_sort_is_stable() {
+        let mut env = Environment::with_builtins();
+        let sorted = "(sort '((b 2) (a 1) (c 2) (d 1) (e 2)) (lambda (x y) (< (nth 1 x) (nth 1 y))))";
+        assert_eq!("((a 1) (d 1) (b 2) (c 2) (e 2))", eval_str(sorted, &mut env).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_define_syntax() {
+        let mut env = Environment::with_builtins();
+        // (my-unless c body...) => (if c (begin) (begin body...))
+        eval_str("(define-syntax my-unless (lambda (form)
+                    (cons 'if (cons (car (cdr form)) (cons '(begin) (cons (cons 'begin (cdr (cdr form))) '()))))))",
+                 &mut env).unwrap();
+        eval_str("(define n 0)", &mut env).unwrap();
+        eval_str("(my-unless #f (set n (+ n 1)) (set n (* n 10)))", &mut env).unwrap();
+        eval_str("(my-unless #t (set n 99))", &mut env).unwrap();
+        assert_eq!(Value::Int(10), env.get("n").unwrap());
+
+        // The arguments are handed over unevaluated, and the expansion is
+        // evaluated where the macro was used.
+        eval_str("(define-syntax swap! (lambda (form)
+                    (let ((a (car (cdr form))) (b (car (cdr (cdr form)))))
+                      (cons 'let (cons (cons (cons 'tmp (cons a '())) '())
+                        (cons (cons 'set (cons a (cons b '())))
+                          (cons (cons 'set (cons b (cons 'tmp '()))) '())))))))",
+                 &mut env).unwrap();
+        eval_str("(define x 1)", &mut env).unwrap();
+        eval_str("(define y \"two\")", &mut env).unwrap();
+        eval_str("(swap! x y)", &mut env).unwrap();
+        assert_eq!(Value::Str(String::from("two")), env.get("x").unwrap());
+        assert_eq!(Value::Int(1), env.get("y").unwrap());
+        assert_eq!("#<macro>", env.get("swap!").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_macros_shadow_functions() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (twice x) (* 2 x))", &mut env).unwrap();
+        eval_str("(define-syntax twice (lambda (form) (cons 'quote (cons form '()))))", &mut env).unwrap();
+        assert_eq!("(twice (undefined 1))", eval_str("(twice (undefined 1))", &mut env).unwrap().to_string());
+        assert!(matches!(eval_str("(define-syntax m 5)", &mut env), Err(LustError::Eval(EvalError::TypeMismatch(_)))));
+        assert!(matches!(eval_str("(define-syntax (m) 5)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        eval_str("(define-syntax broken (lambda (form) (lambda (x) x)))", &mut env).unwrap();
+        assert!(matches!(eval_str("(broken)", &mut env), Err(LustError::Eval(EvalError::Expansion(_, _)))));
+    }
+
+    #[test]
+    fn test_define_constant() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define-constant limit 3)", &mut env).unwrap();
+        assert_eq!(Value::Int(6), eval_str("(* limit 2)", &mut env).unwrap());
+        assert!(matches!(eval_str("(set limit 4)", &mut env), Err(LustError::Eval(EvalError::Immutable(ref name))) if name == "limit"));
+        assert!(matches!(eval_str("((lambda () (set limit 4)))", &mut env), Err(LustError::Eval(EvalError::Immutable(_)))));
+        assert!(matches!(env.set("limit", Value::Int(4)), Err(EvalError::Immutable(_))));
+        assert_eq!(Value::Int(3), env.get("limit").unwrap());
+
+        // A binding in an inner scope is a new, ordinary variable.
+        assert_eq!(Value::Int(5), eval_str("(let ((limit 4)) (set limit 5) limit)", &mut env).unwrap());
+        assert_eq!(Value::Int(3), env.get("limit").unwrap());
+        assert!(matches!(eval_str("(define-constant (f) 1)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        assert!(matches!(eval_str("(define-constant x)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_unset() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define x 1)", &mut env).unwrap();
+        assert!(env.unset("x"));
+        assert!(matches!(env.get("x"), Err(EvalError::UndefinedName(..))));
+        assert!(!env.unset("x"));
+        assert!(!env.unset("nonexistent"));
+
+        // Only the innermost scope is touched.
+        eval_str("(define x 1)", &mut env).unwrap();
+        assert_eq!(Value::Int(1), eval_str("(let ((x 2)) (unset! x) x)", &mut env).unwrap());
+        assert_eq!(Value::Bool(false), eval_str("((lambda () (unset! x)))", &mut env).unwrap());
+        assert_eq!(Value::Int(1), env.get("x").unwrap());
+        let mut fork = env.fork();
+        assert!(!fork.unset("x"));
+        fork.define("y", Value::Int(2)).unwrap();
+        assert!(fork.unset("y"));
+        assert_eq!(Value::Int(1), fork.get("x").unwrap());
+        assert_eq!(Value::Bool(true), eval_str("(unset! x)", &mut env).unwrap());
+        assert!(env.get("x").is_err());
+        assert!(matches!(eval_str("(unset! 'x)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        assert!(matches!(eval_str("(unset! x y)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_rebinding_builtins() {
+        let mut env = Environment::with_builtins();
+        env.set_strict(true);
+        assert!(matches!(eval_str("(define + 5)", &mut env), Err(LustError::Eval(EvalError::Invalid(_)))));
+        assert!(matches!(eval_str("(set car cdr)", &mut env), Err(LustError::Eval(EvalError::Invalid(_)))));
+        assert!(matches!(eval_str("(define-constant pi 3)", &mut env), Err(LustError::Eval(EvalError::Invalid(_)))));
+        assert_eq!(Value::Int(3), eval_str("(+ 1 2)", &mut env).unwrap());
+        assert_eq!(Value::Int(5), eval_str("(let ((+ 5)) +)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("((lambda (car) (define cdr car) cdr) 2)", &mut env).unwrap());
+        assert!(env.take_warnings().is_empty());
+
+        let mut env = Environment::with_builtins();
+        eval_str("(define + 5)", &mut env).unwrap();
+        eval_str("(set car cdr)", &mut env).unwrap();
+        eval_str("(set + 6)", &mut env).unwrap();
+        assert_eq!(Value::Int(6), env.get("+").unwrap());
+        assert_eq!(vec!["rebinding builtin +", "rebinding builtin car"], env.take_warnings());
+        assert!(env.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_gensym() {
+        let mut env = Environment::with_builtins();
+        let first = eval_str("(gensym)", &mut env).unwrap();
+        let second = eval_str("(gensym)", &mut env).unwrap();
+        assert_eq!(Value::Symbol(String::from("g$1")), first);
+        assert_eq!(Value::Symbol(String::from("g$2")), second);
+        assert_eq!(Value::Symbol(String::from("g$3")), eval_str("(gensym)", &mut env.fork()).unwrap());
+
+        // A swap that still works when given a variable named like its
+        // temporary.
+        eval_str("(define-syntax swap! (lambda (form)
+                    (let ((a (car (cdr form))) (b (car (cdr (cdr form)))) (tmp (gensym)))
+                      (cons 'let (cons (cons (cons tmp (cons a '())) '())
+                        (cons (cons 'set (cons a (cons b '())))
+                          (cons (cons 'set (cons b (cons tmp '()))) '())))))))",
+                 &mut env).unwrap();
+        eval_str("(define tmp 1)", &mut env).unwrap();
+        eval_str("(define other 2)", &mut env).unwrap();
+        eval_str("(swap! tmp other)", &mut env).unwrap();
+        assert_eq!(Value::Int(2), env.get("tmp").unwrap());
+        assert_eq!(Value::Int(1), env.get("other").unwrap());
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define xs '(b c))", &mut env).unwrap();
+        let cases = [
+            ("`(1 ,(+ 1 1) 3)", "(1 2 3)"),
+            ("`(a (x ,(car xs) y) ,@xs d)", "(a (x b y) b c d)"),
+            ("`((,@xs) (nested ,@xs ,(length xs)) ,@'())", "((b c) (nested b c 2))"),
+            ("`(,@xs)", "(b c)"),
+            ("`(a 'b \"s\")", "(a b \"s\")"),
+            ("`,(+ 1 2)", "3"),
+            ("`sym", "sym"),
+            ("`()", "()"),
+            ("`(a . b)", "(a . b)"),
+            ("`(1 . ,(+ 1 1))", "(1 . 2)"),
+            ("`(,(car xs) a . ,xs)", "(b a b c)")
+        ];
+        for (src, expected) in cases.iter() {
+            assert_eq!(*expected, eval_str(src, &mut env).unwrap().to_string(), "{}", src);
+        }
+        // A template whose parts are all quoted is read as data.
+        assert_eq!("'(1 (2 3))", read_str("`(1 (2 3))").unwrap().to_string());
+        assert_eq!("(append '(1) (cons x '()) xs)", read_str("`(1 ,x ,@xs)").unwrap().to_string());
+
+        for src in [",x", "`,@xs", "'(a ,b)", "`(a `(b))", "'`a", "`(a ,)", "`(,@xs . 1)", "`(1 . ,@xs)"] {
+            assert!(matches!(read_str(src), Err(ReadError::Invalid(_)) | Err(ReadError::UnexpectedCloseParen(_))), "{}", src);
+        }
+        assert!(matches!(eval_str("`(a ,@1)", &mut env), Err(LustError::Eval(EvalError::TypeMismatch(_)))));
+    }
+
+    #[test]
+    fn test_template_macro() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define-syntax my-unless (lambda (form)
+                    `(if ,(car (cdr form)) (begin) (begin ,@(cdr (cdr form))))))", &mut env).unwrap();
+        assert_eq!(Value::Int(2), eval_str("(my-unless (= 1 2) 1 2)", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(my-unless (= 1 1) (undefined))", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_named_let() {
+        let mut env = Environment::with_builtins();
+        let sum = "(let loop ((i 0) (acc 0)) (if (= i 10) acc (loop (+ i 1) (+ acc i))))";
+        assert_eq!(Value::Int(45), eval_str(sum, &mut env).unwrap());
+        assert_eq!("((letrec ((loop (lambda (i acc) (if (= i 10) acc (loop (+ i 1) (+ acc i)))))) loop) 0 0)",
+                   read_str(sum).unwrap().to_string());
+        // Calls in tail position don't nest, so this is far deeper than the
+        // recursion limit.
+        let big = "(let loop ((i 0) (acc 0)) (if (= i 100000) acc (loop (+ i 1) (+ acc i))))";
+        assert_eq!(Value::Int(4999950000), eval_str(big, &mut env).unwrap());
+        // The name is only bound in the body.
+        eval_str("(define loop 5)", &mut env).unwrap();
+        assert_eq!(Value::Int(5), eval_str("(let loop ((n loop)) n)", &mut env).unwrap());
+        assert!(matches!(read_str("(let loop 5)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(let loop ((i 0)))"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(let* loop ((i 0)) i)"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_tail_calls_through_forms() {
+        let mut env = Environment::with_builtins();
+        let loops = [
+            "(define (g n) (cond ((= n 0) 'done) (else (g (- n 1)))))",
+            "(define (g n) (case n ((0) 'done) (else (g (- n 1)))))",
+            "(define (g n) (let ((m (- n 1))) (if (< m 0) 'done (g m))))",
+            "(define (g n) (let* ((m n)) (letrec () (if (= m 0) 'done (g (- m 1))))))",
+            "(define (g n) (if (= n 0) 'done (begin (when #t (g (- n 1))))))",
+            "(define (g n) (unless (= n 0) (g (- n 1))))",
+            "(define (g n) (or (and (= n 0) 'done) (g (- n 1))))",
+        ];
+        for def in loops.iter() {
+            eval_str(def, &mut env).unwrap();
+            let val = eval_str("(g 100000)", &mut env).unwrap_or_else(|err| panic!("{}: {}", def, err));
+            assert!(matches!(val, Value::Symbol(_) | Value::Unit), "{}", def);
+        }
+        // Only the last argument of and and or is in tail position.
+        assert_eq!(Value::Bool(false), eval_str("(and 1 #f (undefined))", &mut env).unwrap());
+        assert_eq!(Value::Int(1), eval_str("(or #f 1 (undefined))", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("((lambda () (cond (#f 1))))", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("((lambda () (cond (2))))", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_do() {
+        let mut env = Environment::with_builtins();
+        let sum = "(do ((i 0 (+ i 1)) (acc 0 (+ acc i))) ((= i 5) acc))";
+        assert_eq!(Value::Int(10), eval_str(sum, &mut env).unwrap());
+        // The steps all see the variables from before any of them.
+        assert_eq!(Value::Int(1), eval_str("(do ((a 0 b) (b 1 a) (n 0 (+ n 1))) ((= n 3) a))", &mut env).unwrap());
+        let big = "(do ((i 0 (+ i 1)) (acc 0 (+ acc i))) ((= i 100000) acc))";
+        assert_eq!(Value::Int(4999950000), eval_str(big, &mut env).unwrap());
+
+        // Without a result it's unit, and a variable without a step keeps
+        // its value.
+        eval_str("(define count 0)", &mut env).unwrap();
+        assert_eq!(Value::Unit, eval_str("(do ((i 0 (+ i 1)) (by 2)) ((= i 3)) (set count (+ count by)))", &mut env).unwrap());
+        assert_eq!(Value::Int(6), env.get("count").unwrap());
+        assert_eq!(Value::Str(String::from("done")), eval_str("(do () (#t \"done\"))", &mut env).unwrap());
+
+        assert!(matches!(read_str("(do)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(do ((i 0 1)))"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(do ((i)) (#t))"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(do ((i 0 1 2)) (#t))"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(do (i) (#t))"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(do ((i 0)) 5)"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (even? n) (if (= n 0) #t (odd? (- n 1))))", &mut env).unwrap();
+        eval_str("(define (odd? n) (if (= n 0) #f (begin (even? (- n 1)))))", &mut env).unwrap();
+        assert_eq!(Value::Bool(true), eval_str("(even? 10000)", &mut env).unwrap());
+        // Calls that aren't in tail position still count.
+        eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))", &mut env).unwrap();
+        assert!(matches!(eval_str("(sum 10000)", &mut env), Err(LustError::Eval(EvalError::RecursionLimit(_)))));
+        assert!(matches!(eval_error("(even? 'a)", &mut env), EvalError::TypeMismatch(_)));
+        assert_eq!(Value::Bool(false), eval_str("(odd? 2)", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_assert_forms() {
+        let mut env = Environment::with_builtins();
+        assert_eq!(Value::Unit, eval_str("(assert (= 1 1))", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(assert-equal (cons 1 '(\"a\")) '(1 \"a\"))", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(assert-equal (+ 1 1) 2)", &mut env).unwrap());
+        let failure = |src, env: &mut Environment| match eval_str(src, env) {
+            Err(LustError::Eval(EvalError::Custom(message))) => message,
+            other => panic!("Expected a failed assertion, got {:?}", other)
+        };
+        assert_eq!("Assertion failed: (= 1 2)", failure("(assert (= 1 2))", &mut env));
+        assert_eq!("Assertion failed: (equal? (+ 1 1) 3), got 2 and 3", failure("(assert-equal (+ 1 1) 3)", &mut env));
+        assert_eq!("Assertion failed: sums, got 2 and 3", failure("(assert-equal (+ 1 1) 3 \"sums\")", &mut env));
+        assert_eq!("(assert (= 1 2) \"(= 1 2)\")", read_str("(assert (= 1 2))").unwrap().to_string());
+        assert!(matches!(read_str("(assert)"), Err(ReadError::Invalid(_))));
+        assert!(matches!(read_str("(assert-equal 1 2 3 4)"), Err(ReadError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_errors_point_at_arguments() {
+        let mut env = Environment::with_builtins();
+        let at = |line, column| Position {line, column};
+        match eval_str("(+ 1 \"x\")", &mut env) {
+            Err(LustError::Eval(err @ EvalError::At(..))) => {
+                assert_eq!("Type mismatch: expected integer, got \"x\" at line 1, column 6", err.to_string());
+                assert!(matches!(err, EvalError::At(pos, ref inner) if pos == at(1, 6) && matches!(**inner, EvalError::TypeMismatch(_))));
+            },
+            other => panic!("Expected a located error, got {:?}", other)
+        }
+        let located = |src, env: &mut Environment| match eval_all(src, env) {
+            Err(LustError::Eval(EvalError::At(pos, _))) => pos,
+            other => panic!("Expected a located error, got {:?}", other)
+        };
+        assert_eq!(at(4, 4), located("(define x 1)\n(+ 1\n   (* x\n   'a))", &mut env));
+        assert_eq!(at(2, 7), located("(define s \"x\")\n (+ 1 s)", &mut env));
+        assert_eq!(at(1, 21), located("(define (f) 5) (car (f))", &mut env));
+        assert_eq!(at(1, 1), located("(sqrt 1 2)", &mut env));
+        assert_eq!(at(1, 17), located("(+ 1 (let () (- 'b)))", &mut env));
+        // Errors from closures are located in the closure's body.
+        eval_str("(define (half n) (modulo n 0))", &mut env).unwrap();
+        assert_eq!(at(1, 28), located("(half 4)", &mut env));
+        assert!(matches!(eval_str("(error \"raised\")", &mut env), Err(LustError::Eval(EvalError::Custom(_)))));
+    }
+
+    #[test]
+    fn test_read_while() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(begin (define i 0) (define sum 0) (while (< i 4) (set sum (+ sum i)) (set i (+ i 1))) sum)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Int(6), eval(&expr, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_define_is_unit() {
+        let mut env = Environment::with_builtins();
+        let mut m = input("(define x 1)");
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        assert_eq!(Value::Unit, eval(&expr, &mut env).unwrap());
+    }
+
+    fn read_eval(src: &'static str) -> Value {
+        let mut env = Environment::with_builtins();
+        let mut m = input(src);
+        let peekable = &mut iterator(&mut m).peekable();
+        let expr = read_expr(peekable).unwrap();
+        eval(&expr, &mut env).unwrap()
+    }
+
+    #[test]
+    fn test_read_cond() {
+        assert_eq!(Value::Int(2), read_eval("(cond ((= 1 2) 1) ((< 1 2) 2) (else 3))"));
+        assert_eq!(Value::Int(3), read_eval("(cond (#f 1) (0 2) (else 3))"));
+        assert_eq!(Value::Int(5), read_eval("(cond (#f 1) (5))"));
+        assert_eq!(Value::Unit, read_eval("(cond (#f 1))"));
+    }
+
+    #[test]
+    fn test_read_when_unless() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define n 0)", &mut env).unwrap();
+        assert_eq!(Value::Int(2), eval_str("(when (= n 0) (set n 1) (+ n 1))", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(when #f (set n 5))", &mut env).unwrap());
+        assert_eq!(Value::Int(1), env.get("n").unwrap());
+        assert_eq!(Value::Int(3), eval_str("(unless #f (set n 2) 3)", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(unless '() (set n 5))", &mut env).unwrap());
+        assert_eq!(Value::Int(2), env.get("n").unwrap());
+        assert_eq!(Value::Unit, eval_str("(when 1)", &mut env).unwrap());
+        assert!(matches!(eval_str("(unless)", &mut env), Err(LustError::Read(ReadError::Invalid(_)))));
+        let expr = read_one("(when (< n 3) (set n (+ n 1)) n)");
+        assert_eq!(expr, read_one(&expr.to_string()));
+    }
+
+    #[test]
+    fn test_read_case() {
+        let mut env = Environment::with_builtins();
+        eval_str("(define (size n) (case (+ n 0) ((1 2) 'low) ((3 4) 'high) (else 'other)))", &mut env).unwrap();
+        assert_eq!(Value::Symbol(String::from("low")), eval_str("(size 2)", &mut env).unwrap());
+        assert_eq!(Value::Symbol(String::from("high")), eval_str("(size 3)", &mut env).unwrap());
+        assert_eq!(Value::Symbol(String::from("other")), eval_str("(size 5)", &mut env).unwrap());
+        assert_eq!(Value::Int(2), eval_str("(case 'b ((a) 1) ((b c) 2))", &mut env).unwrap());
+        assert_eq!(Value::Int(3), eval_str("(case \"s\" ((#\\s) 1) ((\"s\") (+ 1 1) 3))", &mut env).unwrap());
+        assert_eq!(Value::Unit, eval_str("(case 9 ((1 2) 'low) (() 'never))", &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_read_malformed_case() {
+        let mut env = Environment::with_builtins();
+        for src in &["(case)", "(case 1 2)", "(case 1 ((1)))", "(case 1 (x 1))", "(case 1 ())"] {
+            assert!(matches!(eval_str(src, &mut env), Err(LustError::Read(ReadError::Invalid(_)))), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_read_equal() {
+        assert_eq!(Value::Bool(true), read_eval("(equal? '(1 (2 3)) '(1 (2 3)))"));
+        assert_eq!(Value::Bool(false), read_eval("(equal? '(1 (2 3)) '(1 (2 4)))"));
+        assert_eq!(Value::Bool(false), read_eval("(equal? '(1 (2 3)) '(1 (2 3) 4))"));
+        assert_eq!(Value::Bool(true), read_eval("(equal? \"ab\" (string-append \"a\" \"b\"))"));
+        assert_eq!(Value::Bool(false), read_eval("(equal? 1 '(1))"));
+        assert_eq!(Value::Bool(false), read_eval("(equal? '() #f)"));
+        assert_eq!(Value::Bool(true), read_eval("(equal? (map-set (make-map) 'k '(a)) (map-set (make-map) 'k '(a)))"));
+    }
+
+    #[test]
+    fn test_read_eq() {
+        assert_eq!(Value::Bool(false), read_eval("(eq? '(1 (2 3)) '(1 (2 3)))"));
+        assert_eq!(Value::Bool(true), read_eval("(let ((l '(1 2))) (eq? l l))"));
+        assert_eq!(Value::Bool(true), read_eval("(eq? 'a 'a)"));
+        assert_eq!(Value::Bool(true), read_eval("(eq? '() '())"));
+        assert_eq!(Value::Bool(false), read_eval("(eq? 1 #t)"));
+        assert_eq!(Value::Bool(true), read_eval("(eq? + +)"));
+    }
+
+    #[test]
+    fn test_read_and() {
+        assert_eq!(Value::Int(3), read_eval("(and 1 2 3)"));
+        assert_eq!(Value::Bool(false), read_eval("(and 1 #f (undefined))"));
+        assert_eq!(Value::Bool(true), read_eval("(and)"));
+    }
+
+    #[test]
+    fn test_read_or() {
+        assert_eq!(Value::Int(2), read_eval("(or #f 2 (undefined))"));
+        assert_eq!(Value::Int(0), read_eval("(or #f 0)"));
+        assert_eq!(Value::Bool(false), read_eval("(or)"));
+    }
+
+    #[test]
+    fn test_read_let() {
+        assert_eq!(Value::Int(3), read_eval("(let ((x 1) (y 2)) (+ x y))"));
+        assert_eq!(Value::Int(2), read_eval("(let () 1 2)"));
+        let mut env = Environment::with_builtins();
+        eval_str("(define x 10)", &mut env).unwrap();
+        assert_eq!(Value::Int(11), eval_str("(let ((x 1) (y x)) (+ x y))", &mut env).unwrap());
+        assert_eq!(Value::Int(10), env.get("x").unwrap());
+    }
+
+    #[test]
+    fn test_read_brackets() {
+        assert_eq!(Value::Int(3), read_eval("(let ([x 1] [y 2]) (+ x y))"));
+        assert_eq!(Value::Int(6), read_eval("[let* [(x 1) [y (+ x 1)]] (* x y 3)]"));
+        assert_eq!(read_eval("'(a (b) c)"), read_eval("'[a [b] c]"));
+        for src in &["(foo]", "[foo)", "(let ([x 1)) x)", "'(a]"] {
+            assert!(matches!(read_str(src), Err(ReadError::Invalid(_))), "{}", src);
+        }
+        let mut env = Environment::with_builtins();
+        assert!(matches!(eval_str("(+ 1 2)]", &mut env), Err(LustError::Read(ReadError::UnexpectedCloseParen(_)))));
+    }
+
+    #[test]
+    fn test_read_let_star() {
+        assert_eq!(Value::Int(2), read_eval("(let* ((x 1) (y (+ x 1))) y)"));
+        let mut env = Environment::with_builtins();
+        match eval_str("(let ((x 1) (y (+ x 1))) y)", &mut env) {
+            Err(LustError::Eval(EvalError::UndefinedName(ref name, _))) if name == "x" => (),
+            other => panic!("Expected x to be undefined, got {:?}", other)
+        }
+        assert!(env.get("y").is_err());
+    }
+
+    #[test]
+    fn test_read_letrec() {
+        let mut env = Environment::with_builtins();
+        let even_odd = |call: &str, env: &mut Environment| {
+            let src = format!("(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                                        (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                                 {})", call);
+            eval_str(&src, env).unwrap()
+        };
+        assert_eq!(Value::Bool(true), even_odd("(even? 10)", &mut env));
+        assert_eq!(Value::Bool(false), even_odd("(odd? 10)", &mut env));
+        assert_eq!(Value::Bool(true), even_odd("(odd? 7)", &mut env));
+        assert!(env.get("even?").is_err());
+        match eval_str("(let ((f (lambda (n) (if (= n 0) 0 (f (- n 1)))))) (f 3))", &mut env) {
+            Err(LustError::Eval(EvalError::UndefinedName(ref name, _))) if name == "f" => (),
+            other => panic!("Expected f to be undefined, got {:?}", other)
+        }
+        match eval_str("(letrec ((a b) (b 1)) a)", &mut env) {
+            Err(LustError::Eval(EvalError::NotYetDefined(ref name))) if name == "b" => (),
+            other => panic!("Expected b to be not yet defined, got {:?}", other)
+        }
+        eval_str("(define b 2)", &mut env).unwrap();
+        assert!(matches!(eval_str("(letrec ((a b) (b 1)) a)", &mut env), Err(LustError::Eval(EvalError::NotYetDefined(_)))));
+    }
+
+    #[test]
+    fn test_read_malformed_let() {
+        let mut env = Environment::with_builtins();
+        for src in &["(let)", "(let x 1)", "(let ((x 1)))", "(let ((x)) x)", "(let ((x 1 2)) x)", "(let* (x) x)", "(let ((1 2)) 3)"] {
+            assert!(matches!(eval_str(src, &mut env), Err(LustError::Read(ReadError::Invalid(_)))), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_read_not() {
+        assert_eq!(Value::Bool(false), read_eval("(not '())"));
+        assert_eq!(Value::Bool(true), read_eval("(not 0)"));
     }
 }
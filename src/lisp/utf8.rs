@@ -0,0 +1,118 @@
+use std::io::{BufRead, Bytes};
+use std::io::{Error, ErrorKind};
+use std::str::{from_utf8};
+
+/// Decodes UTF-8 from a byte stream one `char` at a time.
+pub struct Utf8Chars<R> {
+    bytes: Bytes<R>,
+    /// A byte that ended an invalid sequence early and starts the next one.
+    pending: Option<u8>
+}
+
+impl<R: BufRead> Utf8Chars<R> {
+    pub fn new(reader: R) -> Utf8Chars<R> {
+        Utf8Chars {bytes: reader.bytes(), pending: None}
+    }
+
+    fn next_byte(&mut self) -> Option<Result<u8, Error>> {
+        match self.pending.take() {
+            Some(b) => Some(Ok(b)),
+            None => self.bytes.next()
+        }
+    }
+
+    fn decode(&mut self, first: u8) -> Result<char, Error> {
+        let width = match first {
+            0x00..=0x7F => 1,
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return Err(invalid(&[first]))
+        };
+        let mut buf = [first, 0, 0, 0];
+        for i in 1..width {
+            match self.next_byte() {
+                Some(Ok(b)) if b & 0xC0 == 0x80 => buf[i] = b,
+                Some(Ok(b)) => {
+                    self.pending = Some(b);
+                    return Err(invalid(&buf[..i]));
+                },
+                Some(Err(e)) => return Err(e),
+                None => return Err(Error::new(ErrorKind::UnexpectedEof, "Incomplete UTF-8 sequence at end of input"))
+            }
+        }
+        match from_utf8(&buf[..width]) {
+            Ok(s) => Ok(s.chars().next().expect("Non-empty UTF-8 yielded empty string")),
+            Err(_) => Err(invalid(&buf[..width]))
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Utf8Chars<R> {
+    type Item = Result<char, Error>;
+
+    fn next(&mut self) -> Option<Result<char, Error>> {
+        match self.next_byte()? {
+            Ok(b) => Some(self.decode(b)),
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+fn invalid(bytes: &[u8]) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid UTF-8 sequence {:?}", bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Error, ErrorKind};
+    use super::Utf8Chars;
+
+    fn scan(bytes: &[u8]) -> Vec<Result<char, Error>> {
+        Utf8Chars::new(bytes).collect()
+    }
+
+    fn chars(s: &str) -> Vec<char> {
+        scan(s.as_bytes()).into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(vec!['(', '+', ' ', '1', ')'], chars("(+ 1)"));
+    }
+
+    #[test]
+    fn test_multi_byte() {
+        assert_eq!(vec!['a', 'λ', 'b'], chars("aλb"));
+        assert_eq!(vec!['€'], chars("€"));
+        assert_eq!(vec!['🦀', ' ', '😀'], chars("🦀 😀"));
+    }
+
+    #[test]
+    fn test_invalid_byte() {
+        let res = scan(&[b'a', 0xFF, b'b']);
+        assert_eq!(3, res.len());
+        assert_eq!('a', *res[0].as_ref().unwrap());
+        assert_eq!(ErrorKind::InvalidData, res[1].as_ref().unwrap_err().kind());
+        assert_eq!('b', *res[2].as_ref().unwrap());
+    }
+
+    #[test]
+    fn test_truncated_sequence() {
+        let res = scan(&[0xCE, b'b']);
+        assert_eq!(2, res.len());
+        assert_eq!(ErrorKind::InvalidData, res[0].as_ref().unwrap_err().kind());
+        assert_eq!('b', *res[1].as_ref().unwrap());
+
+        let res = scan(&[b'a', 0xF0, 0x9F]);
+        assert_eq!(2, res.len());
+        assert_eq!(ErrorKind::UnexpectedEof, res[1].as_ref().unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_surrogate_is_invalid() {
+        let res = scan(&[0xED, 0xA0, 0x80]);
+        assert_eq!(1, res.len());
+        assert_eq!(ErrorKind::InvalidData, res[0].as_ref().unwrap_err().kind());
+    }
+}
@@ -0,0 +1,394 @@
+#[cfg(feature = "bignum")]
+use std::convert::TryFrom;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+use lisp::builtins::FuncId;
+use lisp::expr::{Closure, EvalError, Native, Promise};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The result of forms that are only evaluated for their side effects.
+    Unit,
+    Int(i64),
+    /// An integer too big for `Int`; never one that would fit.
+    #[cfg(feature = "bignum")]
+    BigInt(BigInt),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Symbol(String),
+    /// The empty list.
+    Nil,
+    /// A cons cell; lists are chains of pairs ending in `Nil`.
+    Pair(Arc<(Value, Value)>),
+    /// A map from keys to values. Maps are never changed in place;
+    /// `map-set` makes a new one.
+    Map(Arc<BTreeMap<Key, Value>>),
+    /// Values by index from 0. Like maps, vectors are never changed in
+    /// place; `vector-set!` makes a new one.
+    Vector(Arc<Vec<Value>>),
+    Builtin(FuncId),
+    Closure(Arc<Closure>),
+    Native(Arc<Native>),
+    Promise(Arc<Promise>),
+    /// A function from forms to forms bound with `define-syntax`.
+    Macro(Arc<Value>)
+}
+
+/// The values that can be keys in a `Value::Map`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Key {
+    Int(i64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Symbol(String)
+}
+
+impl Key {
+    pub fn from_value(val: &Value) -> Result<Key, EvalError> {
+        match *val {
+            Value::Int(i) => Ok(Key::Int(i)),
+            Value::Bool(b) => Ok(Key::Bool(b)),
+            Value::Char(c) => Ok(Key::Char(c)),
+            Value::Str(ref s) => Ok(Key::Str(s.clone())),
+            Value::Symbol(ref s) => Ok(Key::Symbol(s.clone())),
+            ref other => Err(EvalError::TypeMismatch(format!("expected a map key, got {}", other)))
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Key::Int(i) => Value::Int(i),
+            Key::Bool(b) => Value::Bool(b),
+            Key::Char(c) => Value::Char(c),
+            Key::Str(ref s) => Value::Str(s.clone()),
+            Key::Symbol(ref s) => Value::Symbol(s.clone())
+        }
+    }
+}
+
+/// The name a keyword such as `:x` stands for, if `symbol` is one.
+pub fn keyword(symbol: &str) -> Option<&str> {
+    symbol.strip_prefix(':').filter(|name| !name.is_empty())
+}
+
+impl Value {
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        Value::Pair(Arc::new((car, cdr)))
+    }
+
+    /// Builds a proper list of the given values.
+    pub fn list(vals: Vec<Value>) -> Value {
+        Value::dotted(vals, Value::Nil)
+    }
+
+    /// Builds a list of the given values ending in `tail` rather than the
+    /// empty list, as `(1 2 . 3)` does.
+    pub fn dotted(vals: Vec<Value>, tail: Value) -> Value {
+        vals.into_iter().rev().fold(tail, |tail, val| Value::cons(val, tail))
+    }
+
+    /// The name of a keyword, without the colon.
+    pub fn as_keyword(&self) -> Option<&str> {
+        match *self {
+            Value::Symbol(ref symbol) => keyword(symbol),
+            _ => None
+        }
+    }
+
+    /// Whether the value counts as true in conditionals such as `if`,
+    /// `cond`, `and`, `or` and `not`. Only `#f` and, for compatibility with
+    /// the integer-only days, `0` are false; everything else, including the
+    /// empty list and unit, is true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(*self, Value::Bool(false) | Value::Int(0))
+    }
+
+    pub fn is_number(&self) -> bool {
+        match *self {
+            Value::Int(_) => true,
+            #[cfg(feature = "bignum")]
+            Value::BigInt(_) => true,
+            Value::Float(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(*self, Value::Float(_))
+    }
+
+    /// The number as a float, rounded if it's an integer too big for one
+    /// to hold exactly.
+    pub fn as_float(&self) -> Result<f64, EvalError> {
+        match *self {
+            Value::Int(i) => Ok(i as f64),
+            #[cfg(feature = "bignum")]
+            Value::BigInt(ref n) => Ok(n.to_string().parse().expect("Big integers are valid floats")),
+            Value::Float(x) => Ok(x),
+            ref other => Err(EvalError::TypeMismatch(format!("expected number, got {}", other)))
+        }
+    }
+
+    /// Makes an `Int` of `n` if it fits.
+    #[cfg(feature = "bignum")]
+    pub fn from_big(n: BigInt) -> Value {
+        match i64::try_from(&n) {
+            Ok(i) => Value::Int(i),
+            Err(_) => Value::BigInt(n)
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    pub fn as_big(&self) -> Result<BigInt, EvalError> {
+        match *self {
+            Value::Int(i) => Ok(BigInt::from(i)),
+            Value::BigInt(ref n) => Ok(n.clone()),
+            ref other => Err(EvalError::TypeMismatch(format!("expected integer, got {}", other)))
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i64, EvalError> {
+        match *self {
+            Value::Int(i) => Ok(i),
+            ref other => Err(EvalError::TypeMismatch(format!("expected integer, got {}", other)))
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, EvalError> {
+        match *self {
+            Value::Str(ref s) => Ok(s),
+            ref other => Err(EvalError::TypeMismatch(format!("expected string, got {}", other)))
+        }
+    }
+
+    /// The elements of a proper list.
+    pub fn to_vec(&self) -> Result<Vec<Value>, EvalError> {
+        let mut items = vec![];
+        let mut val = self;
+        loop {
+            match *val {
+                Value::Nil => return Ok(items),
+                Value::Pair(ref pair) => { items.push(pair.0.clone()); val = &pair.1; },
+                _ => return Err(EvalError::TypeMismatch(format!("expected list, got {}", self)))
+            }
+        }
+    }
+
+    /// Whether the two are the same object, as `eq?` has it: pairs, maps,
+    /// vectors and functions must be the very same one, other values merely
+    /// equal.
+    pub fn is_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Pair(a), Value::Pair(b)) => Arc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Arc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Arc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Arc::ptr_eq(a, b),
+            (Value::Promise(a), Value::Promise(b)) => Arc::ptr_eq(a, b),
+            (Value::Macro(a), Value::Macro(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a == b
+        }
+    }
+
+    /// Structural equality, as `equal?` has it. Unlike `==` this walks
+    /// along lists rather than recursing, so long ones can't overflow the
+    /// stack.
+    pub fn equal(&self, other: &Value) -> bool {
+        let (mut a, mut b) = (self, other);
+        loop {
+            match (a, b) {
+                (Value::Pair(x), Value::Pair(y)) => {
+                    if Arc::ptr_eq(x, y) {
+                        return true;
+                    }
+                    if !x.0.equal(&y.0) {
+                        return false;
+                    }
+                    a = &x.1;
+                    b = &y.1;
+                },
+                (Value::Map(x), Value::Map(y)) =>
+                    return x.len() == y.len() && x.iter().zip(y.iter()).all(|(p, q)| p.0 == q.0 && p.1.equal(q.1)),
+                (Value::Vector(x), Value::Vector(y)) =>
+                    return x.len() == y.len() && x.iter().zip(y.iter()).all(|(p, q)| p.equal(q)),
+                (a, b) => return a.is_eq(b)
+            }
+        }
+    }
+
+    /// Whether this is `Nil` or a chain of pairs ending in `Nil`.
+    pub fn is_list(&self) -> bool {
+        let mut val = self;
+        loop {
+            match *val {
+                Value::Nil => return true,
+                Value::Pair(ref pair) => val = &pair.1,
+                _ => return false
+            }
+        }
+    }
+}
+
+/// Writes `s` as a string literal the reader understands.
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Writes values as the reader reads them, the way `write` shows them.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.shown(true).fmt(f)
+    }
+}
+
+/// A value shown readably, or else the way `display` shows it, for people
+/// rather than the reader, with strings and characters as just their text.
+pub struct Shown<'a> {
+    val: &'a Value,
+    readable: bool
+}
+
+impl Value {
+    pub fn display(&self) -> Shown<'_> {
+        self.shown(false)
+    }
+
+    fn shown(&self, readable: bool) -> Shown<'_> {
+        Shown {val: self, readable}
+    }
+}
+
+impl<'a> fmt::Display for Shown<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let readable = self.readable;
+        match *self.val {
+            Value::Unit => write!(f, "#<unit>"),
+            Value::Int(i) => write!(f, "{}", i),
+            #[cfg(feature = "bignum")]
+            Value::BigInt(ref n) => write!(f, "{}", n),
+            // Debug always shows a decimal point or exponent, so floats
+            // don't read back as integers.
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Char(c) if !readable => write!(f, "{}", c),
+            Value::Char(' ') => write!(f, "#\\space"),
+            Value::Char('\n') => write!(f, "#\\newline"),
+            Value::Char('\t') => write!(f, "#\\tab"),
+            Value::Char('\r') => write!(f, "#\\return"),
+            Value::Char(c) => write!(f, "#\\{}", c),
+            Value::Str(ref s) if !readable => write!(f, "{}", s),
+            Value::Str(ref s) => write_string(f, s),
+            Value::Symbol(ref name) => write!(f, "{}", name),
+            Value::Nil => write!(f, "()"),
+            Value::Pair(ref pair) => {
+                write!(f, "({}", pair.0.shown(readable))?;
+                let mut rest = &pair.1;
+                loop {
+                    match *rest {
+                        Value::Nil => break,
+                        Value::Pair(ref pair) => { write!(f, " {}", pair.0.shown(readable))?; rest = &pair.1; },
+                        ref tail => { write!(f, " . {}", tail.shown(readable))?; break; }
+                    }
+                }
+                write!(f, ")")
+            },
+            Value::Map(ref map) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in map.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{} {}", sep, key.to_value().shown(readable), val.shown(readable))?;
+                }
+                write!(f, "}}")
+            },
+            Value::Vector(ref items) => {
+                write!(f, "#(")?;
+                for (i, item) in items.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(f, "{}{}", sep, item.shown(readable))?;
+                }
+                write!(f, ")")
+            },
+            Value::Builtin(func) => write!(f, "#<builtin {}>", func.name()),
+            Value::Closure(ref closure) => write!(f, "#<lambda ({})>", closure.params().join(" ")),
+            Value::Native(ref native) => write!(f, "#<native {}>", native.name()),
+            Value::Promise(_) => write!(f, "#<promise>"),
+            Value::Macro(_) => write!(f, "#<macro>")
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use lisp::builtins::FuncId;
+    use lisp::expr::{Closure, Lambda, Environment};
+    use super::Value;
+
+    #[test]
+    fn test_false_values() {
+        assert!(!Value::Bool(false).is_truthy());
+        assert!(!Value::Int(0).is_truthy());
+    }
+
+    #[test]
+    fn test_true_values() {
+        assert!(Value::Bool(true).is_truthy());
+        assert!(Value::Int(1).is_truthy());
+        assert!(Value::Int(-1).is_truthy());
+        assert!(Value::Unit.is_truthy());
+        assert!(Value::Char('\0').is_truthy());
+        assert!(Value::Str(String::new()).is_truthy());
+        assert!(Value::Symbol(String::from("f")).is_truthy());
+        assert!(Value::Nil.is_truthy());
+        assert!(Value::list(vec![Value::Bool(false)]).is_truthy());
+        assert!(Value::Builtin(FuncId::Add).is_truthy());
+        let lambda = Arc::new(Lambda {params: vec![], defaults: vec![], keys: vec![], body: vec![]});
+        let closure = Closure::new(lambda, &Environment::with_builtins());
+        assert!(Value::Closure(Arc::new(closure)).is_truthy());
+    }
+
+    #[test]
+    fn test_display_pairs() {
+        let int = Value::Int;
+        assert_eq!("(1 2)", Value::cons(int(1), Value::cons(int(2), Value::Nil)).to_string());
+        assert_eq!("(1 . 2)", Value::cons(int(1), int(2)).to_string());
+        assert_eq!("(1 2 . 3)", Value::cons(int(1), Value::cons(int(2), int(3))).to_string());
+        let mixed = Value::list(vec![Value::cons(int(1), int(2)), Value::Nil, Value::list(vec![int(3)])]);
+        assert_eq!("((1 . 2) () (3))", mixed.to_string());
+        assert_eq!("((1) 2 . 3)", Value::cons(Value::list(vec![int(1)]), Value::cons(int(2), int(3))).to_string());
+    }
+
+    #[test]
+    fn test_equal_long_lists() {
+        let a = Value::list((0..200000).map(Value::Int).collect());
+        let b = Value::list((0..200000).map(Value::Int).collect());
+        assert!(a.equal(&b));
+        assert!(!a.is_eq(&b));
+        let c = Value::list((0..200000).map(|i| Value::Int(if i == 199999 { 0 } else { i })).collect());
+        assert!(!a.equal(&c));
+        for list in [a, b, c] {
+            let mut rest = list;
+            while let Value::Pair(pair) = rest {
+                rest = Arc::try_unwrap(pair).map(|pair| pair.1).unwrap_or(Value::Nil);
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_repl(input: &str) -> String {
+    run_repl_bytes(input.as_bytes())
+}
+
+fn run_repl_bytes(input: &[u8]) -> String {
+    run_repl_with_stderr(input).0
+}
+
+fn run_repl_with_stderr(input: &[u8]) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lust"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start lust");
+    child.stdin.take().expect("No stdin").write_all(input).unwrap();
+    let output = child.wait_with_output().expect("lust didn't finish");
+    (String::from_utf8(output.stdout).expect("Output wasn't UTF-8"),
+     String::from_utf8(output.stderr).expect("Output wasn't UTF-8"))
+}
+
+#[test]
+fn test_parse_produces_no_extra_output() {
+    assert_eq!("3\n", run_repl("(+ 1 2)\n"));
+}
+
+#[test]
+fn test_unit_results_are_not_printed() {
+    assert_eq!("1\n", run_repl("(define x 1)\n(set x (+ x 0))\nx\n"));
+}
+
+#[test]
+fn test_invalid_utf8_is_reported() {
+    assert_eq!("Error: IO error: Invalid UTF-8 sequence [255]\n3\n", run_repl_bytes(b"\xFF(+ 1 2)\n"));
+}
+
+#[test]
+fn test_stray_close_paren() {
+    assert_eq!("Error: Unexpected ')' at line 1, column 1\n3\n4\n", run_repl(")\n(+ 1 2)\n(+ 2 2)\n"));
+}
+
+#[test]
+fn test_extra_close_paren() {
+    assert_eq!("3\nError: Unexpected ')' at line 1, column 8\n4\n", run_repl("(+ 1 2))\n(+ 2 2)\n"));
+}
+
+#[test]
+fn test_truncated_input_is_reported() {
+    assert_eq!("3\nError: Unexpected end of file in the middle of an expression\n", run_repl("(+ 1 2)\n(+ 1"));
+}
+
+#[test]
+fn test_clean_end_of_input() {
+    assert_eq!("3\n", run_repl("(+ 1 2)\n; done\n"));
+}
+
+#[test]
+fn test_multi_line_expression() {
+    assert_eq!(("... 3\n".to_string(), String::new()), run_repl_with_stderr(b"(+\n1 2)\n"));
+}
+
+#[test]
+fn test_recovers_after_invalid_line() {
+    assert_eq!("Error: Invalid input: Invalid input '#z'\n3\n", run_repl("(foo #z bar) baz)\n(+ 1 2)\n"));
+    assert_eq!("... Error: Invalid input: Invalid input '#z'\n3\n", run_repl("(foo #z\nbar)\n(+ 1 2)\n"));
+}
+
+#[test]
+fn test_read_line_reads_the_next_line() {
+    assert_eq!("\"hello there\"\n\"last\"\n#f\n",
+               run_repl("(read-line)\nhello there\n(define x (read-line))\nlast\nx\n(read-line)\n"));
+}
+
+#[test]
+fn test_commands() {
+    let help = run_repl(":help\n");
+    assert!(help.starts_with(":help "), "{}", help);
+    assert!(help.contains(":env ") && help.contains(":quit "), "{}", help);
+    assert!(help.contains("at most 400 deep"), "{}", help);
+    assert_eq!("nil = ()\npi = 3.141592653589793\nx = 42\n", run_repl("(define x 42)\n:env\n"));
+    assert_eq!("3\n", run_repl("(+ 1 2)\n  :quit\n(+ 3 4)\n"));
+    assert_eq!("Unknown command :what, see :help\n7\n", run_repl(":what \n(+ 3 4)\n"));
+}
+
+#[test]
+fn test_rebinding_a_builtin_warns() {
+    assert_eq!("Warning: rebinding builtin +\n5\n", run_repl("(define + 5)\n+\n"));
+    assert_eq!("1\n", run_repl("(let ((list 1)) list)\n"));
+}